@@ -9,6 +9,8 @@
 
 typedef struct LlgConstraint LlgConstraint;
 
+typedef struct LlgMatcher LlgMatcher;
+
 typedef struct LlgStopController LlgStopController;
 
 typedef struct LlgTokenizer LlgTokenizer;
@@ -239,6 +241,17 @@ struct LlgConstraint *llg_new_constraint_regex(const struct LlgConstraintInit *i
 struct LlgConstraint *llg_new_constraint_json(const struct LlgConstraintInit *init,
                                               const char *json_schema);
 
+/**
+ * Create a new constraint from a given JSON schema, with additional string "format"s.
+ * custom_formats is a JSON object mapping format names to regexes, for example
+ * {"sku": "[A-Z]{3}-[0-9]{4}"}; these take precedence over built-in formats.
+ * It can be NULL, in which case this is the same as llg_new_constraint_json().
+ * Always returns a non-null value. Call llg_get_error() on the result to check for errors.
+ */
+struct LlgConstraint *llg_new_constraint_json_with_formats(const struct LlgConstraintInit *init,
+                                                           const char *json_schema,
+                                                           const char *custom_formats);
+
 /**
  * Create a new constraint from a given lark grammar
  * Always returns a non-null value. Call llg_get_error() on the result to check for errors.
@@ -248,7 +261,7 @@ struct LlgConstraint *llg_new_constraint_lark(const struct LlgConstraintInit *in
 
 /**
  * Create a new constraint with specified type
- * Type can be one of "regex", "json_schema" (or "json"), "lark", "llguidance" (or "guidance")
+ * Type can be one of "regex", "json_schema" (or "json"), "lark", "gbnf", "ebnf", "llguidance" (or "guidance")
  * Always returns a non-null value. Call llg_get_error() on the result to check for errors.
  */
 struct LlgConstraint *llg_new_constraint_any(const struct LlgConstraintInit *init,
@@ -409,6 +422,47 @@ const char *llg_stop_commit_token(struct LlgStopController *stop_ctrl,
  */
 void llg_free_stop_controller(struct LlgStopController *stop_ctrl);
 
+/**
+ * Create a new byte-level matcher, which checks text against a grammar without a tokenizer.
+ * Type can be one of "regex", "json_schema" (or "json"), "lark", "gbnf", "ebnf", "llguidance" (or "guidance")
+ * Returns NULL on error, in which case the error message is written to error_string.
+ * # Safety
+ * This function should only be called from C code.
+ */
+struct LlgMatcher *llg_new_matcher(const char *constraint_type,
+                                   const char *data,
+                                   char *error_string,
+                                   size_t error_string_len);
+
+/**
+ * Feed bytes (not necessarily valid UTF-8) to the matcher.
+ * Returns 1 if the bytes so far match the grammar, 0 if they are a valid prefix of a match,
+ * and -1 if they were rejected (now or before).
+ * # Safety
+ * This function should only be called from C code.
+ */
+int32_t llg_matcher_push(struct LlgMatcher *matcher, const uint8_t *bytes, size_t bytes_len);
+
+/**
+ * Signal end of input to the matcher.
+ * Returns 1 if the input matches the grammar, and -1 otherwise.
+ */
+int32_t llg_matcher_finish(struct LlgMatcher *matcher);
+
+/**
+ * Get the JSON status from the last call to llg_matcher_push() or llg_matcher_finish(), for example:
+ * {"status":"reject","offset":3,"expected":["'+'"],"message":"unexpected \"x\""}
+ * The string is valid until the next call to one of these functions, or until the matcher is freed.
+ */
+const char *llg_matcher_status(const struct LlgMatcher *matcher);
+
+/**
+ * Free the matcher
+ * # Safety
+ * This function should only be called from C code.
+ */
+void llg_free_matcher(struct LlgMatcher *matcher);
+
 #ifdef __cplusplus
 }  // extern "C"
 #endif  // __cplusplus
@@ -7,7 +7,7 @@ use serde_json::Value;
 
 use crate::{
     earley::{lexerspec::LexerSpec, Grammar},
-    lark::lark_regex_quote,
+    lark::{lark_regex_quote, regex_with_captures_to_lark},
 };
 
 /// This represents a collection of grammars, with a designated
@@ -44,6 +44,18 @@ pub struct LLGuidanceOptions {
     /// Any Unicode regex will cause an error.
     #[serde(default)]
     pub allow_invalid_utf8: bool,
+
+    /// If set, captures of `%json` nodes (or of references to JSON schema grammars)
+    /// are reported as `ParserOutput::JsonCapture` with the parsed value,
+    /// instead of `ParserOutput::Capture` with raw bytes.
+    #[serde(default)]
+    pub json_captures: bool,
+
+    /// If set, every rule of a Lark grammar is reported as `ParserOutput::RuleCompleted`
+    /// with its byte span, once it's matched.
+    /// This prevents some grammar optimizations, so it may slow down the parser.
+    #[serde(default)]
+    pub rule_events: bool,
 }
 
 impl LLGuidanceOptions {
@@ -54,6 +66,12 @@ impl LLGuidanceOptions {
         if other.allow_invalid_utf8 {
             self.allow_invalid_utf8 = true;
         }
+        if other.json_captures {
+            self.json_captures = true;
+        }
+        if other.rule_events {
+            self.rule_events = true;
+        }
     }
 }
 
@@ -69,6 +87,15 @@ pub struct GrammarWithLexer {
     /// The Lark grammar that the grammar should generate.
     /// When this is set, nodes and rx_nodes must be empty.
     pub lark_grammar: Option<String>,
+
+    /// The grammar in GBNF format (as used by llama.cpp), with `root` as the start rule.
+    /// When this is set, nodes and rx_nodes must be empty.
+    pub gbnf_grammar: Option<String>,
+
+    /// The grammar in the EBNF notation of W3C specifications, with the first production
+    /// as the start symbol.
+    /// When this is set, nodes and rx_nodes must be empty.
+    pub ebnf_grammar: Option<String>,
     // #[serde(flatten)]
     // pub options: LLGuidanceOptions,
 }
@@ -80,6 +107,10 @@ impl Debug for GrammarWithLexer {
             "GrammarWithLexer [{}]",
             if self.lark_grammar.is_some() {
                 "lark"
+            } else if self.gbnf_grammar.is_some() {
+                "gbnf"
+            } else if self.ebnf_grammar.is_some() {
+                "ebnf"
             } else {
                 "json"
             }
@@ -110,6 +141,8 @@ pub struct NodeProps {
     pub max_tokens: Option<usize>,
     pub name: Option<String>,
     pub capture_name: Option<String>,
+    /// Name reported in `ParserOutput::RuleCompleted` when the node is matched.
+    pub rule_name: Option<String>,
 }
 
 #[derive(Clone)]
@@ -169,6 +202,93 @@ pub struct RegexExt {
     pub substring_words: Option<String>,
     /// Similar to `substring_chunks: s.split('')`
     pub substring_chars: Option<String>,
+    /// The lexeme should accept JSON strings (including quotes) whose contents match this regex.
+    pub json_string: Option<String>,
+
+    /// Either "integer" or "number"; the lexeme should accept numbers in JSON syntax,
+    /// constrained by the fields below (with JSON schema semantics).
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+    #[serde(rename = "exclusiveMinimum")]
+    pub exclusive_minimum: Option<f64>,
+    #[serde(rename = "exclusiveMaximum")]
+    pub exclusive_maximum: Option<f64>,
+    #[serde(rename = "multipleOf")]
+    pub multiple_of: Option<f64>,
+}
+
+/// A non-fatal problem found while compiling a grammar,
+/// e.g., a constraint that could only be approximated or was ignored.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrammarWarning {
+    pub message: String,
+    /// For JSON schemas, the JSON pointer (within the root schema)
+    /// of the sub-schema the warning is about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_pointer: Option<String>,
+    /// For JSON schemas, the keyword the warning is about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyword: Option<String>,
+}
+
+impl GrammarWarning {
+    pub fn new(message: String) -> Self {
+        GrammarWarning {
+            message,
+            json_pointer: None,
+            keyword: None,
+        }
+    }
+}
+
+impl Display for GrammarWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    /// The rule can't be reached from `start`.
+    Unreachable,
+    /// The rule can't produce any finite string.
+    Unproductive,
+    /// The rule can derive itself without consuming any input.
+    NullableCycle,
+    /// The rule is right-recursive, and will hit `max_items_in_row` on long inputs.
+    RightRecursion,
+    /// A single-character lexeme is repeated in a rule (as in `/[a-z]/+`).
+    SingleCharRepetition,
+}
+
+/// A likely problem in a grammar, found by [`GrammarInit::lint()`].
+/// Unlike [`GrammarWarning`], the grammar is compiled as written.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GrammarLint {
+    pub kind: LintKind,
+    /// Name of the rule the lint is about; for rules generated
+    /// by the compiler, the name of the rule that contains them.
+    pub rule: String,
+    pub message: String,
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_value(self).unwrap().as_str().unwrap()
+        )
+    }
+}
+
+impl Display for GrammarLint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.kind, self.rule, self.message)
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -270,6 +390,8 @@ impl TopLevelGrammar {
         }
         if first_non_whitespace == Some('{') {
             Ok(serde_json::from_str(s)?)
+        } else if crate::ebnf::is_gbnf(s) {
+            Ok(TopLevelGrammar::from_gbnf(s.to_string()))
         } else {
             Ok(TopLevelGrammar::from_lark(s.to_string()))
         }
@@ -279,6 +401,12 @@ impl TopLevelGrammar {
         Self::from_grammar(GrammarWithLexer::from_regex(rx))
     }
 
+    pub fn from_regex_with_captures(rx: &str) -> Result<Self> {
+        Ok(Self::from_grammar(
+            GrammarWithLexer::from_regex_with_captures(rx)?,
+        ))
+    }
+
     pub fn from_lark(lark_grammar: String) -> Self {
         Self::from_grammar(GrammarWithLexer::from_lark(lark_grammar))
     }
@@ -287,6 +415,14 @@ impl TopLevelGrammar {
         Self::from_grammar(GrammarWithLexer::from_json_schema(json_schema))
     }
 
+    pub fn from_gbnf(gbnf_grammar: String) -> Self {
+        Self::from_grammar(GrammarWithLexer::from_gbnf(gbnf_grammar))
+    }
+
+    pub fn from_ebnf(ebnf_grammar: String) -> Self {
+        Self::from_grammar(GrammarWithLexer::from_ebnf(ebnf_grammar))
+    }
+
     pub fn from_grammar(grammar: GrammarWithLexer) -> Self {
         TopLevelGrammar {
             grammars: vec![grammar],
@@ -312,10 +448,36 @@ impl GrammarWithLexer {
         }
     }
 
+    pub fn from_gbnf(gbnf_grammar: String) -> Self {
+        GrammarWithLexer {
+            name: Some("gbnf_grammar".to_string()),
+            gbnf_grammar: Some(gbnf_grammar),
+            ..GrammarWithLexer::default()
+        }
+    }
+
+    pub fn from_ebnf(ebnf_grammar: String) -> Self {
+        GrammarWithLexer {
+            name: Some("ebnf_grammar".to_string()),
+            ebnf_grammar: Some(ebnf_grammar),
+            ..GrammarWithLexer::default()
+        }
+    }
+
     pub fn from_regex(rx: &str) -> Self {
         let rx = lark_regex_quote(rx);
         let mut r = Self::from_lark(format!("start: /{}/", rx));
         r.name = Some("regex".to_string());
         r
     }
+
+    /// Like `from_regex()`, but named groups like `(?P<year>\d{4})` are turned
+    /// into captures, available via `TokenParser::get_capture()`.
+    /// Only groups not nested inside repetitions or alternatives are lifted,
+    /// and each part of the regex between them is matched greedily as a separate lexeme.
+    pub fn from_regex_with_captures(rx: &str) -> Result<Self> {
+        let mut r = Self::from_lark(regex_with_captures_to_lark(rx)?);
+        r.name = Some("regex".to_string());
+        Ok(r)
+    }
 }
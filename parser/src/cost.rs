@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{earley::XorShift, Constraint, Instant, TokenParser};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostEstimateOptions {
+    /// Number of random outputs to generate.
+    pub num_samples: usize,
+    /// Maximum number of tokens in each output.
+    pub max_tokens: usize,
+    pub seed: u32,
+}
+
+impl Default for CostEstimateOptions {
+    fn default() -> Self {
+        CostEstimateOptions {
+            num_samples: 10,
+            max_tokens: 200,
+            seed: 1,
+        }
+    }
+}
+
+/// Summary of a set of measurements.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub min: u64,
+    pub median: u64,
+    pub p90: u64,
+    pub max: u64,
+    pub mean: f64,
+}
+
+impl Distribution {
+    pub fn from_samples(mut samples: Vec<u64>) -> Self {
+        if samples.is_empty() {
+            return Distribution::default();
+        }
+        samples.sort_unstable();
+        let n = samples.len();
+        Distribution {
+            count: n,
+            min: samples[0],
+            median: samples[n / 2],
+            p90: samples[(n * 9 / 10).min(n - 1)],
+            max: samples[n - 1],
+            mean: samples.iter().sum::<u64>() as f64 / n as f64,
+        }
+    }
+}
+
+/// How expensive a grammar is to serve, measured on random outputs.
+/// Compare with `ParserLimits` to see how close the grammar gets to them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CostEstimate {
+    /// Time to compile the grammar, if known.
+    pub compile_time_us: u64,
+    pub num_samples: usize,
+    /// Total number of tokens over all samples.
+    pub num_tokens: usize,
+    /// Errors that ended samples early, e.g., when hitting parser limits.
+    pub errors: Vec<String>,
+    /// Lexer (DFA) states at the end of each sample (`max_lexer_states`).
+    pub lexer_states: Distribution,
+    /// Earley items in the current row, for each mask (`max_items_in_row`).
+    pub items_per_row: Distribution,
+    /// Earley items created while computing each mask (`step_max_items`).
+    pub items_per_mask: Distribution,
+    /// Lexer fuel spent on each mask (`step_lexer_fuel`).
+    pub lexer_fuel_per_mask: Distribution,
+    /// Time to compute each mask.
+    pub mask_time_us: Distribution,
+}
+
+/// Generate random outputs (like `sample_parser --rnd`) from copies of `parser`,
+/// which should be fresh, and measure the cost of each step.
+pub fn estimate_cost(parser: &TokenParser, options: &CostEstimateOptions) -> CostEstimate {
+    let trie = parser.token_env.tok_trie();
+    let mut rng = XorShift::new(options.seed);
+    let mut res = CostEstimate {
+        num_samples: options.num_samples,
+        ..Default::default()
+    };
+    let mut lexer_states = vec![];
+    let mut items_per_row = vec![];
+    let mut items_per_mask = vec![];
+    let mut lexer_fuel = vec![];
+    let mut mask_times = vec![];
+
+    for _ in 0..options.num_samples {
+        let mut constraint = Constraint::new(parser.deep_clone());
+        let mut num_tokens = 0;
+        let mut error = None;
+        while num_tokens < options.max_tokens {
+            let t0 = Instant::now();
+            let r = match constraint.compute_mask() {
+                Ok(r) => r,
+                Err(e) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+            };
+            mask_times.push(t0.elapsed().as_micros() as u64);
+            if r.is_stop() {
+                break;
+            }
+            let tok = r.sample_mask.as_ref().map(|mask| {
+                let mut v = mask.clone();
+                // mostly disallow EOS to make outputs longer
+                if !rng.one_in(5) {
+                    v.disallow_token(trie.eos_token());
+                    if v.is_zero() {
+                        v.allow_token(trie.eos_token());
+                    }
+                }
+                rng.sample_from_vob(&v)
+            });
+            let stats = constraint.parser.last_step_stats();
+            items_per_mask.push(stats.all_items as u64);
+            lexer_fuel.push(stats.lexer_cost);
+            items_per_row.push(constraint.parser.parser.items_in_row() as u64);
+            match constraint.commit_token(tok) {
+                Ok(c) => {
+                    num_tokens += c.ff_tokens.len().max(1);
+                    if c.stop {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        if let Some(e) = constraint.parser.error_message().or(error) {
+            res.errors.push(e);
+        }
+        res.num_tokens += num_tokens;
+        lexer_states.push(constraint.parser.parser.lexer_stats().num_states as u64);
+    }
+
+    res.lexer_states = Distribution::from_samples(lexer_states);
+    res.items_per_row = Distribution::from_samples(items_per_row);
+    res.items_per_mask = Distribution::from_samples(items_per_mask);
+    res.lexer_fuel_per_mask = Distribution::from_samples(lexer_fuel);
+    res.mask_time_us = Distribution::from_samples(mask_times);
+    res
+}
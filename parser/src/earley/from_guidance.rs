@@ -4,24 +4,41 @@ use std::{sync::Arc, vec};
 use super::grammar::SymIdx;
 use super::lexerspec::LexerSpec;
 use super::{CGrammar, Grammar};
-use crate::api::{GrammarId, GrammarInit, GrammarWithLexer, ParserLimits, TopLevelGrammar};
+use crate::api::{
+    GrammarId, GrammarInit, GrammarLint, GrammarWithLexer, ParserLimits, TopLevelGrammar,
+};
 use crate::earley::lexerspec::LexemeClass;
+use crate::ebnf::{ebnf_to_llguidance, gbnf_to_llguidance};
 use crate::lark::lark_to_llguidance;
 use crate::Instant;
 use crate::{loginfo, JsonCompileOptions, Logger};
-use crate::{GrammarBuilder, HashMap};
+use crate::{GrammarBuilder, HashMap, ImportResolver, InMemoryImportResolver};
 use anyhow::{bail, ensure, Result};
 use toktrie::TokEnv;
 
 fn process_grammar(ctx: &mut CompileCtx, input: GrammarWithLexer) -> Result<(SymIdx, LexemeClass)> {
     let builder = std::mem::take(&mut ctx.builder).unwrap();
 
+    let num_set = [
+        input.lark_grammar.is_some(),
+        input.json_schema.is_some(),
+        input.gbnf_grammar.is_some(),
+        input.ebnf_grammar.is_some(),
+    ]
+    .iter()
+    .filter(|x| **x)
+    .count();
+    ensure!(
+        num_set <= 1,
+        "only one of lark_grammar, json_schema, gbnf_grammar and ebnf_grammar can be set"
+    );
+
     let res = if let Some(lark) = input.lark_grammar {
-        ensure!(
-            input.json_schema.is_none(),
-            "cannot have both lark_grammar and json_schema"
-        );
         lark_to_llguidance(builder, &lark)?
+    } else if let Some(gbnf) = input.gbnf_grammar {
+        gbnf_to_llguidance(builder, &gbnf)?
+    } else if let Some(ebnf) = input.ebnf_grammar {
+        ebnf_to_llguidance(builder, &ebnf)?
     } else if let Some(mut json_schema) = input.json_schema {
         let mut opts = JsonCompileOptions::default();
         if let Some(x_guidance) = json_schema.get("x-guidance") {
@@ -31,7 +48,7 @@ fn process_grammar(ctx: &mut CompileCtx, input: GrammarWithLexer) -> Result<(Sym
         }
         opts.json_to_llg(builder, json_schema)?
     } else {
-        bail!("grammar must have either lark_grammar or json_schema");
+        bail!("grammar must have lark_grammar, json_schema, gbnf_grammar or ebnf_grammar");
     };
 
     res.builder.check_limits()?;
@@ -49,8 +66,12 @@ fn process_all_grammars(
     input: TopLevelGrammar,
 ) -> Result<(Grammar, LexerSpec)> {
     for (idx, grm) in input.grammars.iter().enumerate() {
-        if grm.lark_grammar.is_none() && grm.json_schema.is_none() {
-            bail!("grammar must have either lark_grammar or json_schema");
+        if grm.lark_grammar.is_none()
+            && grm.json_schema.is_none()
+            && grm.gbnf_grammar.is_none()
+            && grm.ebnf_grammar.is_none()
+        {
+            bail!("grammar must have lark_grammar, json_schema, gbnf_grammar or ebnf_grammar");
         }
         if let Some(n) = &grm.name {
             let n = GrammarId::Name(n.to_string());
@@ -77,6 +98,9 @@ fn process_all_grammars(
     let mut lexer_spec = builder.regex.spec;
 
     grammar.resolve_grammar_refs(&mut lexer_spec, &grammar_by_idx)?;
+    if lexer_spec.json_captures {
+        grammar.resolve_json_captures();
+    }
 
     Ok((grammar, lexer_spec))
 }
@@ -92,6 +116,25 @@ impl GrammarInit {
         self,
         tok_env: Option<TokEnv>,
         limits: ParserLimits,
+    ) -> Result<(Grammar, LexerSpec)> {
+        self.to_internal_with_resolver(tok_env, limits, Arc::new(InMemoryImportResolver::new()))
+    }
+
+    pub fn to_internal_with_resolver(
+        self,
+        tok_env: Option<TokEnv>,
+        limits: ParserLimits,
+        import_resolver: Arc<dyn ImportResolver>,
+    ) -> Result<(Grammar, LexerSpec)> {
+        self.build_internal(tok_env, limits, import_resolver, false)
+    }
+
+    fn build_internal(
+        self,
+        tok_env: Option<TokEnv>,
+        limits: ParserLimits,
+        import_resolver: Arc<dyn ImportResolver>,
+        record_regex_sources: bool,
     ) -> Result<(Grammar, LexerSpec)> {
         match self {
             GrammarInit::Internal(g, l) => Ok((g, l)),
@@ -99,7 +142,11 @@ impl GrammarInit {
             GrammarInit::Serialized(input) => {
                 ensure!(!input.grammars.is_empty(), "empty grammars array");
 
-                let builder = GrammarBuilder::new(tok_env, limits.clone());
+                let mut builder = GrammarBuilder::new(tok_env, limits.clone());
+                builder.set_import_resolver(import_resolver);
+                if record_regex_sources {
+                    builder.record_regex_sources();
+                }
 
                 let ctx = CompileCtx {
                     builder: Some(builder),
@@ -118,12 +165,60 @@ impl GrammarInit {
         logger: &mut Logger,
         limits: ParserLimits,
         extra_lexemes: Vec<String>,
+    ) -> Result<Arc<CGrammar>> {
+        self.to_cgrammar_with_resolver(
+            tok_env,
+            logger,
+            limits,
+            extra_lexemes,
+            Arc::new(InMemoryImportResolver::new()),
+        )
+    }
+
+    pub fn to_cgrammar_with_resolver(
+        self,
+        tok_env: Option<TokEnv>,
+        logger: &mut Logger,
+        limits: ParserLimits,
+        extra_lexemes: Vec<String>,
+        import_resolver: Arc<dyn ImportResolver>,
     ) -> Result<Arc<CGrammar>> {
         let t0 = Instant::now();
-        let (grammar, mut lexer_spec) = self.to_internal(tok_env, limits)?;
+        let (grammar, mut lexer_spec) =
+            self.to_internal_with_resolver(tok_env, limits, import_resolver)?;
         lexer_spec.add_extra_lexemes(&extra_lexemes);
         compile_grammar(t0, grammar, lexer_spec, logger)
     }
+
+    /// Compile the grammar and render it back in Lark syntax, see [`CGrammar::to_lark`].
+    pub fn to_lark(
+        self,
+        tok_env: Option<TokEnv>,
+        logger: &mut Logger,
+        limits: ParserLimits,
+    ) -> Result<String> {
+        let t0 = Instant::now();
+        let (grammar, lexer_spec) = self.build_internal(
+            tok_env,
+            limits,
+            Arc::new(InMemoryImportResolver::new()),
+            true,
+        )?;
+        compile_grammar(t0, grammar, lexer_spec, logger)?.to_lark()
+    }
+
+    /// Check the grammar for likely mistakes, see `LintKind`.
+    /// Returns an error if the grammar doesn't compile.
+    pub fn lint(
+        self,
+        tok_env: Option<TokEnv>,
+        limits: ParserLimits,
+        import_resolver: Arc<dyn ImportResolver>,
+    ) -> Result<Vec<GrammarLint>> {
+        let (grammar, lexer_spec) =
+            self.to_internal_with_resolver(tok_env, limits, import_resolver)?;
+        Ok(grammar.lint(&lexer_spec))
+    }
 }
 
 fn compile_grammar(
@@ -132,6 +227,10 @@ fn compile_grammar(
     lexer_spec: LexerSpec,
     logger: &mut Logger,
 ) -> Result<Arc<CGrammar>> {
+    for warning in grammar.warnings() {
+        logger.warn(&warning.to_string());
+    }
+
     let log_grammar = logger.level_enabled(3) || (logger.level_enabled(2) && grammar.is_small());
     if log_grammar {
         writeln!(
@@ -1,7 +1,8 @@
 use super::lexerspec::{LexemeClass, LexemeIdx, LexerSpec};
-use crate::api::{GenGrammarOptions, GrammarId, NodeProps};
-use crate::HashMap;
+use crate::api::{GenGrammarOptions, GrammarId, GrammarLint, GrammarWarning, LintKind, NodeProps};
+use crate::{HashMap, HashSet};
 use anyhow::{bail, ensure, Result};
+use serde_json::Value;
 use std::fmt::Display;
 use std::{fmt::Debug, hash::Hash};
 
@@ -36,6 +37,7 @@ pub struct SymbolProps {
     pub max_tokens: usize,
     pub capture_name: Option<String>,
     pub stop_capture_name: Option<String>,
+    pub rule_name: Option<String>,
     pub temperature: f32,
     pub grammar_id: LexemeClass,
     pub is_start: bool,
@@ -47,6 +49,7 @@ impl Default for SymbolProps {
             max_tokens: usize::MAX,
             capture_name: None,
             stop_capture_name: None,
+            rule_name: None,
             temperature: 0.0,
             is_start: false,
             grammar_id: LexemeClass::ROOT,
@@ -60,6 +63,7 @@ impl SymbolProps {
         self.max_tokens < usize::MAX
             || self.capture_name.is_some()
             || self.stop_capture_name.is_some()
+            || self.rule_name.is_some()
             || self.is_start
     }
 
@@ -69,6 +73,7 @@ impl SymbolProps {
             max_tokens: self.max_tokens,
             capture_name: None,
             stop_capture_name: None,
+            rule_name: None,
             temperature: self.temperature,
             grammar_id: self.grammar_id,
             is_start: false,
@@ -88,6 +93,9 @@ impl Display for SymbolProps {
                 self.stop_capture_name.as_ref().unwrap()
             )?;
         }
+        if let Some(name) = &self.rule_name {
+            write!(f, " RULE={}", name)?;
+        }
         if self.max_tokens < 10000 {
             write!(f, " max_tokens={}", self.max_tokens)?;
         }
@@ -126,6 +134,14 @@ pub struct Grammar {
     symbols: Vec<Symbol>,
     symbol_count_cache: HashMap<String, usize>,
     symbol_by_name: HashMap<String, SymIdx>,
+    warnings: Vec<GrammarWarning>,
+    // start symbols of grammars compiled from JSON schemas
+    json_schemas: HashMap<SymIdx, Value>,
+    // capture name -> JSON schema of the captured value
+    json_captures: HashMap<String, Value>,
+    // symbol -> name of the rule in the source grammar (only used in lints)
+    source_names: HashMap<SymIdx, String>,
+    lints: Vec<GrammarLint>,
 }
 
 impl Grammar {
@@ -135,7 +151,52 @@ impl Grammar {
             symbols: vec![],
             symbol_by_name: HashMap::default(),
             symbol_count_cache: HashMap::default(),
+            warnings: vec![],
+            json_schemas: HashMap::default(),
+            json_captures: HashMap::default(),
+            source_names: HashMap::default(),
+            lints: vec![],
+        }
+    }
+
+    pub fn add_warning(&mut self, warning: GrammarWarning) {
+        self.warnings.push(warning);
+    }
+
+    /// Warnings generated while building the grammar, e.g., about constraints
+    /// that could only be approximated.
+    pub fn warnings(&self) -> &[GrammarWarning] {
+        &self.warnings
+    }
+
+    /// Record that `start` is the start symbol of a grammar generated from `schema`.
+    pub fn add_json_schema(&mut self, start: SymIdx, schema: Value) {
+        self.json_schemas.insert(start, schema);
+    }
+
+    /// Find captures whose body is a JSON schema grammar (possibly through
+    /// a chain of single-symbol rules, as generated for `x[capture]: %json {...}`).
+    /// Has to be called after grammar references are resolved.
+    pub fn resolve_json_captures(&mut self) {
+        let mut found = vec![];
+        for sym in &self.symbols {
+            let Some(name) = &sym.props.capture_name else {
+                continue;
+            };
+            let mut curr = sym;
+            // bound the walk, in case of cycles
+            for _ in 0..self.symbols.len() {
+                if let Some(schema) = self.json_schemas.get(&curr.idx) {
+                    found.push((name.clone(), schema.clone()));
+                    break;
+                }
+                match curr.rules.as_slice() {
+                    [r] if r.rhs.len() == 1 => curr = self.sym_data(r.rhs[0]),
+                    _ => break,
+                }
+            }
         }
+        self.json_captures.extend(found);
     }
 
     pub fn start(&self) -> SymIdx {
@@ -229,6 +290,9 @@ impl Grammar {
         if let Some(capture_name) = props.capture_name {
             sym.props.capture_name = Some(capture_name);
         }
+        if let Some(rule_name) = props.rule_name {
+            sym.props.rule_name = Some(rule_name);
+        }
     }
 
     pub fn sym_props_mut(&mut self, sym: SymIdx) -> &mut SymbolProps {
@@ -243,6 +307,253 @@ impl Grammar {
         &self.symbols[sym.0 as usize].name
     }
 
+    /// Record that `sym` implements rule `name` of the source grammar (e.g., Lark);
+    /// this is only used to name things in lints. The first name recorded wins.
+    pub fn set_source_name(&mut self, sym: SymIdx, name: &str) {
+        self.source_names
+            .entry(sym)
+            .or_insert_with(|| name.to_string());
+    }
+
+    /// Add a lint found by the front-end, to be returned from lint().
+    pub fn add_lint(&mut self, lint: GrammarLint) {
+        self.lints.push(lint);
+    }
+
+    /// For every symbol, the source name of the closest enclosing named symbol.
+    fn source_owners(&self) -> Vec<Option<&str>> {
+        let mut owners = vec![None; self.symbols.len()];
+        for sym in &self.symbols {
+            if let Some(name) = self.source_names.get(&sym.idx) {
+                owners[sym.idx.as_usize()] = Some(name.as_str());
+            }
+        }
+        for sym in &self.symbols {
+            let Some(name) = self.source_names.get(&sym.idx) else {
+                continue;
+            };
+            let mut stack = vec![sym.idx];
+            while let Some(s) = stack.pop() {
+                for r in &self.sym_data(s).rules {
+                    for e in &r.rhs {
+                        if owners[e.as_usize()].is_none() {
+                            owners[e.as_usize()] = Some(name.as_str());
+                            stack.push(*e);
+                        }
+                    }
+                }
+            }
+        }
+        owners
+    }
+
+    /// Static analysis of the grammar; it has to run before optimize(),
+    /// which inlines and renames symbols.
+    /// Reports rules unreachable from start, unproductive rules, nullable cycles,
+    /// right recursion, and repetition of single-character lexemes.
+    pub fn lint(&self, lexer_spec: &LexerSpec) -> Vec<GrammarLint> {
+        let num_symbols = self.symbols.len();
+        let owners = self.source_owners();
+        let sym_name = |s: SymIdx| owners[s.as_usize()].unwrap_or(self.sym_name(s));
+
+        let mut lints = vec![];
+        let mut seen = HashSet::default();
+        let mut add_lint = |kind: LintKind, rule: &str, message: String| {
+            let lint = GrammarLint {
+                kind,
+                rule: rule.to_string(),
+                message,
+            };
+            if seen.insert(lint.clone()) {
+                lints.push(lint);
+            }
+        };
+        for lint in &self.lints {
+            add_lint(lint.kind, &lint.rule, lint.message.clone());
+        }
+
+        let mut reachable = vec![false; num_symbols];
+        reachable[self.start().as_usize()] = true;
+        let mut stack = vec![self.start()];
+        while let Some(s) = stack.pop() {
+            for r in &self.sym_data(s).rules {
+                for e in &r.rhs {
+                    if !reachable[e.as_usize()] {
+                        reachable[e.as_usize()] = true;
+                        stack.push(*e);
+                    }
+                }
+            }
+        }
+        let reachable_syms = self
+            .symbols
+            .iter()
+            .filter(|s| reachable[s.idx.as_usize()])
+            .collect::<Vec<_>>();
+
+        for sym in &self.symbols {
+            if !reachable[sym.idx.as_usize()] {
+                if let Some(name) = self.source_names.get(&sym.idx) {
+                    add_lint(
+                        LintKind::Unreachable,
+                        name,
+                        "rule is not reachable from start".to_string(),
+                    );
+                }
+            }
+        }
+
+        let fixpoint = |init: &dyn Fn(&Symbol) -> bool| {
+            let mut res = self.symbols.iter().map(init).collect::<Vec<_>>();
+            loop {
+                let mut changed = false;
+                for sym in &self.symbols {
+                    if !res[sym.idx.as_usize()]
+                        && sym
+                            .rules
+                            .iter()
+                            .any(|r| r.rhs.iter().all(|e| res[e.as_usize()]))
+                    {
+                        res[sym.idx.as_usize()] = true;
+                        changed = true;
+                    }
+                }
+                if !changed {
+                    return res;
+                }
+            }
+        };
+        // unresolved grammar references are assumed to be fine
+        let productive =
+            fixpoint(&|s| s.is_terminal() || (s.gen_grammar.is_some() && s.rules.is_empty()));
+        let nullable = fixpoint(&|_| false);
+
+        for sym in &reachable_syms {
+            if !productive[sym.idx.as_usize()] {
+                add_lint(
+                    LintKind::Unproductive,
+                    sym_name(sym.idx),
+                    "rule can't produce any finite string; \
+                    all its alternatives are infinitely recursive"
+                        .to_string(),
+                );
+            }
+        }
+
+        // A -> B when A ::= x B y; for nullable cycles x and y are nullable,
+        // for right recursion only y is
+        let mut unit_edges = vec![vec![]; num_symbols];
+        let mut right_edges = vec![vec![]; num_symbols];
+        let mut consuming_edges = vec![];
+        for sym in &reachable_syms {
+            for r in &sym.rules {
+                let mut suffix_nullable = true;
+                for (pos, e) in r.rhs.iter().enumerate().rev() {
+                    if !suffix_nullable {
+                        break;
+                    }
+                    let a = sym.idx.as_usize();
+                    let b = e.as_usize();
+                    right_edges[a].push(b);
+                    if r.rhs[..pos].iter().all(|e| nullable[e.as_usize()]) {
+                        unit_edges[a].push(b);
+                    } else {
+                        consuming_edges.push((a, b));
+                    }
+                    suffix_nullable = nullable[b];
+                }
+            }
+        }
+
+        // generated symbols are only listed when no source rule is involved
+        let cycle_names = |comp: &[usize]| {
+            let mut names: Vec<&str> = vec![];
+            for &s in comp {
+                if let Some(name) = owners[s] {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+            if names.is_empty() {
+                names = comp
+                    .iter()
+                    .map(|&s| self.sym_name(SymIdx(s as u32)))
+                    .collect();
+            }
+            names
+        };
+
+        for comp in cyclic_components(&unit_edges) {
+            let names = cycle_names(&comp);
+            add_lint(
+                LintKind::NullableCycle,
+                names[0],
+                format!(
+                    "rule can derive itself without consuming any input (through {})",
+                    names.join(", ")
+                ),
+            );
+        }
+
+        let mut comp_of = vec![usize::MAX; num_symbols];
+        let right_comps = cyclic_components(&right_edges);
+        for (idx, comp) in right_comps.iter().enumerate() {
+            for &s in comp {
+                comp_of[s] = idx;
+            }
+        }
+        let mut right_recursive = vec![false; right_comps.len()];
+        for (a, b) in consuming_edges {
+            if comp_of[a] != usize::MAX && comp_of[a] == comp_of[b] {
+                right_recursive[comp_of[a]] = true;
+            }
+        }
+        for (comp, _) in right_comps
+            .iter()
+            .zip(right_recursive)
+            .filter(|(_, rec)| *rec)
+        {
+            let names = cycle_names(comp);
+            add_lint(
+                LintKind::RightRecursion,
+                names[0],
+                format!(
+                    "rule is right-recursive (through {}); the parser will be slow \
+                    and hit max_items_in_row after a few hundred repetitions; \
+                    use `one+` or left recursion instead",
+                    names.join(", ")
+                ),
+            );
+        }
+
+        for sym in &reachable_syms {
+            for r in &sym.rules {
+                let elt = match r.rhs.as_slice() {
+                    [a, b] if *a == sym.idx => *b,
+                    [a, b] if *b == sym.idx => *a,
+                    _ => continue,
+                };
+                if let Some(lx) = self.sym_data(elt).lexeme {
+                    if lexer_spec.is_single_char(lx) {
+                        add_lint(
+                            LintKind::SingleCharRepetition,
+                            sym_name(sym.idx),
+                            format!(
+                                "single-character lexeme {} is repeated by a rule, \
+                                which is slow and allows ignored whitespace between characters; \
+                                repeat inside the lexeme instead (an uppercase TERMINAL in Lark)",
+                                lexer_spec.lexeme_description(lx)
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        lints
+    }
+
     fn rule_to_string(&self, rule: &Rule, dot: Option<usize>, is_first: bool) -> String {
         let ldata = self.sym_data(rule.lhs());
         let dot_data = rule
@@ -380,6 +691,8 @@ impl Grammar {
         }
 
         let mut outp = Grammar::new(self.name.clone());
+        outp.warnings = self.warnings.clone();
+        outp.json_captures = self.json_captures.clone();
 
         let start_data = self.sym_data(self.start());
         if start_data.is_terminal()
@@ -631,7 +944,7 @@ pub struct CSymbol {
 }
 
 impl CSymbol {
-    fn short_name(&self) -> String {
+    pub(crate) fn short_name(&self) -> String {
         if let Some(lex) = self.lexeme {
             format!("[{}]", lex.as_usize())
         } else {
@@ -703,6 +1016,10 @@ pub struct CGrammar {
     rhs_ptr_to_sym_idx: Vec<CSymIdx>,
     // this is cache, rhs_ptr_to_sym_flags[x] == symbols[rhs_ptr_to_sym_idx[x]].sym_flags
     rhs_ptr_to_sym_flags: Vec<SymFlags>,
+    warnings: Vec<GrammarWarning>,
+    json_captures: HashMap<String, Value>,
+    // whether any symbol has rule_name, i.e., rule events are to be reported
+    has_rule_names: bool,
 }
 
 const RULE_SHIFT: usize = 2;
@@ -712,6 +1029,29 @@ impl CGrammar {
         &self.lexer_spec
     }
 
+    /// Render the grammar in the Lark syntax accepted by llguidance,
+    /// e.g., to inspect or hand-tune a grammar compiled from a JSON schema.
+    /// Requires the regex sources to be kept, as done by [`crate::api::GrammarInit::to_lark`].
+    pub fn to_lark(&self) -> Result<String> {
+        crate::lark::cgrammar_to_lark(self)
+    }
+
+    /// Warnings generated while building the grammar.
+    pub fn warnings(&self) -> &[GrammarWarning] {
+        &self.warnings
+    }
+
+    /// Whether the parser needs to track rule spans, see `LLGuidanceOptions::rule_events`.
+    pub fn has_rule_names(&self) -> bool {
+        self.has_rule_names
+    }
+
+    /// The JSON schema of capture `name`, if it is to be reported as parsed JSON
+    /// (`json_captures` option).
+    pub fn json_capture_schema(&self, name: &str) -> Option<&Value> {
+        self.json_captures.get(name)
+    }
+
     pub fn sym_idx_lhs(&self, rule: RhsPtr) -> CSymIdx {
         self.rhs_ptr_to_sym_idx[rule.as_index() >> RULE_SHIFT]
     }
@@ -774,6 +1114,9 @@ impl CGrammar {
             rhs_elements: vec![CSymIdx::NULL], // make sure RhsPtr::NULL is invalid
             rhs_ptr_to_sym_idx: vec![],
             rhs_ptr_to_sym_flags: vec![],
+            warnings: grammar.warnings.clone(),
+            json_captures: grammar.json_captures.clone(),
+            has_rule_names: grammar.symbols.iter().any(|s| s.props.rule_name.is_some()),
         };
         outp.add_symbol(CSymbol {
             idx: CSymIdx::NULL,
@@ -947,6 +1290,66 @@ fn rule_to_string(
     format!("{:15} ⇦ {}  {}", lhs, rhs.join(" "), props)
 }
 
+/// Strongly connected components of the graph that contain a cycle
+/// (Tarjan's algorithm, without recursion).
+fn cyclic_components(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let n = edges.len();
+    let mut index = vec![usize::MAX; n];
+    let mut lowlink = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = vec![];
+    let mut next_index = 0;
+    let mut result = vec![];
+    for root in 0..n {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        index[root] = next_index;
+        lowlink[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        let mut work = vec![(root, 0)];
+        while let Some(top) = work.last_mut() {
+            let v = top.0;
+            if let Some(&w) = edges[v].get(top.1) {
+                top.1 += 1;
+                if index[w] == usize::MAX {
+                    index[w] = next_index;
+                    lowlink[w] = next_index;
+                    next_index += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    lowlink[v] = lowlink[v].min(index[w]);
+                }
+            } else {
+                work.pop();
+                if let Some(&(u, _)) = work.last() {
+                    lowlink[u] = lowlink[u].min(lowlink[v]);
+                }
+                if lowlink[v] == index[v] {
+                    let mut comp = vec![];
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        comp.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    if comp.len() > 1 || edges[v].contains(&v) {
+                        comp.sort();
+                        result.push(comp);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
 fn uf_find(map: &mut [Option<SymIdx>], e: SymIdx) -> SymIdx {
     let mut root = e;
     let mut steps = 0;
@@ -3,7 +3,10 @@ use derivre::{raw::ExprSet, ExprRef, JsonQuoteOptions, RegexAst, RegexBuilder};
 use std::{fmt::Debug, hash::Hash, ops::RangeInclusive};
 use toktrie::{bytes::limit_bytes, SimpleVob, TokTrie, TokenId};
 
-use crate::{api::ParserLimits, id32_type};
+use crate::{
+    api::{ParserLimits, RegexExt},
+    id32_type, HashMap, HashSet,
+};
 
 use super::{
     lexer::MatchingLexemesIdx,
@@ -15,6 +18,7 @@ pub struct LexerSpec {
     pub lexemes: Vec<LexemeSpec>,
     pub regex_builder: RegexBuilder,
     pub no_forcing: bool,
+    pub json_captures: bool,
     pub allow_initial_skip: bool,
     pub num_extra_lexemes: usize,
     pub skip_by_class: Vec<LexemeIdx>,
@@ -23,6 +27,17 @@ pub struct LexerSpec {
     pub special_token_rx: Option<ExprRef>,
     pub has_stop: bool,
     pub has_max_tokens: bool,
+    // what the regexes were built from, so the grammar can be rendered back as Lark;
+    // only kept when record_regex_sources is set
+    pub(crate) regex_sources: HashMap<ExprRef, RegexSource>,
+    pub(crate) record_regex_sources: bool,
+}
+
+/// The definition a compiled regex was built from.
+#[derive(Clone)]
+pub(crate) enum RegexSource {
+    Ast(RegexAst),
+    Ext(RegexExt),
 }
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
@@ -47,13 +62,14 @@ pub struct LexemeSpec {
     pub(crate) rx: RegexAst,
     class: LexemeClass,
     compiled_rx: ExprRef,
-    ends_at_eos: bool,
-    lazy: bool,
+    pub(crate) ends_at_eos: bool,
+    pub(crate) lazy: bool,
     contextual: bool,
     max_tokens: usize,
+    pub(crate) priority: i32,
     pub(crate) is_suffix: bool,
     pub(crate) is_skip: bool,
-    json_options: Option<JsonQuoteOptions>,
+    pub(crate) json_options: Option<JsonQuoteOptions>,
     pub(crate) token_ranges: Vec<RangeInclusive<TokenId>>,
 }
 
@@ -92,7 +108,12 @@ impl LexemeSpec {
         use std::fmt::Write;
         let mut f = String::new();
         write!(f, "[{}] {} ", self.idx.0, self.name).unwrap();
-        self.rx.write_to_str(&mut f, max_len, exprset);
+        match (&self.rx, exprset) {
+            (RegexAst::ExprRef(e), Some(exprset)) => {
+                f.push_str(&exprset.expr_to_string_max_len(*e, max_len))
+            }
+            _ => self.rx.write_to_str(&mut f, max_len, exprset),
+        }
         if self.lazy {
             f.push_str(" lazy");
         }
@@ -102,6 +123,9 @@ impl LexemeSpec {
         if self.contextual {
             f.push_str(" contextual");
         }
+        if self.priority != 0 {
+            write!(f, " priority={}", self.priority).unwrap();
+        }
         if !self.token_ranges.is_empty() {
             write!(f, " tokens={}", token_ranges_to_string(&self.token_ranges)).unwrap();
         }
@@ -128,15 +152,36 @@ impl LexerSpec {
             special_token_rx: None,
             regex_builder: RegexBuilder::new(),
             no_forcing: false,
+            json_captures: false,
             allow_initial_skip: false,
             num_extra_lexemes: 0,
             skip_by_class: Vec::new(),
             current_class: LexemeClass(0),
             has_stop: false,
             has_max_tokens: false,
+            regex_sources: HashMap::default(),
+            record_regex_sources: false,
         })
     }
 
+    /// Remember what `id` was built from; the first definition wins.
+    pub(crate) fn add_regex_source(&mut self, id: ExprRef, source: RegexSource) {
+        if !self.record_regex_sources {
+            return;
+        }
+        if let RegexSource::Ast(ast) = &source {
+            // derivre may simplify e.g. Or([x]) to x
+            if ast
+                .get_args()
+                .iter()
+                .any(|a| matches!(a, RegexAst::ExprRef(r) if *r == id))
+            {
+                return;
+            }
+        }
+        self.regex_sources.entry(id).or_insert(source);
+    }
+
     pub fn can_rollback(&self) -> bool {
         !self.has_stop && !self.has_max_tokens
     }
@@ -203,6 +248,10 @@ impl LexerSpec {
         self.lexeme_set(|lex| lex.ends_at_eos)
     }
 
+    pub fn has_token_ranges(&self) -> bool {
+        self.lexemes.iter().any(|l| !l.token_ranges.is_empty())
+    }
+
     pub fn token_range_lexemes(&self, possible: &LexemeSet) -> Vec<&LexemeSpec> {
         let mut res = Vec::new();
         for idx in possible.iter() {
@@ -219,6 +268,53 @@ impl LexerSpec {
             .is_nullable(self.lexemes[idx.as_usize()].compiled_rx)
     }
 
+    /// Check if the lexeme only matches single (UTF-8) characters, like `/[a-z]/`.
+    pub fn is_single_char(&self, idx: LexemeIdx) -> bool {
+        let Ok(mut rx) = self
+            .regex_builder
+            .to_regex_limited(self.lexemes[idx.as_usize()].compiled_rx, 100_000)
+        else {
+            return false;
+        };
+        let initial = rx.initial_state();
+        if rx.is_accepting(initial) {
+            return false;
+        }
+        // (state, number of continuation bytes left in the first character);
+        // the initial state is not revisited, as it's not accepting
+        let mut todo = vec![(initial, 0)];
+        let mut visited = HashSet::default();
+        while let Some((state, cont_left)) = todo.pop() {
+            let char_done = cont_left == 0 && state != initial;
+            for b in 0..=255u8 {
+                let next_cont_left = if cont_left > 0 {
+                    if b & 0xC0 != 0x80 {
+                        continue;
+                    }
+                    cont_left - 1
+                } else {
+                    match b.leading_ones() {
+                        0 => 0,
+                        n @ 2..=4 => n as usize - 1,
+                        _ => 0,
+                    }
+                };
+                let next = rx.transition(state, b);
+                if next.is_dead() {
+                    continue;
+                }
+                if char_done {
+                    // can match more than one character
+                    return false;
+                }
+                if visited.insert((next, next_cont_left)) {
+                    todo.push((next, next_cont_left));
+                }
+            }
+        }
+        true
+    }
+
     pub fn to_regex_vec(&self, limits: &mut ParserLimits) -> Result<RegexVec> {
         // TODO
         // Find all non-contextual lexemes that are literals (we call them 'keywords')
@@ -231,7 +327,7 @@ impl LexerSpec {
             .iter()
             .map(|lex| RxLexeme {
                 rx: lex.compiled_rx,
-                priority: 0,
+                priority: lex.priority,
                 lazy: lex.lazy,
             })
             .collect();
@@ -285,6 +381,7 @@ impl LexerSpec {
             lex.compiled_rx == compiled
                 && lex.class == spec.class
                 && lex.max_tokens == spec.max_tokens
+                && lex.priority == spec.priority
                 && lex.token_ranges == spec.token_ranges
         }) {
             return Ok(LexemeIdx::new(idx));
@@ -316,6 +413,7 @@ impl LexerSpec {
             json_options: None,
             class: self.current_class,
             max_tokens: usize::MAX,
+            priority: 0,
             token_ranges: vec![],
         }
     }
@@ -378,6 +476,7 @@ impl LexerSpec {
         contextual: bool,
         json_options: Option<JsonQuoteOptions>,
         max_tokens: usize,
+        priority: i32,
     ) -> Result<LexemeIdx> {
         self.add_lexeme_spec(LexemeSpec {
             name,
@@ -385,6 +484,7 @@ impl LexerSpec {
             contextual,
             json_options,
             max_tokens,
+            priority,
             ..self.empty_spec()
         })
     }
@@ -399,6 +499,7 @@ impl LexerSpec {
                 false,
                 None,
                 usize::MAX,
+                0,
             )
             .expect("adding lexeme");
         }
@@ -431,6 +532,13 @@ impl LexerSpec {
         self.skip_by_class[class.as_usize()]
     }
 
+    /// Regex of the lexeme, shortened for use in error messages.
+    pub fn lexeme_description(&self, idx: LexemeIdx) -> String {
+        self.regex_builder
+            .exprset()
+            .expr_to_string_max_len(self.lexemes[idx.as_usize()].compiled_rx, 64)
+    }
+
     pub fn lexeme_def_to_string(&self, idx: LexemeIdx) -> String {
         self.lexemes[idx.as_usize()].to_string(512, Some(self.regex_builder.exprset()))
     }
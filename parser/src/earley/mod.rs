@@ -12,6 +12,6 @@ pub mod regexvec;
 pub use grammar::{CGrammar, CSymIdx, Grammar, SymIdx, SymbolProps};
 pub use parser::{
     BiasComputer, DefaultBiasComputer, Parser, ParserError, ParserMetrics, ParserRecognizer,
-    ParserStats, XorShift,
+    ParserStats, RejectionReport, RuleEvent, RuleInProgress, XorShift,
 };
 pub use slicer::SlicedBiasComputer;
@@ -5,7 +5,7 @@
 // (Retrieved 18 Sep 2024).
 
 use std::{
-    fmt::Debug,
+    fmt::{Debug, Display},
     hash::Hash,
     ops::Range,
     sync::{Arc, Mutex},
@@ -398,6 +398,79 @@ impl Captures {
     }
 }
 
+/// A rule (a symbol with `rule_name`) matched the bytes `start..end` of the output;
+/// see `LLGuidanceOptions::rule_events`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleEvent {
+    pub rule: String,
+    pub start: usize,
+    pub end: usize,
+    /// Set when the rule matched the same span in more than one way.
+    pub ambiguous: bool,
+}
+
+/// Why the parser rejected some bytes; see `TokenParser::rejection_report()`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RejectionReport {
+    /// Offset of the first rejected byte in the output.
+    pub byte_offset: usize,
+    /// The rejected token, if any.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub token: Option<String>,
+    /// Bytes that could not be consumed.
+    pub rejected: String,
+    /// Output consumed so far (only the end of it, if it's long).
+    pub consumed: String,
+    /// Lexemes allowed at `byte_offset`.
+    pub expected_lexemes: Vec<String>,
+    /// Rules that are partially matched at `byte_offset`.
+    pub rules_in_progress: Vec<RuleInProgress>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RuleInProgress {
+    pub rule: String,
+    /// Right-hand side of the rule, with `•` marking the current position.
+    pub rhs: String,
+    /// Offset where the rule started.
+    pub start: usize,
+}
+
+impl Display for RejectionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rejected {:?}", self.rejected)?;
+        if let Some(token) = &self.token {
+            write!(f, " (token {})", token)?;
+        }
+        writeln!(
+            f,
+            " at byte {}, after {:?}",
+            self.byte_offset, self.consumed
+        )?;
+        writeln!(f, "expected one of:")?;
+        for lex in &self.expected_lexemes {
+            writeln!(f, "  {}", lex)?;
+        }
+        write!(
+            f,
+            "rules in progress: {}",
+            serde_json::to_string(&self.rules_in_progress).unwrap()
+        )
+    }
+}
+
+#[derive(Clone, Default)]
+struct RuleEvents {
+    events: Vec<RuleEvent>,
+    // byte offset of the start of each row (only kept with rule events);
+    // truncated when row_infos change
+    row_offsets: Vec<usize>,
+    // first event of the row currently being processed
+    row_first_event: usize,
+    // (symbol, start row) of rule items completed more than once in the current row
+    ambiguous: HashSet<(CSymIdx, usize)>,
+}
+
 #[derive(Clone)]
 struct ParserState {
     grammar: Arc<CGrammar>,
@@ -406,6 +479,7 @@ struct ParserState {
     trie_lexer_stack: usize,
     trie_grammar_stack: usize,
     captures: Captures,
+    rule_events: RuleEvents,
 
     // These are updated also in speculative mode.
     // Both are stacks only in the sense that items can be popped on backtracking
@@ -553,10 +627,15 @@ impl Scratch {
     // Ensure that Earley table 'self' contains
     // Earley item 'item'.  That is, look for 'item' in 'self',
     // and add 'item' to 'self' if it is not there already.
+    //
+    // Returns false if the item was already there.
     #[inline(always)]
-    fn add_unique(&mut self, item: Item, origin_item_idx: usize, info: &str) {
+    fn add_unique(&mut self, item: Item, origin_item_idx: usize, info: &str) -> bool {
         if self.find_item(item).is_none() {
             self.just_add(item, origin_item_idx, info);
+            true
+        } else {
+            false
         }
     }
 
@@ -592,6 +671,7 @@ impl ParserState {
             rows_valid_end: 0,
             row_infos: vec![],
             captures: Captures::new(),
+            rule_events: RuleEvents::default(),
             scratch,
             stats: ParserStats::default(),
             metrics: ParserMetrics::default(),
@@ -950,6 +1030,7 @@ impl ParserState {
         self.lexer_stack.truncate(new_len + 1);
 
         self.row_infos.truncate(self.num_rows());
+        self.truncate_row_offsets(self.num_rows());
         self.token_idx = *self.byte_to_token_idx.last().unwrap_or(&0) as usize;
         self.last_force_bytes_len = usize::MAX;
         self.lexer_stack_top_eos = false;
@@ -1097,7 +1178,11 @@ impl ParserState {
                 if !ok {
                     if bidx == 0 {
                         let toks = self.tok_env.tok_trie().greedy_tokenize(tok_bytes);
-                        if self.flush_lexer() && toks.len() == 1 {
+                        // only flush if needed, so the state can be still reported on error
+                        if toks.len() == 1
+                            && self.lexer_spec().has_token_ranges()
+                            && self.flush_lexer()
+                        {
                             for spec in self.token_range_lexemes() {
                                 if spec.contains_token(toks[0]) {
                                     self.add_numeric_token(spec.idx, tok_bytes)?;
@@ -1461,6 +1546,91 @@ impl ParserState {
         self.run_speculative("is_accepting", |s| s.is_accepting_inner())
     }
 
+    fn rejection_report(&mut self, rejected: &[u8]) -> RejectionReport {
+        const MAX_CONSUMED: usize = 100;
+
+        let byte_offset = self.byte_to_token_idx.len();
+        let consumed = &self.bytes[..byte_offset];
+        let consumed = if consumed.len() > MAX_CONSUMED {
+            format!(
+                "...{}",
+                String::from_utf8_lossy(&consumed[consumed.len() - MAX_CONSUMED..])
+            )
+        } else {
+            String::from_utf8_lossy(consumed).to_string()
+        };
+
+        let lexemes = self.expected_lexemes();
+        let spec = self.lexer_spec();
+        let expected_lexemes = lexemes
+            .iter()
+            .filter(|idx| !spec.lexeme_spec(*idx).is_skip)
+            .map(|idx| spec.lexeme_def_to_string(idx))
+            .collect();
+
+        let mut rules_in_progress = vec![];
+        for i in self.curr_row().item_indices() {
+            let item = self.scratch.items[i];
+            let (rhs, dot) = self.grammar.rule_rhs(item.rhs_ptr());
+            if dot == 0 || dot == rhs.len() {
+                continue;
+            }
+            let mut rhs = rhs
+                .iter()
+                .map(|s| self.grammar.sym_data(*s).short_name())
+                .collect::<Vec<_>>();
+            rhs.insert(dot, "•".to_string());
+            let rule = self
+                .grammar
+                .sym_name(self.grammar.sym_idx_lhs(item.rhs_ptr()))
+                .to_string();
+            rules_in_progress.push((rule, rhs.join(" "), item.start_pos()));
+        }
+        let rules_in_progress = rules_in_progress
+            .into_iter()
+            .map(|(rule, rhs, start_row)| RuleInProgress {
+                rule,
+                rhs,
+                start: self.row_byte_offset(start_row),
+            })
+            .collect();
+
+        RejectionReport {
+            byte_offset,
+            token: None,
+            rejected: String::from_utf8_lossy(rejected).to_string(),
+            consumed,
+            expected_lexemes,
+            rules_in_progress,
+        }
+    }
+
+    // Lexemes that can continue the current lexeme, or, if the current lexeme
+    // can end here, start the next one.
+    fn expected_lexemes(&mut self) -> LexemeSet {
+        let mut res = self
+            .lexer()
+            .possible_lexemes(self.lexer_state().lexer_state)
+            .clone();
+        if self.has_pending_lexeme_bytes() {
+            let next = self.run_speculative("expected_lexemes", |s| {
+                if s.flush_lexer() {
+                    Some(
+                        s.lexer()
+                            .possible_lexemes(s.lexer_state().lexer_state)
+                            .clone(),
+                    )
+                } else {
+                    None
+                }
+            });
+            for idx in next.iter().flat_map(|n| n.iter()) {
+                res.add(idx);
+            }
+        }
+        res
+    }
+
     // try_push_byte_definitive() attempts to 'push' a byte (that is advance
     // the parse with 'byte') into the parse in definitive mode.
     // Returns 'false' if this is not possible.
@@ -1771,6 +1941,115 @@ impl ParserState {
             }
             self.captures.push(self.mk_capture(var_name, &bytes));
         }
+
+        if let Some(rule) = sym_data.props.rule_name.clone() {
+            let start_row = if is_lexeme {
+                curr_idx - 1
+            } else {
+                capture_start
+            };
+            if start_row < curr_idx {
+                let start = self.row_byte_offset(start_row);
+                let end = self.row_byte_offset(curr_idx - 1)
+                    + lexeme.upper_visible_bytes(is_lexeme).len();
+                if start < end {
+                    debug!("      rule event: {} {}-{}", rule, start, end);
+                    self.rule_events.events.push(RuleEvent {
+                        rule,
+                        start,
+                        end,
+                        ambiguous: false,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Byte offset (in the visible output) where the given row starts.
+    /// Only valid for rows whose preceding lexemes are already known.
+    fn row_byte_offset(&mut self, row: usize) -> usize {
+        let offsets = &mut self.rule_events.row_offsets;
+        while offsets.len() <= row {
+            let r = offsets.len();
+            let off = if r == 0 {
+                0
+            } else {
+                offsets[r - 1]
+                    + self.row_infos[r - 1]
+                        .lexeme
+                        .upper_visible_bytes(false)
+                        .len()
+            };
+            offsets.push(off);
+        }
+        offsets[row]
+    }
+
+    /// Forget byte offsets of rows from `row` on; they are only kept for rule events.
+    fn truncate_row_offsets(&mut self, row: usize) {
+        if self.grammar.has_rule_names() {
+            self.rule_events.row_offsets.truncate(row);
+        }
+    }
+
+    fn note_ambiguous_rule(&mut self, item: Item) {
+        let rule = item.rhs_ptr();
+        if self.grammar.sym_idx_dot(rule) == CSymIdx::NULL {
+            let entry = (self.grammar.sym_idx_lhs(rule), item.start_pos());
+            self.rule_events.ambiguous.insert(entry);
+        }
+    }
+
+    /// Mark events of the current row that were derived more than once
+    /// (either from different items, or the same item added twice) as ambiguous.
+    fn finish_rule_events(&mut self) {
+        let mut ambiguous = std::mem::take(&mut self.rule_events.ambiguous);
+
+        // rule names are typically on wrapper symbols (X -> inner), so propagate
+        // ambiguity of completed symbols through such single-symbol rules
+        let mut changed = !ambiguous.is_empty();
+        while changed {
+            changed = false;
+            for &item in &self.scratch.items[self.scratch.row_start..self.scratch.row_end] {
+                let rule = item.rhs_ptr();
+                if self.grammar.sym_idx_dot(rule) != CSymIdx::NULL {
+                    continue;
+                }
+                if let (&[sym], _) = self.grammar.rule_rhs(rule) {
+                    let entry = (self.grammar.sym_idx_lhs(rule), item.start_pos());
+                    if ambiguous.contains(&(sym, entry.1)) && ambiguous.insert(entry) {
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        for (lhs, start_row) in ambiguous {
+            let rule = match self.grammar.sym_data(lhs).props.rule_name.as_ref() {
+                Some(rule) => rule.clone(),
+                None => continue,
+            };
+            let start = self.row_byte_offset(start_row);
+            for ev in self.rule_events.events[self.rule_events.row_first_event..].iter_mut() {
+                if ev.start == start && ev.rule == rule {
+                    ev.ambiguous = true;
+                }
+            }
+        }
+
+        let mut idx = self.rule_events.row_first_event;
+        while idx < self.rule_events.events.len() {
+            let ev = &self.rule_events.events[idx];
+            if let Some(prev) = self.rule_events.events[self.rule_events.row_first_event..idx]
+                .iter()
+                .position(|e| e.rule == ev.rule && e.start == ev.start && e.end == ev.end)
+            {
+                self.rule_events.events[self.rule_events.row_first_event + prev].ambiguous = true;
+                self.rule_events.events.remove(idx);
+            } else {
+                idx += 1;
+            }
+        }
     }
 
     fn process_captures(&mut self, item: Item, curr_idx: usize, lexeme: &Lexeme, for_lexeme: bool) {
@@ -1801,6 +2080,11 @@ impl ParserState {
     fn process_agenda(&mut self, curr_idx: usize, lexeme: &Lexeme) {
         let mut agenda_ptr = self.scratch.row_start;
 
+        let track_rules = self.scratch.definitive && self.grammar.has_rule_names();
+        if track_rules {
+            self.rule_events.row_first_event = self.rule_events.events.len();
+        }
+
         self.scratch.push_allowed_lexemes.clear();
         self.scratch.push_allowed_grammar_ids.set_all(false);
 
@@ -1847,7 +2131,10 @@ impl ParserState {
                     for i in self.rows[item.start_pos()].item_indices() {
                         let item = self.scratch.items[i];
                         if self.grammar.sym_idx_dot(item.rhs_ptr()) == lhs {
-                            self.scratch.add_unique(item.advance_dot(), i, "complete");
+                            let new_item = item.advance_dot();
+                            if !self.scratch.add_unique(new_item, i, "complete") && track_rules {
+                                self.note_ambiguous_rule(new_item);
+                            }
                         }
                     }
                 }
@@ -1890,6 +2177,13 @@ impl ParserState {
                 }
             }
         }
+
+        if track_rules
+            && (self.rule_events.events.len() > self.rule_events.row_first_event
+                || !self.rule_events.ambiguous.is_empty())
+        {
+            self.finish_rule_events();
+        }
     }
 
     #[inline(always)]
@@ -1950,6 +2244,7 @@ impl ParserState {
                 if self.row_infos.len() > idx {
                     self.row_infos.drain(idx..);
                 }
+                self.truncate_row_offsets(idx);
 
                 // Typically, the current byte was not yet pushed,
                 // yet it's part of the previous lexeme.
@@ -2176,6 +2471,7 @@ impl ParserState {
         if self.scratch.definitive {
             // save lexeme at the last row, before we mess with the stack
             self.row_infos[added_row - 1].lexeme = lexeme;
+            self.truncate_row_offsets(added_row);
             // if there is a transition byte it means it goes to the next lexeme,
             // and thus we were overeager assigning start_byte_idx,
             // so we need to correct it
@@ -2388,6 +2684,7 @@ impl ParserState {
                     if self.scratch.definitive {
                         // clean up row infos if needed
                         self.row_infos.drain(no_hidden.row_idx as usize..);
+                        self.truncate_row_offsets(no_hidden.row_idx as usize);
                     }
                     return false;
                 }
@@ -2613,10 +2910,19 @@ impl Parser {
         self.state.captures.capture_map.get(name).map(|v| &v[..])
     }
 
+    pub fn rule_events(&self) -> &[RuleEvent] {
+        &self.state.rule_events.events
+    }
+
     pub fn stats(&self) -> &ParserStats {
         &self.state.stats
     }
 
+    /// Number of Earley items in the current row (see `ParserLimits::max_items_in_row`).
+    pub fn items_in_row(&self) -> usize {
+        self.state.curr_row().item_indices().len()
+    }
+
     pub fn set_perf_counters(&mut self, counters: Arc<ParserPerfCounters>) {
         self.state.perf_counters = counters;
     }
@@ -2761,6 +3067,28 @@ impl Parser {
         self.with_shared(|state| state.is_accepting())
     }
 
+    /// Explain why `rejected` bytes can't be consumed at the current position.
+    pub fn rejection_report(&mut self, rejected: &[u8]) -> RejectionReport {
+        self.with_shared(|state| state.rejection_report(rejected))
+    }
+
+    /// Number of bytes consumed with apply_token(), not counting forced bytes.
+    pub fn num_applied_bytes(&self) -> usize {
+        self.state.byte_to_token_idx.len()
+    }
+
+    /// Regexes of (non-skip) lexemes that are allowed at the current position.
+    pub fn expected_lexemes(&mut self) -> Vec<String> {
+        self.with_shared(|state| {
+            let set = state.expected_lexemes();
+            let spec = state.lexer_spec();
+            set.iter()
+                .filter(|idx| !spec.lexeme_spec(*idx).is_skip)
+                .map(|idx| spec.lexeme_description(idx))
+                .collect()
+        })
+    }
+
     pub fn currently_forced_bytes(&self) -> &[u8] {
         &self.state.bytes[self.state.byte_to_token_idx.len()..]
     }
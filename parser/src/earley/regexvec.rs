@@ -440,14 +440,30 @@ impl RegexVec {
         }
 
         if lazies.is_some() {
-            desc.lazy_accepting = lazies;
+            desc.lazy_accepting = self.highest_priority(lazies);
             desc.lazy_hidden_len = hidden_len;
         } else if all_eoi {
-            desc.lazy_accepting = eois;
+            desc.lazy_accepting = self.highest_priority(eois);
             // no hidden len
         }
     }
 
+    /// When several lexemes match the same bytes, only keep the ones with highest priority.
+    fn highest_priority(&self, lexemes: MatchingLexemes) -> MatchingLexemes {
+        if lexemes.len() < 2 {
+            return lexemes;
+        }
+        let priority = |idx: &LexemeIdx| self.rx_lexemes[idx.as_usize()].priority;
+        let max_priority = lexemes.as_slice().iter().map(priority).max().unwrap();
+        let mut res = MatchingLexemes::None;
+        for idx in lexemes.as_slice() {
+            if priority(idx) == max_priority {
+                res.add(*idx);
+            }
+        }
+        res
+    }
+
     /// Check if the there is only one transition out of state.
     /// This is an approximation - see docs for NextByte.
     pub fn next_byte(&mut self, state: StateID) -> NextByte {
@@ -547,7 +563,6 @@ impl RegexVec {
 pub(crate) struct RxLexeme {
     pub rx: ExprRef,
     pub lazy: bool,
-    #[allow(dead_code)]
     pub priority: i32,
 }
 
@@ -687,6 +702,7 @@ impl RegexVec {
         if res.possible.is_empty() {
             assert!(state == StateID::DEAD);
         }
+        res.greedy_accepting = self.highest_priority(res.greedy_accepting);
 
         self.lowest_match_inner(&mut res);
 
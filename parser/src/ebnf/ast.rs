@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Result};
+
+use crate::HashMap;
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal(String),
+    CharClass {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    AnyChar,
+    Rule(usize),
+    Seq(Vec<Expr>),
+    Alt(Vec<Expr>),
+    Repeat(Box<Expr>, u32, Option<u32>),
+    /// `a - b`, i.e., strings matching `a` but not `b`; only valid for terminals
+    Diff(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn visit_rules(&self, f: &mut impl FnMut(usize)) {
+        match self {
+            Expr::Rule(r) => f(*r),
+            Expr::Seq(args) | Expr::Alt(args) => args.iter().for_each(|a| a.visit_rules(f)),
+            Expr::Repeat(e, _, _) => e.visit_rules(f),
+            Expr::Diff(a, b) => {
+                a.visit_rules(f);
+                b.visit_rules(f);
+            }
+            Expr::Literal(_) | Expr::CharClass { .. } | Expr::AnyChar => {}
+        }
+    }
+
+    /// Whether `-` is used, not counting referenced rules.
+    pub fn has_diff(&self) -> bool {
+        match self {
+            Expr::Diff(..) => true,
+            Expr::Seq(args) | Expr::Alt(args) => args.iter().any(|a| a.has_diff()),
+            Expr::Repeat(e, _, _) => e.has_diff(),
+            Expr::Rule(_) | Expr::Literal(_) | Expr::CharClass { .. } | Expr::AnyChar => false,
+        }
+    }
+
+    pub fn seq(mut items: Vec<Expr>) -> Expr {
+        if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            Expr::Seq(items)
+        }
+    }
+
+    pub fn alt(mut items: Vec<Expr>) -> Expr {
+        if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            Expr::Alt(items)
+        }
+    }
+}
+
+pub struct Rule {
+    pub name: String,
+    pub body: Option<Expr>,
+    /// Location of first reference, for error reporting.
+    pub used_at: Option<(usize, usize)>,
+}
+
+/// Input position and rules defined so far; shared by the GBNF and W3C parsers.
+pub struct Parser<'a> {
+    pub src: &'a str,
+    pub pos: usize,
+    pub rules: Vec<Rule>,
+    rule_ids: HashMap<String, usize>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Parser {
+            src,
+            pos: 0,
+            rules: vec![],
+            rule_ids: HashMap::default(),
+        }
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    pub fn peek2(&self) -> Option<char> {
+        self.src[self.pos..].chars().nth(1)
+    }
+
+    pub fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    pub fn starts_with(&self, s: &str) -> bool {
+        self.src[self.pos..].starts_with(s)
+    }
+
+    fn line_col(&self, pos: usize) -> (usize, usize) {
+        let before = &self.src[..pos];
+        let line = before.matches('\n').count() + 1;
+        let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        (line, col)
+    }
+
+    pub fn err(&self, msg: impl std::fmt::Display) -> anyhow::Error {
+        let (line, col) = self.line_col(self.pos);
+        anyhow!("at {}({}): {}", line, col, msg)
+    }
+
+    pub fn expect(&mut self, s: &str) -> Result<()> {
+        if self.starts_with(s) {
+            self.pos += s.len();
+            Ok(())
+        } else {
+            Err(self.err(format!("expecting {:?}", s)))
+        }
+    }
+
+    pub fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.next();
+        }
+        &self.src[start..self.pos]
+    }
+
+    pub fn parse_hex(&mut self, min_len: usize, max_len: usize) -> Result<char> {
+        let start = self.pos;
+        while self.pos - start < max_len && self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.next();
+        }
+        if self.pos - start < min_len {
+            return Err(self.err(format!("expecting {} hex digits", min_len)));
+        }
+        let v = u32::from_str_radix(&self.src[start..self.pos], 16).unwrap();
+        char::from_u32(v).ok_or_else(|| self.err(format!("invalid code point {:#x}", v)))
+    }
+
+    /// Reference rule `name` at `self.pos`.
+    pub fn rule_ref(&mut self, name: &str) -> Expr {
+        let used_at = Some(self.line_col(self.pos - name.len()));
+        let id = self.rule_id(name);
+        let rule = &mut self.rules[id];
+        rule.used_at = rule.used_at.or(used_at);
+        Expr::Rule(id)
+    }
+
+    /// Define rule `name` that started at `start`.
+    pub fn define_rule(&mut self, start: usize, name: &str, body: Expr) -> Result<usize> {
+        let id = self.rule_id(name);
+        if self.rules[id].body.is_some() {
+            self.pos = start;
+            return Err(self.err(format!("rule {:?} defined twice", name)));
+        }
+        self.rules[id].body = Some(body);
+        Ok(id)
+    }
+
+    pub fn lookup_rule(&self, name: &str) -> Option<usize> {
+        self.rule_ids.get(name).copied()
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.rule_ids.get(name) {
+            return id;
+        }
+        let id = self.rules.len();
+        self.rules.push(Rule {
+            name: name.to_string(),
+            body: None,
+            used_at: None,
+        });
+        self.rule_ids.insert(name.to_string(), id);
+        id
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{anyhow, bail, Result};
+use derivre::{ExprRef, RegexAst};
+
+use crate::{
+    api::{GrammarLint, LLGuidanceOptions, LintKind},
+    grammar_builder::{GrammarResult, RegexId},
+    GrammarBuilder, NodeRef,
+};
+
+use super::ast::{Expr, Rule};
+
+struct Compiler {
+    builder: GrammarBuilder,
+    rules: Vec<Rule>,
+    is_regular: Vec<bool>,
+    is_terminal: Vec<bool>,
+    case_insensitive: bool,
+    regex_ids: Vec<Option<RegexId>>,
+    node_ids: Vec<Option<NodeRef>>,
+}
+
+/// How rules are lowered, beyond the rules themselves.
+#[derive(Default)]
+pub struct CompileOptions {
+    /// Rules to compile into lexemes; if not set, all rules (other than `start`)
+    /// that can be are.
+    pub terminals: Option<Vec<bool>>,
+    /// Skipped between lexemes, like `%ignore` in Lark.
+    pub ignore: Vec<Expr>,
+    /// Match string literals outside of terminals case-insensitively.
+    pub case_insensitive: bool,
+}
+
+/// Lower parsed rules into `builder`, with `start` as the start rule.
+///
+/// Rules (other than `start`) that only reference other such rules
+/// (and thus are not recursive) can be compiled into a single lexeme,
+/// similar to TERMINALS in Lark. Everything else becomes grammar nodes.
+pub fn compile_rules(
+    builder: GrammarBuilder,
+    rules: Vec<Rule>,
+    start: usize,
+    options: CompileOptions,
+) -> Result<GrammarResult> {
+    for r in &rules {
+        if r.body.is_none() {
+            let (line, col) = r.used_at.unwrap();
+            bail!("at {}({}): rule {:?} not defined", line, col, r.name);
+        }
+    }
+
+    let mut is_regular = vec![false; rules.len()];
+    loop {
+        let mut changed = false;
+        for (id, r) in rules.iter().enumerate() {
+            if id == start || is_regular[id] {
+                continue;
+            }
+            let mut ok = true;
+            r.body
+                .as_ref()
+                .unwrap()
+                .visit_rules(&mut |r| ok &= is_regular[r]);
+            if ok {
+                is_regular[id] = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let is_terminal = match options.terminals {
+        Some(mut terminals) => {
+            terminals[start] = false;
+            for (id, r) in rules.iter().enumerate() {
+                if terminals[id] && !is_regular[id] {
+                    bail!(
+                        "terminal {:?} can only reference non-recursive rules",
+                        r.name
+                    );
+                }
+            }
+            // '-' can only be lowered to regexes, so rules using it (directly or
+            // through other non-terminals) are matched as a whole too
+            let explicit = terminals.clone();
+            loop {
+                let mut changed = false;
+                for (id, r) in rules.iter().enumerate() {
+                    if terminals[id] || !is_regular[id] {
+                        continue;
+                    }
+                    let body = r.body.as_ref().unwrap();
+                    let mut diff = body.has_diff();
+                    body.visit_rules(&mut |r| diff |= terminals[r] && !explicit[r]);
+                    if diff {
+                        terminals[id] = true;
+                        changed = true;
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+            terminals
+        }
+        None => is_regular.clone(),
+    };
+
+    let n = rules.len();
+    let mut compiler = Compiler {
+        builder,
+        rules,
+        is_regular,
+        is_terminal,
+        case_insensitive: options.case_insensitive,
+        regex_ids: vec![None; n],
+        node_ids: vec![None; n],
+    };
+    let mut ignore = vec![];
+    for expr in &options.ignore {
+        if !compiler.is_regular(expr) {
+            bail!("%ignore can only reference non-recursive rules");
+        }
+        ignore.push(RegexAst::ExprRef(compiler.do_regex(expr)?));
+    }
+    let id = compiler
+        .builder
+        .add_grammar(LLGuidanceOptions::default(), RegexAst::Or(ignore))?;
+    let start = compiler.do_rule(start)?;
+    compiler.builder.set_start_node(start);
+    for (idx, r) in compiler.rules.iter().enumerate() {
+        if compiler.node_ids[idx].is_none() && compiler.regex_ids[idx].is_none() {
+            compiler.builder.add_lint(GrammarLint {
+                kind: LintKind::Unreachable,
+                rule: r.name.clone(),
+                message: "rule is not reachable from start".to_string(),
+            });
+        }
+    }
+    Ok(compiler.builder.finalize(id))
+}
+
+impl Compiler {
+    fn body(&self, rule: usize) -> &Expr {
+        self.rules[rule].body.as_ref().unwrap()
+    }
+
+    fn is_regular(&self, expr: &Expr) -> bool {
+        let mut ok = true;
+        expr.visit_rules(&mut |r| ok &= self.is_regular[r]);
+        ok
+    }
+
+    fn char_class(negated: bool, ranges: &[(char, char)]) -> String {
+        if ranges.is_empty() {
+            assert!(negated);
+            return "(?s:.)".to_string();
+        }
+        let esc = |c: char| {
+            if c.is_ascii_alphanumeric() {
+                c.to_string()
+            } else {
+                format!("\\x{{{:x}}}", c as u32)
+            }
+        };
+        let mut rx = if negated { "[^" } else { "[" }.to_string();
+        for &(start, end) in ranges {
+            rx.push_str(&esc(start));
+            if start != end {
+                rx.push('-');
+                rx.push_str(&esc(end));
+            }
+        }
+        rx.push(']');
+        rx
+    }
+
+    fn do_regex(&mut self, expr: &Expr) -> Result<RegexId> {
+        Ok(match expr {
+            Expr::Literal(s) => self.builder.regex.literal(s.clone()),
+            Expr::CharClass { negated, ranges } => {
+                if ranges.is_empty() && !negated {
+                    ExprRef::NO_MATCH
+                } else {
+                    self.builder
+                        .regex
+                        .regex(&Self::char_class(*negated, ranges))?
+                }
+            }
+            Expr::AnyChar => self.builder.regex.regex("(?s:.)")?,
+            Expr::Rule(r) => self.do_rule_regex(*r)?,
+            Expr::Seq(args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.do_regex(a))
+                    .collect::<Result<Vec<_>>>()?;
+                self.builder.regex.concat(args)
+            }
+            Expr::Alt(args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.do_regex(a))
+                    .collect::<Result<Vec<_>>>()?;
+                self.builder.regex.select(args)
+            }
+            Expr::Repeat(e, min, max) => {
+                let e = self.do_regex(e)?;
+                self.builder.regex.repeat(e, *min, *max)
+            }
+            Expr::Diff(a, b) => {
+                let a = self.do_regex(a)?;
+                let b = self.do_regex(b)?;
+                let not_b = self.builder.regex.not(b);
+                self.builder.regex.and(vec![a, not_b])
+            }
+        })
+    }
+
+    fn do_rule_regex(&mut self, rule: usize) -> Result<RegexId> {
+        if let Some(id) = self.regex_ids[rule] {
+            return Ok(id);
+        }
+        let body = self.body(rule).clone();
+        let id = self.do_regex(&body)?;
+        self.regex_ids[rule] = Some(id);
+        Ok(id)
+    }
+
+    fn do_expr(&mut self, expr: &Expr) -> Result<NodeRef> {
+        Ok(match expr {
+            Expr::Literal(s) if self.case_insensitive && s.chars().any(|c| c.is_alphabetic()) => {
+                let rx = self
+                    .builder
+                    .regex
+                    .regex(&format!("(?i){}", regex_syntax::escape(s)))?;
+                self.builder.lexeme(rx)
+            }
+            Expr::Literal(s) => self.builder.string(s),
+            Expr::CharClass { .. } | Expr::AnyChar => {
+                let rx = self.do_regex(expr)?;
+                self.builder.lexeme(rx)
+            }
+            Expr::Diff(..) => {
+                // only regular expressions can be subtracted
+                if !self.is_regular(expr) {
+                    bail!("operands of '-' can only reference non-recursive rules");
+                }
+                let rx = self.do_regex(expr)?;
+                self.builder.lexeme(rx)
+            }
+            Expr::Rule(r) => self.do_rule(*r)?,
+            Expr::Seq(args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.do_expr(a))
+                    .collect::<Result<Vec<_>>>()?;
+                self.builder.join(&args)
+            }
+            Expr::Alt(args) => {
+                self.builder.check_limits()?;
+                let args = args
+                    .iter()
+                    .map(|a| self.do_expr(a))
+                    .collect::<Result<Vec<_>>>()?;
+                self.builder.select(&args)
+            }
+            Expr::Repeat(e, min, max) => {
+                let inner = self.do_expr(e)?;
+                match (min, max) {
+                    (0, None) => self.builder.zero_or_more(inner),
+                    (1, None) => self.builder.one_or_more(inner),
+                    (0, Some(1)) => self.builder.optional(inner),
+                    _ => self
+                        .builder
+                        .repeat(inner, *min as usize, max.map(|m| m as usize)),
+                }
+            }
+        })
+    }
+
+    fn do_rule(&mut self, rule: usize) -> Result<NodeRef> {
+        if let Some(id) = self.node_ids[rule] {
+            return Ok(id);
+        }
+        if self.is_terminal[rule] {
+            let rx = self.do_rule_regex(rule)?;
+            let id = self.builder.lexeme(rx);
+            self.node_ids[rule] = Some(id);
+            return Ok(id);
+        }
+        let id = self.builder.new_node(&self.rules[rule].name);
+        self.node_ids[rule] = Some(id);
+        let body = self.body(rule).clone();
+        let inner = self
+            .do_expr(&body)
+            .map_err(|e| anyhow!("in rule {:?}: {}", self.rules[rule].name, e))?;
+        self.builder.set_placeholder(id, inner);
+        self.builder.set_source_name(id, &self.rules[rule].name);
+        self.builder.set_source_name(inner, &self.rules[rule].name);
+        Ok(id)
+    }
+}
@@ -0,0 +1,252 @@
+//! Frontend for the GBNF grammar format used by llama.cpp;
+//! see <https://github.com/ggerganov/llama.cpp/blob/master/grammars/README.md>.
+
+use std::ops::{Deref, DerefMut};
+
+use anyhow::{anyhow, Result};
+
+use crate::{grammar_builder::GrammarResult, GrammarBuilder};
+
+use super::{
+    ast::{Expr, Parser},
+    compiler::{compile_rules, CompileOptions},
+};
+
+struct GbnfParser<'a>(Parser<'a>);
+
+impl<'a> Deref for GbnfParser<'a> {
+    type Target = Parser<'a>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for GbnfParser<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'a> GbnfParser<'a> {
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => {}
+                '\r' | '\n' if newlines => {}
+                '#' => {
+                    self.take_while(|c| c != '\r' && c != '\n');
+                    continue;
+                }
+                _ => break,
+            }
+            self.next();
+        }
+    }
+
+    fn is_word_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-'
+    }
+
+    fn parse_name(&mut self) -> Result<&'a str> {
+        let name = self.take_while(Self::is_word_char);
+        if name.is_empty() {
+            return Err(self.err("expecting rule name"));
+        }
+        Ok(name)
+    }
+
+    fn parse_int(&mut self) -> Result<u32> {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        if digits.is_empty() {
+            return Err(self.err("expecting integer"));
+        }
+        digits
+            .parse()
+            .map_err(|e| self.err(format!("invalid integer: {}", e)))
+    }
+
+    fn parse_char(&mut self) -> Result<char> {
+        match self.next() {
+            Some('\\') => match self.next() {
+                Some('x') => self.parse_hex(2, 2),
+                Some('u') => self.parse_hex(4, 4),
+                Some('U') => self.parse_hex(8, 8),
+                Some('t') => Ok('\t'),
+                Some('r') => Ok('\r'),
+                Some('n') => Ok('\n'),
+                Some(c @ ('\\' | '"' | '[' | ']')) => Ok(c),
+                Some(c) => Err(self.err(format!("invalid escape sequence \\{}", c))),
+                None => Err(self.err("unexpected end of input")),
+            },
+            Some(c) => Ok(c),
+            None => Err(self.err("unexpected end of input")),
+        }
+    }
+
+    fn parse_grammar(&mut self) -> Result<()> {
+        self.skip_space(true);
+        while self.peek().is_some() {
+            self.parse_rule()?;
+            self.skip_space(true);
+        }
+        Ok(())
+    }
+
+    fn parse_rule(&mut self) -> Result<()> {
+        let start = self.pos;
+        let name = self.parse_name()?;
+        self.skip_space(false);
+        self.expect("::=")?;
+        self.skip_space(true);
+        let body = self.parse_alternatives(false)?;
+        match self.peek() {
+            None | Some('\r') | Some('\n') => {}
+            Some(c) => return Err(self.err(format!("unexpected {:?}", c))),
+        }
+        self.define_rule(start, name, body)?;
+        Ok(())
+    }
+
+    fn parse_alternatives(&mut self, nested: bool) -> Result<Expr> {
+        let mut alts = vec![];
+        loop {
+            alts.push(self.parse_sequence(nested)?);
+            self.skip_space(nested);
+            if self.peek() != Some('|') {
+                break;
+            }
+            self.next();
+            self.skip_space(true);
+        }
+        Ok(Expr::alt(alts))
+    }
+
+    fn parse_sequence(&mut self, nested: bool) -> Result<Expr> {
+        let mut items = vec![];
+        while let Some(c) = self.peek() {
+            let item = match c {
+                '|' | ')' => break,
+                '\r' | '\n' if !nested => break,
+                '"' => self.parse_literal()?,
+                '[' => self.parse_char_class()?,
+                '.' => {
+                    self.next();
+                    Expr::AnyChar
+                }
+                '(' => {
+                    self.next();
+                    self.skip_space(true);
+                    let inner = self.parse_alternatives(true)?;
+                    self.expect(")")?;
+                    inner
+                }
+                _ if Self::is_word_char(c) => {
+                    let name = self.parse_name()?;
+                    self.rule_ref(name)
+                }
+                '*' | '+' | '?' | '{' => {
+                    return Err(self.err(format!("expecting item before {:?}", c)))
+                }
+                _ => return Err(self.err(format!("unexpected {:?}", c))),
+            };
+            self.skip_space(nested);
+            let item = self.parse_repetition(item)?;
+            self.skip_space(nested);
+            items.push(item);
+        }
+        Ok(Expr::seq(items))
+    }
+
+    fn parse_literal(&mut self) -> Result<Expr> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        while self.peek() != Some('"') {
+            s.push(self.parse_char()?);
+        }
+        self.next();
+        Ok(Expr::Literal(s))
+    }
+
+    fn parse_char_class(&mut self) -> Result<Expr> {
+        self.expect("[")?;
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.next();
+        }
+        let mut ranges = vec![];
+        while self.peek() != Some(']') {
+            let start = self.parse_char()?;
+            let end = if self.peek() == Some('-') && !matches!(self.peek2(), Some(']') | None) {
+                self.next();
+                let end = self.parse_char()?;
+                if start > end {
+                    return Err(self.err(format!("invalid range {:?}-{:?}", start, end)));
+                }
+                end
+            } else {
+                start
+            };
+            ranges.push((start, end));
+        }
+        self.next();
+        Ok(Expr::CharClass { negated, ranges })
+    }
+
+    fn parse_repetition(&mut self, item: Expr) -> Result<Expr> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.next();
+                self.skip_space(true);
+                let min = self.parse_int()?;
+                self.skip_space(true);
+                let max = if self.peek() == Some(',') {
+                    self.next();
+                    self.skip_space(true);
+                    if self.peek() == Some('}') {
+                        None
+                    } else {
+                        let max = self.parse_int()?;
+                        self.skip_space(true);
+                        if min > max {
+                            return Err(self.err(format!("invalid repetition {{{},{}}}", min, max)));
+                        }
+                        Some(max)
+                    }
+                } else {
+                    Some(min)
+                };
+                if self.peek() != Some('}') {
+                    return Err(self.err("expecting '}'"));
+                }
+                (min, max)
+            }
+            _ => return Ok(item),
+        };
+        self.next();
+        Ok(Expr::Repeat(Box::new(item), min, max))
+    }
+}
+
+/// Check if the grammar looks like GBNF, i.e., if the first rule
+/// (after comments) is in the `name ::= ...` form.
+pub fn is_gbnf(grammar: &str) -> bool {
+    let mut p = GbnfParser(Parser::new(grammar));
+    p.skip_space(true);
+    if p.parse_name().is_err() {
+        return false;
+    }
+    p.skip_space(false);
+    p.expect("::=").is_ok()
+}
+
+pub fn gbnf_to_llguidance(builder: GrammarBuilder, gbnf: &str) -> Result<GrammarResult> {
+    let mut parser = GbnfParser(Parser::new(gbnf));
+    parser.parse_grammar()?;
+    let root = parser
+        .lookup_rule("root")
+        .ok_or_else(|| anyhow!("no root rule found"))?;
+    compile_rules(builder, parser.0.rules, root, CompileOptions::default())
+}
@@ -0,0 +1,10 @@
+//! Frontends for EBNF-style grammar formats: GBNF used by llama.cpp
+//! and the EBNF notation of W3C specifications.
+
+mod ast;
+mod compiler;
+mod gbnf;
+mod w3c;
+
+pub use gbnf::{gbnf_to_llguidance, is_gbnf};
+pub use w3c::ebnf_to_llguidance;
@@ -0,0 +1,292 @@
+//! Frontend for the EBNF notation used in W3C specifications;
+//! see <https://www.w3.org/TR/xml/#sec-notation>.
+//!
+//! Productions can be numbered (`[1] document ::= ...`), and `/* ... */` comments
+//! as well as `[ wfc: ... ]` and `[ vc: ... ]` annotations are ignored.
+//! The first production is the start symbol.
+//!
+//! Only productions with UPPERCASE names, and the ones following `%terminals`,
+//! are compiled into lexemes. `%ignore expr` sets what is skipped between lexemes,
+//! and `%case_insensitive` makes string literals outside of terminals case-insensitive
+//! (as for keywords in SPARQL).
+
+use std::ops::{Deref, DerefMut};
+
+use anyhow::{anyhow, Result};
+
+use crate::{grammar_builder::GrammarResult, GrammarBuilder};
+
+use super::{
+    ast::{Expr, Parser},
+    compiler::{compile_rules, CompileOptions},
+};
+
+struct W3cParser<'a> {
+    parser: Parser<'a>,
+    options: CompileOptions,
+    in_terminals: bool,
+}
+
+impl<'a> Deref for W3cParser<'a> {
+    type Target = Parser<'a>;
+    fn deref(&self) -> &Self::Target {
+        &self.parser
+    }
+}
+
+impl DerefMut for W3cParser<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.parser
+    }
+}
+
+impl<'a> W3cParser<'a> {
+    fn is_annotation(&self) -> bool {
+        let Some(rest) = self.src[self.pos..].strip_prefix('[') else {
+            return false;
+        };
+        let rest = rest.trim_start();
+        ["wfc:", "vc:"].iter().any(|p| {
+            rest.get(..p.len())
+                .is_some_and(|r| r.eq_ignore_ascii_case(p))
+        })
+    }
+
+    fn skip_space(&mut self) -> Result<()> {
+        loop {
+            if self.peek().is_some_and(|c| c.is_whitespace()) {
+                self.next();
+            } else if self.starts_with("/*") {
+                match self.src[self.pos..].find("*/") {
+                    Some(end) => self.pos += end + 2,
+                    None => return Err(self.err("unterminated comment")),
+                }
+            } else if self.is_annotation() {
+                match self.src[self.pos..].find(']') {
+                    Some(end) => self.pos += end + 1,
+                    None => return Err(self.err("unterminated annotation")),
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+
+    fn parse_name(&mut self) -> Result<&'a str> {
+        if !self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            return Err(self.err("expecting symbol name"));
+        }
+        Ok(self.take_while(Self::is_name_char))
+    }
+
+    /// Parse `[N] name ::=` returning the name.
+    fn parse_production_head(&mut self) -> Result<&'a str> {
+        if self.peek() == Some('[') {
+            self.next();
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.err("expecting production number"));
+            }
+            self.take_while(|c| c.is_ascii_alphanumeric());
+            self.expect("]")?;
+            self.skip_space()?;
+        }
+        let name = self.parse_name()?;
+        self.skip_space()?;
+        self.expect("::=")?;
+        Ok(name)
+    }
+
+    fn at_production_head(&mut self) -> bool {
+        let pos = self.pos;
+        let r = self.parse_production_head().is_ok();
+        self.pos = pos;
+        r
+    }
+
+    fn parse_directive(&mut self) -> Result<()> {
+        let start = self.pos;
+        self.expect("%")?;
+        match self.take_while(Self::is_name_char) {
+            "ignore" => {
+                self.skip_space()?;
+                let expr = self.parse_alternatives()?;
+                self.options.ignore.push(expr);
+            }
+            "terminals" => self.in_terminals = true,
+            "case_insensitive" => self.options.case_insensitive = true,
+            name => {
+                self.pos = start;
+                return Err(self.err(format!("unknown directive %{}", name)));
+            }
+        }
+        self.skip_space()
+    }
+
+    fn is_terminal_name(name: &str) -> bool {
+        name.chars().any(|c| c.is_ascii_uppercase()) && !name.chars().any(|c| c.is_lowercase())
+    }
+
+    fn parse_grammar(&mut self) -> Result<Option<usize>> {
+        let mut start_rule = None;
+        let mut terminals = vec![];
+        self.skip_space()?;
+        while self.peek().is_some() {
+            if self.peek() == Some('%') {
+                self.parse_directive()?;
+                continue;
+            }
+            let start = self.pos;
+            let name = self.parse_production_head()?;
+            self.skip_space()?;
+            let body = self.parse_alternatives()?;
+            if self.peek() == Some(')') {
+                return Err(self.err("unexpected ')'"));
+            }
+            let id = self.define_rule(start, name, body)?;
+            start_rule = start_rule.or(Some(id));
+            if self.in_terminals || Self::is_terminal_name(name) {
+                terminals.push(id);
+            }
+        }
+        let mut is_terminal = vec![false; self.rules.len()];
+        for id in terminals {
+            is_terminal[id] = true;
+        }
+        self.options.terminals = Some(is_terminal);
+        Ok(start_rule)
+    }
+
+    fn parse_alternatives(&mut self) -> Result<Expr> {
+        let mut alts = vec![];
+        loop {
+            alts.push(self.parse_sequence()?);
+            if self.peek() != Some('|') {
+                break;
+            }
+            self.next();
+        }
+        Ok(Expr::alt(alts))
+    }
+
+    fn parse_sequence(&mut self) -> Result<Expr> {
+        let mut items = vec![];
+        loop {
+            self.skip_space()?;
+            match self.peek() {
+                None | Some('|') | Some(')') | Some('%') => break,
+                _ if self.at_production_head() => break,
+                _ => items.push(self.parse_difference()?),
+            }
+        }
+        Ok(Expr::seq(items))
+    }
+
+    fn parse_difference(&mut self) -> Result<Expr> {
+        let mut r = self.parse_postfix()?;
+        loop {
+            self.skip_space()?;
+            if self.peek() != Some('-') {
+                return Ok(r);
+            }
+            self.next();
+            self.skip_space()?;
+            let b = self.parse_postfix()?;
+            r = Expr::Diff(Box::new(r), Box::new(b));
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr> {
+        let item = self.parse_primary()?;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            _ => return Ok(item),
+        };
+        self.next();
+        Ok(Expr::Repeat(Box::new(item), min, max))
+    }
+
+    fn parse_code_point(&mut self) -> Result<char> {
+        self.expect("#x")?;
+        self.parse_hex(1, 8)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some('(') => {
+                self.next();
+                let inner = self.parse_alternatives()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(q @ ('"' | '\'')) => {
+                self.next();
+                let s = self.take_while(|c| c != q);
+                self.expect(&q.to_string())?;
+                Ok(Expr::Literal(s.to_string()))
+            }
+            Some('#') => Ok(Expr::Literal(self.parse_code_point()?.to_string())),
+            Some('[') => self.parse_char_class(),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name = self.parse_name()?;
+                Ok(self.rule_ref(name))
+            }
+            Some(c) => Err(self.err(format!("unexpected {:?}", c))),
+            None => Err(self.err("unexpected end of input")),
+        }
+    }
+
+    fn parse_class_char(&mut self) -> Result<char> {
+        if self.starts_with("#x") {
+            self.parse_code_point()
+        } else {
+            self.next()
+                .ok_or_else(|| self.err("unterminated character class"))
+        }
+    }
+
+    fn parse_char_class(&mut self) -> Result<Expr> {
+        self.expect("[")?;
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.next();
+        }
+        let mut ranges = vec![];
+        while self.peek() != Some(']') {
+            let start = self.parse_class_char()?;
+            let end = if self.peek() == Some('-') && !matches!(self.peek2(), Some(']') | None) {
+                self.next();
+                let end = self.parse_class_char()?;
+                if start > end {
+                    return Err(self.err(format!("invalid range {:?}-{:?}", start, end)));
+                }
+                end
+            } else {
+                start
+            };
+            ranges.push((start, end));
+        }
+        self.next();
+        Ok(Expr::CharClass { negated, ranges })
+    }
+}
+
+pub fn ebnf_to_llguidance(builder: GrammarBuilder, ebnf: &str) -> Result<GrammarResult> {
+    let mut parser = W3cParser {
+        parser: Parser::new(ebnf),
+        options: CompileOptions::default(),
+        in_terminals: false,
+    };
+    let start = parser
+        .parse_grammar()?
+        .ok_or_else(|| anyhow!("no productions found"))?;
+    compile_rules(builder, parser.parser.rules, start, parser.options)
+}
@@ -6,7 +6,8 @@ use toktrie::{InferenceCapabilities, TokEnv};
 use crate::{
     api::{GrammarInit, ParserLimits, TopLevelGrammar},
     earley::{SlicedBiasComputer, XorShift},
-    Logger, TokenParser,
+    estimate_cost, CostEstimate, CostEstimateOptions, ImportResolver, InMemoryImportResolver,
+    Instant, Logger, TokenParser,
 };
 
 pub struct ParserFactory {
@@ -17,6 +18,7 @@ pub struct ParserFactory {
     buffer_log_level: u32,
     limits: ParserLimits,
     seed: Mutex<XorShift>,
+    import_resolver: Arc<dyn ImportResolver>,
 }
 
 impl ParserFactory {
@@ -34,6 +36,7 @@ impl ParserFactory {
             buffer_log_level: 0,
             seed: Mutex::new(XorShift::default()),
             limits: ParserLimits::default(),
+            import_resolver: Arc::new(InMemoryImportResolver::new()),
         })
     }
 
@@ -49,6 +52,17 @@ impl ParserFactory {
         &self.tok_env
     }
 
+    /// Set the resolver used for `%import` statements in Lark grammars.
+    /// By default, an empty [`InMemoryImportResolver`] is used.
+    pub fn set_import_resolver(&mut self, resolver: Arc<dyn ImportResolver>) -> &mut Self {
+        self.import_resolver = resolver;
+        self
+    }
+
+    pub fn import_resolver(&self) -> Arc<dyn ImportResolver> {
+        self.import_resolver.clone()
+    }
+
     pub fn quiet(&mut self) -> &mut Self {
         self.stderr_log_level = 0;
         self.buffer_log_level = 0;
@@ -93,6 +107,24 @@ impl ParserFactory {
         self.create_parser_ext2(grammar, self.buffer_log_level, self.stderr_log_level)
     }
 
+    /// Compile `grammar` and generate a few random outputs, to see how expensive
+    /// it would be to serve, before actually serving it.
+    /// Fails only if the grammar doesn't compile; errors while sampling
+    /// (e.g., `ParserTooComplex`) are listed in `CostEstimate::errors`.
+    pub fn estimate_cost(
+        &self,
+        grammar: TopLevelGrammar,
+        options: &CostEstimateOptions,
+    ) -> Result<CostEstimate> {
+        let t0 = Instant::now();
+        let parser = self.create_parser_ext2(grammar, 0, 0)?;
+        let compile_time_us = t0.elapsed().as_micros() as u64;
+        Ok(CostEstimate {
+            compile_time_us,
+            ..estimate_cost(&parser, options)
+        })
+    }
+
     pub fn create_parser_ext(
         &self,
         grammar: TopLevelGrammar,
@@ -124,13 +156,14 @@ impl ParserFactory {
         buffer_log_level: u32,
         stderr_log_level: u32,
     ) -> Result<TokenParser> {
-        let mut parser = TokenParser::from_init(
+        let mut parser = TokenParser::from_init_with_resolver(
             self.tok_env.clone(),
             init,
             Logger::new(buffer_log_level, stderr_log_level),
             self.inference_caps.clone(),
             self.limits.clone(),
             self.extra_lexemes(),
+            self.import_resolver.clone(),
         )?;
         self.post_process_parser(&mut parser);
         Ok(parser)
@@ -9,7 +9,9 @@ use toktrie::{InferenceCapabilities, TokEnv, TokRxInfo, TokTrie, TokenizerEnv};
 
 use crate::{
     api::{ParserLimits, TopLevelGrammar},
-    CommitResult, Constraint, Logger, ParserFactory, StopController, TokenParser,
+    json::json_merge,
+    CommitResult, Constraint, GrammarMatcher, Logger, MatchStatus, ParserFactory, StopController,
+    TokenParser,
 };
 
 struct CTokenizerInner {
@@ -290,6 +292,24 @@ pub struct LlgStopController {
     last_result: String,
 }
 
+pub struct LlgMatcher {
+    matcher: GrammarMatcher,
+    last_status: String,
+}
+
+impl LlgMatcher {
+    fn set_status(&mut self, status: MatchStatus) -> i32 {
+        let r = match status {
+            MatchStatus::Accept => 1,
+            MatchStatus::Prefix => 0,
+            MatchStatus::Reject { .. } => -1,
+        };
+        self.last_status = serde_json::to_string(&status).unwrap();
+        self.last_status.push('\0');
+        r
+    }
+}
+
 impl Clone for LlgConstraint {
     fn clone(&self) -> Self {
         LlgConstraint {
@@ -369,9 +389,34 @@ fn new_constraint_lark(init: &LlgConstraintInit, lark: *const c_char) -> Result<
 }
 
 fn new_constraint_json(init: &LlgConstraintInit, json_schema: *const c_char) -> Result<Constraint> {
+    new_constraint_json_with_formats(init, json_schema, std::ptr::null())
+}
+
+fn new_constraint_json_with_formats(
+    init: &LlgConstraintInit,
+    json_schema: *const c_char,
+    custom_formats: *const c_char,
+) -> Result<Constraint> {
     let json_schema = unsafe { c_str_to_str(json_schema, "json_schema") }?;
-    let json_schema = serde_json::from_str(json_schema)
+    let mut json_schema: serde_json::Value = serde_json::from_str(json_schema)
         .map_err(|e| anyhow::anyhow!("Invalid JSON in json_schema: {e}"))?;
+    if !custom_formats.is_null() {
+        let custom_formats = unsafe { c_str_to_str(custom_formats, "custom_formats") }?;
+        let custom_formats: serde_json::Value = serde_json::from_str(custom_formats)
+            .map_err(|e| anyhow::anyhow!("Invalid JSON in custom_formats: {e}"))?;
+        ensure!(
+            custom_formats.is_object(),
+            "custom_formats must be a JSON object"
+        );
+        ensure!(
+            json_schema.is_object(),
+            "custom_formats require json_schema to be an object"
+        );
+        json_merge(
+            &mut json_schema,
+            &serde_json::json!({ "x-guidance": { "custom_formats": custom_formats } }),
+        );
+    }
     let grammar = TopLevelGrammar::from_json_schema(json_schema);
     init.build_constraint(grammar)
 }
@@ -383,19 +428,31 @@ fn new_constraint(init: &LlgConstraintInit, grammar_json: *const c_char) -> Resu
     init.build_constraint(grammar)
 }
 
+fn grammar_from_type(tp: &str, data: &str) -> Result<TopLevelGrammar> {
+    let grammar = match tp {
+        "regex" => TopLevelGrammar::from_regex(data),
+        "json" | "json_schema" => TopLevelGrammar::from_json_schema(
+            serde_json::from_str(data)
+                .map_err(|e| anyhow::anyhow!("Invalid JSON in json_schema: {e}"))?,
+        ),
+        "lark" => TopLevelGrammar::from_lark(data.to_string()),
+        "gbnf" => TopLevelGrammar::from_gbnf(data.to_string()),
+        "ebnf" => TopLevelGrammar::from_ebnf(data.to_string()),
+        "llguidance" | "guidance" => serde_json::from_str(data)
+            .map_err(|e| anyhow::anyhow!("Invalid JSON in grammar_json: {e}"))?,
+        _ => bail!("unknown constraint type: {tp}"),
+    };
+    Ok(grammar)
+}
+
 fn new_constraint_any(
     init: &LlgConstraintInit,
     constraint_type: *const c_char,
     data: *const c_char,
 ) -> Result<Constraint> {
     let tp = unsafe { c_str_to_str(constraint_type, "constraint_type") }?;
-    match tp {
-        "regex" => new_constraint_regex(init, data),
-        "json" | "json_schema" => new_constraint_json(init, data),
-        "lark" => new_constraint_lark(init, data),
-        "llguidance" | "guidance" => new_constraint(init, data),
-        _ => bail!("unknown constraint type: {tp}"),
-    }
+    let data = unsafe { c_str_to_str(data, tp) }?;
+    init.build_constraint(grammar_from_type(tp, data)?)
 }
 
 impl LlgConstraint {
@@ -480,6 +537,24 @@ pub extern "C" fn llg_new_constraint_json(
     constraint_to_llg(new_constraint_json(init, json_schema))
 }
 
+/// Create a new constraint from a given JSON schema, with additional string "format"s.
+/// custom_formats is a JSON object mapping format names to regexes, for example
+/// {"sku": "[A-Z]{3}-[0-9]{4}"}; these take precedence over built-in formats.
+/// It can be NULL, in which case this is the same as llg_new_constraint_json().
+/// Always returns a non-null value. Call llg_get_error() on the result to check for errors.
+#[no_mangle]
+pub extern "C" fn llg_new_constraint_json_with_formats(
+    init: &LlgConstraintInit,
+    json_schema: *const c_char,
+    custom_formats: *const c_char,
+) -> *mut LlgConstraint {
+    constraint_to_llg(new_constraint_json_with_formats(
+        init,
+        json_schema,
+        custom_formats,
+    ))
+}
+
 /// Create a new constraint from a given lark grammar
 /// Always returns a non-null value. Call llg_get_error() on the result to check for errors.
 #[no_mangle]
@@ -491,7 +566,7 @@ pub extern "C" fn llg_new_constraint_lark(
 }
 
 /// Create a new constraint with specified type
-/// Type can be one of "regex", "json_schema" (or "json"), "lark", "llguidance" (or "guidance")
+/// Type can be one of "regex", "json_schema" (or "json"), "lark", "gbnf", "ebnf", "llguidance" (or "guidance")
 /// Always returns a non-null value. Call llg_get_error() on the result to check for errors.
 #[no_mangle]
 pub extern "C" fn llg_new_constraint_any(
@@ -833,3 +908,84 @@ pub unsafe extern "C" fn llg_free_stop_controller(stop_ctrl: *mut LlgStopControl
         drop(Box::from_raw(stop_ctrl));
     }
 }
+
+fn matcher_grammar(constraint_type: *const c_char, data: *const c_char) -> Result<TopLevelGrammar> {
+    let tp = unsafe { c_str_to_str(constraint_type, "constraint_type") }?;
+    let data = unsafe { c_str_to_str(data, tp) }?;
+    grammar_from_type(tp, data)
+}
+
+/// Create a new byte-level matcher, which checks text against a grammar without a tokenizer.
+/// Type can be one of "regex", "json_schema" (or "json"), "lark", "gbnf", "ebnf", "llguidance" (or "guidance")
+/// Returns NULL on error, in which case the error message is written to error_string.
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_new_matcher(
+    constraint_type: *const c_char,
+    data: *const c_char,
+    error_string: *mut c_char,
+    error_string_len: usize,
+) -> *mut LlgMatcher {
+    match matcher_grammar(constraint_type, data).and_then(GrammarMatcher::new) {
+        Ok(matcher) => {
+            let mut m = LlgMatcher {
+                matcher,
+                last_status: String::new(),
+            };
+            let status = m.matcher.status();
+            m.set_status(status);
+            Box::into_raw(Box::new(m))
+        }
+        Err(e) => {
+            save_error_string(e, error_string, error_string_len);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Feed bytes (not necessarily valid UTF-8) to the matcher.
+/// Returns 1 if the bytes so far match the grammar, 0 if they are a valid prefix of a match,
+/// and -1 if they were rejected (now or before).
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_matcher_push(
+    matcher: &mut LlgMatcher,
+    bytes: *const u8,
+    bytes_len: usize,
+) -> i32 {
+    let bytes = if bytes_len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(bytes, bytes_len) }
+    };
+    let status = matcher.matcher.push(bytes);
+    matcher.set_status(status)
+}
+
+/// Signal end of input to the matcher.
+/// Returns 1 if the input matches the grammar, and -1 otherwise.
+#[no_mangle]
+pub extern "C" fn llg_matcher_finish(matcher: &mut LlgMatcher) -> i32 {
+    let status = matcher.matcher.finish();
+    matcher.set_status(status)
+}
+
+/// Get the JSON status from the last call to llg_matcher_push() or llg_matcher_finish(), for example:
+/// {"status":"reject","offset":3,"expected":["'+'"],"message":"unexpected \"x\""}
+/// The string is valid until the next call to one of these functions, or until the matcher is freed.
+#[no_mangle]
+pub extern "C" fn llg_matcher_status(matcher: &LlgMatcher) -> *const c_char {
+    matcher.last_status.as_ptr() as *const c_char
+}
+
+/// Free the matcher
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_free_matcher(matcher: *mut LlgMatcher) {
+    unsafe {
+        drop(Box::from_raw(matcher));
+    }
+}
@@ -1,17 +1,17 @@
 use crate::{
     api::{LLGuidanceOptions, ParserLimits},
     earley::{
-        lexerspec::{token_ranges_to_string, LexemeClass, LexemeIdx, LexerSpec},
+        lexerspec::{token_ranges_to_string, LexemeClass, LexemeIdx, LexerSpec, RegexSource},
         Grammar, SymIdx, SymbolProps,
     },
-    HashMap,
+    HashMap, ImportResolver, InMemoryImportResolver,
 };
 use anyhow::{anyhow, bail, ensure, Result};
 use derivre::{ExprRef, RegexAst};
-use std::ops::RangeInclusive;
+use std::{ops::RangeInclusive, sync::Arc};
 use toktrie::{bytes::limit_str, TokEnv};
 
-use crate::api::{GenGrammarOptions, GenOptions, NodeProps};
+use crate::api::{GenGrammarOptions, GenOptions, GrammarLint, GrammarWarning, NodeProps};
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub struct NodeRef {
@@ -35,6 +35,7 @@ pub struct GrammarBuilder {
     pub regex: RegexBuilder,
     tok_env: Option<TokEnv>,
     limits: ParserLimits,
+    import_resolver: Arc<dyn ImportResolver>,
 
     strings: HashMap<String, NodeRef>,
     at_most_cache: HashMap<(NodeRef, usize), NodeRef>,
@@ -46,6 +47,21 @@ pub struct GrammarResult {
     pub start_node: SymIdx,
 }
 
+impl GrammarResult {
+    /// Render the (optimized) grammar in Lark syntax; see [`crate::earley::CGrammar::to_lark`].
+    pub fn to_lark(&self) -> Result<String> {
+        ensure!(
+            self.start_node == self.builder.grammar.start(),
+            "only the first grammar of a builder can be rendered as Lark"
+        );
+        self.builder
+            .grammar
+            .optimize()
+            .compile(self.builder.regex.spec.clone())
+            .to_lark()
+    }
+}
+
 pub struct RegexBuilder {
     pub(crate) spec: LexerSpec,
 }
@@ -70,11 +86,16 @@ impl RegexBuilder {
     }
 
     pub fn add_ast(&mut self, ast: RegexAst) -> Result<RegexId> {
-        self.spec.regex_builder.mk(&ast)
+        let id = self.spec.regex_builder.mk(&ast)?;
+        self.spec.add_regex_source(id, RegexSource::Ast(ast));
+        Ok(id)
     }
 
     pub fn regex(&mut self, rx: &str) -> Result<RegexId> {
-        self.spec.regex_builder.mk_regex(rx)
+        let id = self.spec.regex_builder.mk_regex(rx)?;
+        self.spec
+            .add_regex_source(id, RegexSource::Ast(RegexAst::Regex(rx.to_string())));
+        Ok(id)
     }
 
     pub fn literal(&mut self, s: String) -> RegexId {
@@ -148,9 +169,38 @@ impl GrammarBuilder {
             repeat_exact_cache: HashMap::default(),
             limits,
             tok_env,
+            import_resolver: Arc::new(InMemoryImportResolver::new()),
         }
     }
 
+    /// Keep the regex ASTs the grammar is built from, so that it can be rendered
+    /// with [`GrammarResult::to_lark`]; has to be called before any regexes are added.
+    pub fn record_regex_sources(&mut self) {
+        self.regex.spec.record_regex_sources = true;
+    }
+
+    /// Set the resolver used for `%import` statements in Lark grammars.
+    pub fn set_import_resolver(&mut self, resolver: Arc<dyn ImportResolver>) {
+        self.import_resolver = resolver;
+    }
+
+    pub fn import_resolver(&self) -> Arc<dyn ImportResolver> {
+        self.import_resolver.clone()
+    }
+
+    pub fn add_warning(&mut self, warning: GrammarWarning) {
+        self.grammar.add_warning(warning);
+    }
+
+    pub fn add_lint(&mut self, lint: GrammarLint) {
+        self.grammar.add_lint(lint);
+    }
+
+    /// Name `node` after rule `name` of the source grammar, for lints.
+    pub fn set_source_name(&mut self, node: NodeRef, name: &str) {
+        self.grammar.set_source_name(node.idx, name);
+    }
+
     pub fn check_limits(&self) -> Result<()> {
         ensure!(
             self.regex.spec.cost() <= self.limits.initial_lexer_fuel,
@@ -186,6 +236,9 @@ impl GrammarBuilder {
         if options.no_forcing {
             self.regex.spec.no_forcing = true;
         }
+        if options.json_captures {
+            self.regex.spec.json_captures = true;
+        }
 
         // add root node
         self.curr_start_idx = self.new_node("start");
@@ -224,6 +277,7 @@ impl GrammarBuilder {
                     false,
                     None,
                     usize::MAX,
+                    0,
                 )
                 .unwrap();
             self.lexeme_to_node(lx_id)
@@ -326,6 +380,17 @@ impl GrammarBuilder {
         rx: ExprRef,
         temperature: Option<f32>,
         props: NodeProps,
+    ) -> NodeRef {
+        self.lexeme_ext_with_priority(rx, temperature, 0, props)
+    }
+
+    /// When several lexemes match the same bytes, the ones with higher `priority` are preferred.
+    pub fn lexeme_ext_with_priority(
+        &mut self,
+        rx: ExprRef,
+        temperature: Option<f32>,
+        priority: i32,
+        props: NodeProps,
     ) -> NodeRef {
         let idx = self
             .regex
@@ -339,6 +404,7 @@ impl GrammarBuilder {
                 false,
                 None,
                 props.max_tokens.unwrap_or(usize::MAX),
+                priority,
             )
             .unwrap();
         let r = self.lexeme_to_node(idx);
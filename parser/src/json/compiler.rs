@@ -1,14 +1,16 @@
-use crate::api::LLGuidanceOptions;
+use crate::api::{GrammarWarning, LLGuidanceOptions};
 use crate::grammar_builder::GrammarResult;
-use crate::HashMap;
-use anyhow::{anyhow, Context, Result};
+use crate::{HashMap, HashSet};
+use anyhow::{anyhow, bail, Context, Result};
 use derivre::{JsonQuoteOptions, RegexAst};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use super::numeric::{check_number_bounds, rx_float_range, rx_int_range, Decimal};
-use super::schema::{build_schema, Schema};
+use super::numeric::{
+    check_number_bounds, lower_bound, rx_float_range, rx_int_bounds, upper_bound, Decimal,
+};
+use super::schema::{build_schema, pattern_to_regex, ArrayContains, Schema, SchemaBuilderOptions};
 use super::RetrieveWrapper;
 
 use crate::{GrammarBuilder, NodeRef};
@@ -17,17 +19,60 @@ use crate::{GrammarBuilder, NodeRef};
 // TODO: array maxItems etc limits
 // TODO: schemastore/src/schemas/json/BizTalkServerApplicationSchema.json - this breaks 1M fuel on lexer, why?!
 
+/// Order of properties in generated objects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PropertyOrder {
+    /// As listed in `properties` (followed by other required ones).
+    #[default]
+    Declared,
+    /// Required properties first, then the optional ones, otherwise as declared.
+    RequiredFirst,
+    /// Any order, each property at most once; only for objects with up to 8 properties,
+    /// falls back to `Declared` otherwise.
+    AnyOrder,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct JsonCompileOptions {
     pub item_separator: String,
-    pub key_separator: String,
+    /// Defaults to `":"`, or to `": "` when `indent` is set (like in Python's `json.dumps()`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_separator: Option<String>,
     pub whitespace_flexible: bool,
+    /// Pretty-print with this many spaces of indentation per nesting level
+    /// (like Python's `json.dumps(indent=...)`); overrides `whitespace_flexible`.
+    /// `item_separator` is followed by a newline and indentation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indent: Option<usize>,
     pub coerce_one_of: bool,
+    #[serde(skip_serializing_if = "is_declared")]
+    pub property_order: PropertyOrder,
+    /// Additional string "format"s, mapping format name to a regex
+    /// (which always has to match the whole string).
+    /// These take precedence over built-in formats.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub custom_formats: HashMap<String, String>,
+    /// Drop unsupported keywords instead of failing; each dropped keyword
+    /// is reported as a warning (see [`crate::TokenParser::warnings`]).
+    pub lenient: bool,
     #[serde(skip)]
     pub retriever: Option<RetrieveWrapper>,
 }
 
+fn is_declared(order: &PropertyOrder) -> bool {
+    *order == PropertyOrder::Declared
+}
+
+/// How JSON strings are quoted; also used by `%regex { "json_string": ... }` in Lark.
+pub(crate) fn json_quote_options() -> JsonQuoteOptions {
+    JsonQuoteOptions {
+        allowed_escapes: "nrbtf\\\"u".to_string(),
+        raw_mode: false,
+    }
+}
+
 fn json_dumps(target: &serde_json::Value) -> String {
     serde_json::to_string(target).unwrap()
 }
@@ -43,15 +88,18 @@ impl std::fmt::Display for UnsatisfiableSchemaError {
     }
 }
 
-const CHAR_REGEX: &str = r#"(\\([\"\\\/bfnrt]|u[a-fA-F0-9]{4})|[^\"\\\x00-\x1F\x7F])"#;
+pub(crate) const CHAR_REGEX: &str = r#"(\\([\"\\\/bfnrt]|u[a-fA-F0-9]{4})|[^\"\\\x00-\x1F\x7F])"#;
 
 struct Compiler {
     builder: GrammarBuilder,
     options: JsonCompileOptions,
-    definitions: HashMap<String, NodeRef>,
-    pending_definitions: Vec<(String, NodeRef)>,
+    // definitions and 'any' are generated separately for each nesting level when indenting
+    definitions: HashMap<(String, usize), NodeRef>,
+    pending_definitions: Vec<(String, usize, NodeRef)>,
+    depth: usize,
+    indent_capped: bool,
 
-    any_cache: Option<NodeRef>,
+    any_cache: HashMap<usize, NodeRef>,
     string_cache: Option<NodeRef>,
 }
 
@@ -68,9 +116,13 @@ impl Default for JsonCompileOptions {
     fn default() -> Self {
         Self {
             item_separator: ",".to_string(),
-            key_separator: ":".to_string(),
+            key_separator: None,
             whitespace_flexible: true,
+            indent: None,
             coerce_one_of: false,
+            property_order: PropertyOrder::Declared,
+            custom_formats: HashMap::default(),
+            lenient: false,
             retriever: None,
         }
     }
@@ -97,6 +149,14 @@ impl JsonCompileOptions {
         compiler.execute(schema)
     }
 
+    pub fn key_separator(&self) -> &str {
+        match &self.key_separator {
+            Some(sep) => sep,
+            None if self.indent.is_some() => ": ",
+            None => ":",
+        }
+    }
+
     pub fn apply_to(&self, schema: &mut Value) {
         schema.as_object_mut().unwrap().insert(
             "x-guidance".to_string(),
@@ -112,13 +172,15 @@ impl Compiler {
             options,
             definitions: HashMap::default(),
             pending_definitions: vec![],
-            any_cache: None,
+            depth: 0,
+            indent_capped: false,
+            any_cache: HashMap::default(),
             string_cache: None,
         }
     }
 
     pub fn execute(mut self, schema: Value) -> Result<GrammarResult> {
-        let skip = if self.options.whitespace_flexible {
+        let skip = if self.options.whitespace_flexible && self.options.indent.is_none() {
             RegexAst::Regex(r"[\x20\x0A\x0D\x09]+".to_string())
         } else {
             RegexAst::NoMatch
@@ -127,15 +189,29 @@ impl Compiler {
             .builder
             .add_grammar(LLGuidanceOptions::default(), skip)?;
 
-        let (compiled_schema, definitions) = build_schema(schema, self.options.retriever.clone())?;
+        self.builder.grammar.add_json_schema(id, schema.clone());
+
+        let (compiled_schema, definitions, warnings) = build_schema(
+            schema,
+            self.options.retriever.clone(),
+            SchemaBuilderOptions {
+                custom_formats: self.options.custom_formats.clone(),
+                lenient: self.options.lenient,
+                ..SchemaBuilderOptions::default()
+            },
+        )?;
+        for warning in warnings {
+            self.builder.add_warning(warning);
+        }
 
         let root = self.gen_json(&compiled_schema)?;
         self.builder.set_start_node(root);
 
-        while let Some((path, pl)) = self.pending_definitions.pop() {
+        while let Some((path, depth, pl)) = self.pending_definitions.pop() {
             let schema = definitions
                 .get(&path)
                 .ok_or_else(|| anyhow!("Definition not found: {}", path))?;
+            self.depth = depth;
             let compiled = self.gen_json(schema)?;
             self.builder.set_placeholder(pl, compiled);
         }
@@ -147,6 +223,13 @@ impl Compiler {
         if let Some(ast) = self.regex_compile(json_schema)? {
             return self.ast_lexeme(ast);
         }
+        if self.options.indent.is_some() && self.depth > MAX_INDENT_DEPTH && !self.indent_capped {
+            self.indent_capped = true;
+            self.builder.add_warning(GrammarWarning::new(format!(
+                "arrays and objects nested deeper than {} levels are not indented",
+                MAX_INDENT_DEPTH
+            )));
+        }
         match json_schema {
             Schema::Any => Ok(self.gen_json_any()),
             Schema::Unsatisfiable { reason } => Err(anyhow!(UnsatisfiableSchemaError {
@@ -158,21 +241,37 @@ impl Compiler {
                 max_items,
                 prefix_items,
                 items,
-            } => self.gen_json_array(
-                prefix_items,
-                items.as_deref().unwrap_or(&Schema::Any),
-                *min_items,
-                *max_items,
-            ),
+                contains,
+                unique_items,
+            } => self.nested(|c| {
+                c.gen_json_array(
+                    prefix_items,
+                    items.as_deref().unwrap_or(&Schema::Any),
+                    *min_items,
+                    *max_items,
+                    contains.as_deref(),
+                    *unique_items,
+                )
+            }),
             Schema::Object {
                 properties,
+                pattern_properties,
                 additional_properties,
+                property_names,
+                min_properties,
+                max_properties,
                 required,
-            } => self.gen_json_object(
-                properties,
-                additional_properties.as_deref().unwrap_or(&Schema::Any),
-                required.iter().cloned().collect(),
-            ),
+            } => self.nested(|c| {
+                c.gen_json_object(
+                    properties,
+                    pattern_properties,
+                    additional_properties.as_deref().unwrap_or(&Schema::Any),
+                    property_names.as_deref(),
+                    *min_properties,
+                    *max_properties,
+                    required.iter().cloned().collect(),
+                )
+            }),
 
             Schema::AnyOf { options } => self.process_any_of(options),
             Schema::OneOf { options } => self.process_one_of(options),
@@ -266,36 +365,7 @@ impl Compiler {
                 message: e.to_string(),
             })
         })?;
-        let minimum = match (minimum, exclusive_minimum) {
-            (Some(min_val), true) => {
-                if min_val.fract() != 0.0 {
-                    Some(min_val.ceil())
-                } else {
-                    Some(min_val + 1.0)
-                }
-            }
-            (Some(min_val), false) => Some(min_val.ceil()),
-            _ => None,
-        }
-        .map(|val| val as i64);
-        let maximum = match (maximum, exclusive_maximum) {
-            (Some(max_val), true) => {
-                if max_val.fract() != 0.0 {
-                    Some(max_val.floor())
-                } else {
-                    Some(max_val - 1.0)
-                }
-            }
-            (Some(max_val), false) => Some(max_val.floor()),
-            _ => None,
-        }
-        .map(|val| val as i64);
-        let rx = rx_int_range(minimum, maximum).with_context(|| {
-            format!(
-                "Failed to generate regex for integer range: min={:?}, max={:?}",
-                minimum, maximum
-            )
-        })?;
+        let rx = rx_int_bounds(minimum, maximum, exclusive_minimum, exclusive_maximum)?;
         let mut ast = RegexAst::Regex(rx);
         if let Some(d) = multiple_of {
             ast = RegexAst::And(vec![ast, RegexAst::MultipleOf(d.coef, d.exp)]);
@@ -350,43 +420,134 @@ impl Compiler {
         })
     }
 
+    /// Nesting level that the generated grammar depends on;
+    /// levels past MAX_INDENT_DEPTH are all the same, as they are not indented.
+    fn indent_depth(&self) -> usize {
+        if self.options.indent.is_some() {
+            self.depth.min(MAX_INDENT_DEPTH + 1)
+        } else {
+            0
+        }
+    }
+
+    /// Indentation of the current array or object, if any.
+    fn indent(&self) -> Option<usize> {
+        self.options
+            .indent
+            .filter(|_| self.depth <= MAX_INDENT_DEPTH)
+    }
+
+    /// Run `f` to generate contents of an array or object.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.depth += 1;
+        let r = f(self);
+        self.depth -= 1;
+        r
+    }
+
+    /// Separator placed before an array item or object property;
+    /// 'prefixed' is set when it follows another one.
+    fn item_separator(&mut self, prefixed: bool) -> NodeRef {
+        match self.indent() {
+            None if prefixed => self.builder.string(&self.options.item_separator),
+            None => self.builder.empty(),
+            Some(indent) => {
+                let newline = format!("\n{}", " ".repeat(indent * self.depth));
+                if prefixed {
+                    let sep = format!("{}{}", self.options.item_separator, newline);
+                    self.builder.string(&sep)
+                } else {
+                    self.builder.string(&newline)
+                }
+            }
+        }
+    }
+
+    /// What goes before the closing bracket of an array or object;
+    /// 'prefixed' is set when it's not empty.
+    fn items_end(&mut self, prefixed: bool) -> NodeRef {
+        match self.indent() {
+            Some(indent) if prefixed => {
+                let newline = format!("\n{}", " ".repeat(indent * (self.depth - 1)));
+                self.builder.string(&newline)
+            }
+            _ => self.builder.empty(),
+        }
+    }
+
     fn get_definition(&mut self, reference: &str) -> Result<NodeRef> {
-        if let Some(definition) = self.definitions.get(reference) {
+        let key = (reference.to_string(), self.indent_depth());
+        if let Some(definition) = self.definitions.get(&key) {
             return Ok(*definition);
         }
         let r = self.builder.new_node(reference);
-        self.definitions.insert(reference.to_string(), r);
-        self.pending_definitions.push((reference.to_string(), r));
+        self.definitions.insert(key, r);
+        self.pending_definitions
+            .push((reference.to_string(), self.depth, r));
         Ok(r)
     }
 
     fn gen_json_any(&mut self) -> NodeRef {
-        cache!(self.any_cache, {
-            let json_any = self.builder.new_node("json_any");
-            self.any_cache = Some(json_any); // avoid infinite recursion
-            let num = self.json_number(None, None, false, false, None).unwrap();
-            let tf = self.builder.regex.regex("true|false").unwrap();
-            let options = vec![
-                self.builder.string("null"),
-                self.builder.lexeme(tf),
-                self.ast_lexeme(num).unwrap(),
-                self.json_simple_string(),
-                self.gen_json_array(&[], &Schema::Any, 0, None).unwrap(),
-                self.gen_json_object(&IndexMap::new(), &Schema::Any, vec![])
-                    .unwrap(),
-            ];
-            let inner = self.builder.select(&options);
-            self.builder.set_placeholder(json_any, inner);
-            json_any
-        })
+        let depth = self.indent_depth();
+        if let Some(json_any) = self.any_cache.get(&depth) {
+            return *json_any;
+        }
+        let json_any = self.builder.new_node("json_any");
+        self.any_cache.insert(depth, json_any); // avoid infinite recursion
+        let num = self.json_number(None, None, false, false, None).unwrap();
+        let tf = self.builder.regex.regex("true|false").unwrap();
+        let options = vec![
+            self.builder.string("null"),
+            self.builder.lexeme(tf),
+            self.ast_lexeme(num).unwrap(),
+            self.json_simple_string(),
+            self.nested(|c| c.gen_json_array(&[], &Schema::Any, 0, None, None, false))
+                .unwrap(),
+            self.nested(|c| {
+                c.gen_json_object(
+                    &IndexMap::new(),
+                    &IndexMap::new(),
+                    &Schema::Any,
+                    None,
+                    0,
+                    None,
+                    vec![],
+                )
+            })
+            .unwrap(),
+        ];
+        let inner = self.builder.select(&options);
+        self.builder.set_placeholder(json_any, inner);
+        json_any
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn gen_json_object(
         &mut self,
         properties: &IndexMap<String, Schema>,
+        pattern_properties: &IndexMap<String, Schema>,
         additional_properties: &Schema,
+        property_names: Option<&Schema>,
+        min_properties: u64,
+        max_properties: Option<u64>,
         required: Vec<String>,
     ) -> Result<NodeRef> {
+        if let Some(max_properties) = max_properties {
+            if min_properties > max_properties {
+                return Err(anyhow!(UnsatisfiableSchemaError {
+                    message: format!(
+                        "minProperties ({}) is greater than maxProperties ({})",
+                        min_properties, max_properties
+                    ),
+                }));
+            }
+        }
+
+        let names_rx = match property_names {
+            Some(schema) => self.property_names_regex(schema)?,
+            None => None,
+        };
+
         let mut taken_names: Vec<String> = vec![];
         let mut items: Vec<(NodeRef, bool)> = vec![];
         for name in properties.keys().chain(
@@ -398,7 +559,15 @@ impl Compiler {
             let is_required = required.contains(name);
             // Quote (and escape) the name
             let quoted_name = json_dumps(&json!(name));
-            let property = match self.gen_json(property_schema) {
+            let property = match &names_rx {
+                Some(rx) if !regex_matches(rx, &quoted_name)? => {
+                    Err(anyhow!(UnsatisfiableSchemaError {
+                        message: format!("property name '{}' is excluded by propertyNames", name),
+                    }))
+                }
+                _ => self.gen_json(property_schema),
+            };
+            let property = match property {
                 Ok(node) => node,
                 Err(e) => match e.downcast_ref::<UnsatisfiableSchemaError>() {
                     // If it's not an UnsatisfiableSchemaError, just propagate it normally
@@ -418,11 +587,59 @@ impl Compiler {
             };
             let name = self.builder.string(&quoted_name);
             taken_names.push(quoted_name);
-            let colon = self.builder.string(&self.options.key_separator);
+            let colon = self.builder.string(self.options.key_separator());
             let item = self.builder.join(&[name, colon, property]);
             items.push((item, is_required));
         }
 
+        // constraints shared by all keys not listed in 'properties'
+        let mut key_constraints = vec![];
+        if !taken_names.is_empty() {
+            let taken = taken_names
+                .iter()
+                .map(|n| RegexAst::Literal(n.to_string()))
+                .collect();
+            key_constraints.push(RegexAst::Not(Box::new(RegexAst::Or(taken))));
+        }
+        if let Some(rx) = names_rx {
+            key_constraints.push(rx);
+        }
+
+        let pattern_rxs = pattern_properties
+            .keys()
+            .map(|pattern| self.json_quote(pattern_to_regex(pattern)))
+            .collect::<Vec<_>>();
+        let mut extra_items = vec![];
+
+        for (idx, schema) in pattern_properties.values().enumerate() {
+            match self.gen_json(schema) {
+                Err(e) => {
+                    if e.downcast_ref::<UnsatisfiableSchemaError>().is_none() {
+                        return Err(e);
+                    }
+                    // Keys matching the pattern are not allowed
+                }
+                Ok(property) => {
+                    let mut key = key_constraints.clone();
+                    key.push(pattern_rxs[idx].clone());
+                    // Keys matching several patterns would need to satisfy all of their schemas;
+                    // we don't generate them.
+                    let others = pattern_rxs
+                        .iter()
+                        .enumerate()
+                        .filter(|(other_idx, _)| *other_idx != idx)
+                        .map(|(_, rx)| rx.clone())
+                        .collect::<Vec<_>>();
+                    if !others.is_empty() {
+                        key.push(RegexAst::Not(Box::new(RegexAst::Or(others))));
+                    }
+                    let name = self.ast_lexeme(RegexAst::And(key))?;
+                    let colon = self.builder.string(self.options.key_separator());
+                    extra_items.push(self.builder.join(&[name, colon, property]));
+                }
+            }
+        }
+
         match self.gen_json(additional_properties) {
             Err(e) => {
                 if e.downcast_ref::<UnsatisfiableSchemaError>().is_none() {
@@ -432,34 +649,281 @@ impl Compiler {
                 // Ignore UnsatisfiableSchemaError for additionalProperties
             }
             Ok(property) => {
-                let name = if taken_names.is_empty() {
+                let name = if key_constraints.is_empty() && pattern_rxs.is_empty() {
                     self.json_simple_string()
                 } else {
-                    let taken_name_ids = taken_names
-                        .iter()
-                        .map(|n| self.builder.regex.literal(n.to_string()))
-                        .collect::<Vec<_>>();
-                    let taken = self.builder.regex.select(taken_name_ids);
-                    let not_taken = self.builder.regex.not(taken);
-                    let valid = self
-                        .builder
-                        .regex
-                        .regex(&format!("\"({})*\"", CHAR_REGEX))?;
-                    let valid_and_not_taken = self.builder.regex.and(vec![valid, not_taken]);
-                    self.builder.lexeme(valid_and_not_taken)
+                    let mut key = key_constraints;
+                    key.push(RegexAst::Regex(format!("\"({})*\"", CHAR_REGEX)));
+                    if !pattern_rxs.is_empty() {
+                        key.push(RegexAst::Not(Box::new(RegexAst::Or(pattern_rxs))));
+                    }
+                    self.ast_lexeme(RegexAst::And(key))?
                 };
-                let colon = self.builder.string(&self.options.key_separator);
-                let item = self.builder.join(&[name, colon, property]);
-                let seq = self.sequence(item);
-                items.push((seq, false));
+                let colon = self.builder.string(self.options.key_separator());
+                extra_items.push(self.builder.join(&[name, colon, property]));
+            }
+        }
+
+        let extra = match extra_items.len() {
+            0 => None,
+            1 => Some(extra_items[0]),
+            _ => Some(self.builder.select(&extra_items)),
+        };
+
+        let mut any_order = None;
+        match self.options.property_order {
+            PropertyOrder::Declared => {}
+            PropertyOrder::RequiredFirst => {
+                // stable, so declaration order is kept otherwise
+                items.sort_by_key(|(_, is_required)| !is_required);
+            }
+            PropertyOrder::AnyOrder if items.len() > 1 => {
+                any_order =
+                    self.any_order_sequence(&items, extra, (min_properties, max_properties))?;
+                if any_order.is_none() {
+                    self.builder.add_warning(GrammarWarning::new(format!(
+                        "any_order is only supported for objects with up to {} properties \
+                         and {} states (used properties times property counts); using declared order",
+                        MAX_ANY_ORDER_PROPERTIES, MAX_ANY_ORDER_STATES
+                    )));
+                }
             }
+            PropertyOrder::AnyOrder => {}
         }
+
+        let inner = if let Some(inner) = any_order {
+            inner
+        } else if min_properties == 0 && max_properties.is_none() {
+            if let Some(extra) = extra {
+                let seq = self.sequence(extra);
+                items.push((seq, false));
+            }
+            self.ordered_sequence(&items, false, &mut HashMap::default())
+        } else {
+            self.counted_sequence(
+                &items,
+                extra,
+                (min_properties, max_properties),
+                (0, false),
+                &mut HashMap::default(),
+            )
+            .ok_or_else(|| {
+                anyhow!(UnsatisfiableSchemaError {
+                    message: format!(
+                        "object cannot have between {} and {:?} properties",
+                        min_properties, max_properties
+                    ),
+                })
+            })?
+        };
         let opener = self.builder.string("{");
-        let inner = self.ordered_sequence(&items, false, &mut HashMap::default());
         let closer = self.builder.string("}");
         Ok(self.builder.join(&[opener, inner, closer]))
     }
 
+    /// Properties from 'items' in any order (each at most once), with 'extra' (if any)
+    /// allowed anywhere in between, so that the total is within 'bounds'.
+    /// Returns None if there are too many properties to track which ones were used.
+    fn any_order_sequence(
+        &mut self,
+        items: &[(NodeRef, bool)],
+        extra: Option<NodeRef>,
+        bounds: (u64, Option<u64>),
+    ) -> Result<Option<NodeRef>> {
+        let (min_count, max_count) = bounds;
+        let max_reachable = if extra.is_some() {
+            u64::MAX
+        } else {
+            items.len() as u64
+        };
+        let n_counts = max_count.unwrap_or(min_count).min(max_reachable) + 1;
+        if items.len() > MAX_ANY_ORDER_PROPERTIES
+            || (1 << items.len()) * n_counts > MAX_ANY_ORDER_STATES
+        {
+            return Ok(None);
+        }
+        if !any_order_feasible(items, extra.is_some(), bounds, 0, 0) {
+            return Err(anyhow!(UnsatisfiableSchemaError {
+                message: format!(
+                    "object cannot have between {} and {:?} properties",
+                    min_count, max_count
+                ),
+            }));
+        }
+        Ok(Some(self.any_order_state(
+            items,
+            extra,
+            bounds,
+            (0, 0, false),
+            &mut HashMap::default(),
+        )))
+    }
+
+    /// Rest of the object given bitmask of 'used' items, 'count' of all properties so far,
+    /// and whether there were any ('prefixed').
+    fn any_order_state(
+        &mut self,
+        items: &[(NodeRef, bool)],
+        extra: Option<NodeRef>,
+        bounds: (u64, Option<u64>),
+        state: (u64, u64, bool),
+        cache: &mut HashMap<(u64, u64, bool), NodeRef>,
+    ) -> NodeRef {
+        if let Some(node) = cache.get(&state) {
+            return *node;
+        }
+        let (used, count, prefixed) = state;
+        let (min_count, max_count) = bounds;
+        let node = self.builder.new_node("any_order");
+        // 'extra' can lead back to the same state
+        cache.insert(state, node);
+
+        let mut options = vec![];
+        let all_required = items
+            .iter()
+            .enumerate()
+            .all(|(idx, (_, required))| !required || used & (1 << idx) != 0);
+        if all_required && count >= min_count {
+            options.push(self.items_end(prefixed));
+        }
+        if max_count.is_none_or(|max| count < max) {
+            // with no upper bound, counts above the lower bound are all equivalent
+            let next_count = if max_count.is_none() {
+                (count + 1).min(min_count)
+            } else {
+                count + 1
+            };
+            let sep = self.item_separator(prefixed);
+            let next_items = items
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| used & (1 << idx) == 0)
+                .map(|(idx, (item, _))| (*item, used | (1 << idx)));
+            for (item, next_used) in next_items.chain(extra.map(|e| (e, used))) {
+                if any_order_feasible(items, extra.is_some(), bounds, next_used, next_count) {
+                    let rest = self.any_order_state(
+                        items,
+                        extra,
+                        bounds,
+                        (next_used, next_count, true),
+                        cache,
+                    );
+                    options.push(self.builder.join(&[sep, item, rest]));
+                }
+            }
+        }
+        let inner = self.builder.select(&options);
+        self.builder.set_placeholder(node, inner);
+        node
+    }
+
+    /// Regex for quoted keys allowed by 'propertyNames', or None if all keys are allowed.
+    fn property_names_regex(&mut self, schema: &Schema) -> Result<Option<RegexAst>> {
+        let options = match schema {
+            Schema::Any => return Ok(None),
+            Schema::AnyOf { options } => options.as_slice(),
+            _ => std::slice::from_ref(schema),
+        };
+        let mut regexes = vec![];
+        for option in options {
+            if matches!(option, Schema::Unsatisfiable { .. }) {
+                continue;
+            }
+            match self.regex_compile(option) {
+                Ok(Some(rx)) => regexes.push(rx),
+                Ok(None) => bail!("propertyNames only supports string schemas"),
+                Err(e) => {
+                    if e.downcast_ref::<UnsatisfiableSchemaError>().is_none() {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        if regexes.is_empty() {
+            Ok(Some(RegexAst::NoMatch))
+        } else {
+            Ok(Some(RegexAst::Or(regexes)))
+        }
+    }
+
+    /// Like ordered_sequence(), but keeps track of the number of properties,
+    /// so that the total is within 'bounds'.
+    /// 'extra' (if any) can be repeated any number of times after 'items'.
+    fn counted_sequence(
+        &mut self,
+        items: &[(NodeRef, bool)],
+        extra: Option<NodeRef>,
+        bounds: (u64, Option<u64>),
+        state: (u64, bool),
+        cache: &mut HashMap<(usize, u64, bool), Option<NodeRef>>,
+    ) -> Option<NodeRef> {
+        let (min_count, max_count) = bounds;
+        let (count, prefixed) = state;
+        let key = (items.len(), count, prefixed);
+        if let Some(node) = cache.get(&key) {
+            return *node;
+        }
+        // with no upper bound, counts above the lower bound are all equivalent
+        let next_count = if max_count.is_none() {
+            (count + 1).min(min_count)
+        } else {
+            count + 1
+        };
+        let can_add = max_count.is_none_or(|max| count < max);
+        let comma = self.item_separator(true);
+        let sep = self.item_separator(prefixed);
+        let end = self.items_end(true);
+
+        let node = if items.is_empty() {
+            let lo = min_count.saturating_sub(count) as usize;
+            let hi = max_count.map(|max| (max - count) as usize);
+            match extra {
+                _ if hi == Some(0) || (lo == 0 && extra.is_none()) => {
+                    Some(self.items_end(prefixed))
+                }
+                None => None,
+                Some(extra) if prefixed => {
+                    let comma_extra = self.builder.join(&[comma, extra]);
+                    let seq = self.builder.repeat(comma_extra, lo, hi);
+                    Some(self.builder.join(&[seq, end]))
+                }
+                Some(extra) => {
+                    let comma_extra = self.builder.join(&[comma, extra]);
+                    let rest =
+                        self.builder
+                            .repeat(comma_extra, lo.saturating_sub(1), hi.map(|h| h - 1));
+                    let seq = self.builder.join(&[sep, extra, rest, end]);
+                    if lo == 0 {
+                        let empty = self.items_end(false);
+                        Some(self.builder.select(&[empty, seq]))
+                    } else {
+                        Some(seq)
+                    }
+                }
+            }
+        } else {
+            let (item, required) = items[0];
+            let rest = &items[1..];
+            let with_item = if can_add {
+                self.counted_sequence(rest, extra, bounds, (next_count, true), cache)
+                    .map(|rest_seq| self.builder.join(&[sep, item, rest_seq]))
+            } else {
+                None
+            };
+            let without_item = if required {
+                None
+            } else {
+                self.counted_sequence(rest, extra, bounds, (count, prefixed), cache)
+            };
+            match (with_item, without_item) {
+                (Some(a), Some(b)) => Some(self.builder.select(&[a, b])),
+                (a, b) => a.or(b),
+            }
+        };
+        cache.insert(key, node);
+        node
+    }
+
     #[allow(clippy::type_complexity)]
     fn ordered_sequence<'a>(
         &mut self,
@@ -472,9 +936,10 @@ impl Compiler {
             return *node;
         }
         if items.is_empty() {
-            return self.builder.string("");
+            return self.items_end(prefixed);
         }
-        let comma = self.builder.string(&self.options.item_separator);
+        let comma = self.item_separator(true);
+        let first = self.item_separator(false);
         let (item, required) = items[0];
         let rest = &items[1..];
 
@@ -495,7 +960,7 @@ impl Compiler {
             (false, true) => {
                 // No preceding elements, so we just add the element (no comma)
                 let rest_seq = self.ordered_sequence(rest, true, cache);
-                self.builder.join(&[item, rest_seq])
+                self.builder.join(&[first, item, rest_seq])
             }
             (false, false) => {
                 // No preceding elements, but our element is optional. If we add the element, the remaining
@@ -503,7 +968,10 @@ impl Compiler {
                 // TODO: same nested optimization as above
                 let prefixed_rest = self.ordered_sequence(rest, true, cache);
                 let unprefixed_rest = self.ordered_sequence(rest, false, cache);
-                let opts = [self.builder.join(&[item, prefixed_rest]), unprefixed_rest];
+                let opts = [
+                    self.builder.join(&[first, item, prefixed_rest]),
+                    unprefixed_rest,
+                ];
                 self.builder.select(&opts)
             }
         };
@@ -512,20 +980,14 @@ impl Compiler {
     }
 
     fn sequence(&mut self, item: NodeRef) -> NodeRef {
-        let comma = self.builder.string(&self.options.item_separator);
+        let comma = self.item_separator(true);
         let item_comma = self.builder.join(&[item, comma]);
         let item_comma_star = self.builder.zero_or_more(item_comma);
         self.builder.join(&[item_comma_star, item])
     }
 
     fn json_quote(&self, ast: RegexAst) -> RegexAst {
-        RegexAst::JsonQuote(
-            Box::new(ast),
-            JsonQuoteOptions {
-                allowed_escapes: "nrbtf\\\"u".to_string(),
-                raw_mode: false,
-            },
-        )
+        RegexAst::JsonQuote(Box::new(ast), json_quote_options())
     }
 
     fn regex_compile(&mut self, schema: &Schema) -> Result<Option<RegexAst>> {
@@ -548,30 +1010,8 @@ impl Compiler {
                 integer,
                 multiple_of,
             } => {
-                let (minimum, exclusive_minimum) = match (minimum, exclusive_minimum) {
-                    (Some(min), Some(xmin)) => {
-                        if xmin >= min {
-                            (Some(*xmin), true)
-                        } else {
-                            (Some(*min), false)
-                        }
-                    }
-                    (Some(min), None) => (Some(*min), false),
-                    (None, Some(xmin)) => (Some(*xmin), true),
-                    (None, None) => (None, false),
-                };
-                let (maximum, exclusive_maximum) = match (maximum, exclusive_maximum) {
-                    (Some(max), Some(xmax)) => {
-                        if xmax <= max {
-                            (Some(*xmax), true)
-                        } else {
-                            (Some(*max), false)
-                        }
-                    }
-                    (Some(max), None) => (Some(*max), false),
-                    (None, Some(xmax)) => (Some(*xmax), true),
-                    (None, None) => (None, false),
-                };
+                let (minimum, exclusive_minimum) = lower_bound(*minimum, *exclusive_minimum);
+                let (maximum, exclusive_maximum) = upper_bound(*maximum, *exclusive_maximum);
                 Some(if *integer {
                     self.json_int(
                         minimum,
@@ -692,6 +1132,8 @@ impl Compiler {
         item_schema: &Schema,
         min_items: u64,
         max_items: Option<u64>,
+        contains: Option<&ArrayContains>,
+        unique_items: bool,
     ) -> Result<NodeRef> {
         let mut max_items = max_items;
 
@@ -706,6 +1148,30 @@ impl Compiler {
             }
         }
 
+        if unique_items {
+            let unique = if prefix_items.is_empty() && contains.is_none() {
+                self.gen_json_unique_array(item_schema, min_items, max_items)?
+            } else {
+                None
+            };
+            match unique {
+                Some(node) => return Ok(node),
+                None => self.builder.add_warning(GrammarWarning::new(
+                    "uniqueItems is only enforced for arrays of small enums; generated arrays may contain duplicates".to_string(),
+                )),
+            }
+        }
+
+        if let Some(contains) = contains {
+            return self.gen_json_array_contains(
+                prefix_items,
+                item_schema,
+                min_items,
+                max_items,
+                contains,
+            );
+        }
+
         let additional_item_grm = match self.gen_json(item_schema) {
             Ok(node) => Some(node),
             Err(e) => match e.downcast_ref::<UnsatisfiableSchemaError>() {
@@ -775,9 +1241,11 @@ impl Compiler {
         }
 
         let mut grammars: Vec<NodeRef> = vec![self.builder.string("[")];
-        let comma = self.builder.string(&self.options.item_separator);
+        let comma = self.item_separator(true);
+        let end = self.items_end(true);
 
         if !required_items.is_empty() {
+            grammars.push(self.item_separator(false));
             grammars.push(required_items[0]);
             for item in &required_items[1..] {
                 grammars.push(comma);
@@ -787,28 +1255,621 @@ impl Compiler {
 
         if !optional_items.is_empty() {
             let first = optional_items[0];
-            let tail =
-                optional_items
-                    .into_iter()
-                    .skip(1)
-                    .rev()
-                    .fold(self.builder.empty(), |acc, item| {
-                        let j = self.builder.join(&[comma, item, acc]);
-                        self.builder.optional(j)
-                    });
+            let tail = optional_items
+                .into_iter()
+                .skip(1)
+                .rev()
+                .fold(end, |acc, item| {
+                    let j = self.builder.join(&[comma, item, acc]);
+                    self.builder.select(&[end, j])
+                });
             let tail = self.builder.join(&[first, tail]);
 
             if !required_items.is_empty() {
                 let j = self.builder.join(&[comma, tail]);
-                grammars.push(self.builder.optional(j));
+                grammars.push(self.builder.select(&[end, j]));
             } else {
-                grammars.push(self.builder.optional(tail));
+                let sep = self.item_separator(false);
+                let j = self.builder.join(&[sep, tail]);
+                let empty = self.items_end(false);
+                grammars.push(self.builder.select(&[empty, j]));
             }
+        } else if !required_items.is_empty() {
+            grammars.push(end);
         }
 
         grammars.push(self.builder.string("]"));
         Ok(self.builder.join(&grammars))
     }
+
+    /// Like regex_compile(), but returns None for unsatisfiable schemas.
+    fn regex_compile_opt(&mut self, schema: &Schema) -> Result<Option<Option<RegexAst>>> {
+        if matches!(schema, Schema::Unsatisfiable { .. }) {
+            return Ok(None);
+        }
+        match self.regex_compile(schema) {
+            Ok(rx) => Ok(Some(rx)),
+            Err(e) => match e.downcast_ref::<UnsatisfiableSchemaError>() {
+                Some(_) => Ok(None),
+                None => Err(e),
+            },
+        }
+    }
+
+    fn gen_json_opt(&mut self, schema: &Schema) -> Result<Option<NodeRef>> {
+        match self.gen_json(schema) {
+            Ok(node) => Ok(Some(node)),
+            Err(e) => match e.downcast_ref::<UnsatisfiableSchemaError>() {
+                Some(_) => Ok(None),
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Returns the regexes of distinct constants making up the schema,
+    /// or None if the schema is not a (known) finite set of constants.
+    fn finite_values(&mut self, schema: &Schema) -> Result<Option<Vec<RegexAst>>> {
+        let options = match schema {
+            Schema::AnyOf { options } => options.as_slice(),
+            _ => std::slice::from_ref(schema),
+        };
+        let mut seen = HashSet::default();
+        let mut values = vec![];
+        for option in options {
+            let key = match option {
+                Schema::Unsatisfiable { .. } => continue,
+                Schema::Null => "null".to_string(),
+                Schema::LiteralBool { value } => value.to_string(),
+                Schema::Boolean => {
+                    for value in ["true", "false"] {
+                        if seen.insert(value.to_string()) {
+                            values.push(RegexAst::Literal(value.to_string()));
+                        }
+                    }
+                    continue;
+                }
+                Schema::String {
+                    regex: Some(RegexAst::Literal(s)),
+                    ..
+                } => json_dumps(&json!(s)),
+                Schema::Number {
+                    minimum: Some(min),
+                    maximum: Some(max),
+                    exclusive_minimum: None,
+                    exclusive_maximum: None,
+                    multiple_of: None,
+                    ..
+                } if min == max => min.to_string(),
+                _ => return Ok(None),
+            };
+            if !seen.insert(key) {
+                continue;
+            }
+            match self.regex_compile(option) {
+                Ok(Some(rx)) => values.push(rx),
+                Ok(None) => return Ok(None),
+                Err(e) => {
+                    if e.downcast_ref::<UnsatisfiableSchemaError>().is_none() {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(Some(values))
+    }
+
+    /// Arrays with 'uniqueItems' where items are a small enum; None if not applicable.
+    fn gen_json_unique_array(
+        &mut self,
+        item_schema: &Schema,
+        min_items: u64,
+        max_items: Option<u64>,
+    ) -> Result<Option<NodeRef>> {
+        let values = match self.finite_values(item_schema)? {
+            Some(values) if values.len() <= MAX_UNIQUE_VALUES => values,
+            _ => return Ok(None),
+        };
+        let max_items = max_items.map_or(values.len(), |max| values.len().min(max as usize));
+        if min_items as usize > max_items {
+            return Err(anyhow!(UnsatisfiableSchemaError {
+                message: format!(
+                    "minItems ({}) is greater than number of unique items ({})",
+                    min_items,
+                    values.len()
+                ),
+            }));
+        }
+        let values = values
+            .into_iter()
+            .map(|rx| self.ast_lexeme(rx))
+            .collect::<Result<Vec<_>>>()?;
+        let inner = self.unique_sequence(
+            &values,
+            (min_items as usize, max_items),
+            0,
+            &mut HashMap::default(),
+        );
+        let opener = self.builder.string("[");
+        let closer = self.builder.string("]");
+        Ok(Some(self.builder.join(&[opener, inner, closer])))
+    }
+
+    /// Sequence of distinct elements of 'values', given the bitmask of already 'used' ones.
+    fn unique_sequence(
+        &mut self,
+        values: &[NodeRef],
+        bounds: (usize, usize),
+        used: u64,
+        cache: &mut HashMap<u64, NodeRef>,
+    ) -> NodeRef {
+        if let Some(node) = cache.get(&used) {
+            return *node;
+        }
+        let count = used.count_ones() as usize;
+        let sep = self.item_separator(used != 0);
+        let mut options = vec![];
+        if count >= bounds.0 {
+            options.push(self.items_end(used != 0));
+        }
+        if count < bounds.1 {
+            for (idx, value) in values.iter().enumerate() {
+                if used & (1 << idx) != 0 {
+                    continue;
+                }
+                let rest = self.unique_sequence(values, bounds, used | (1 << idx), cache);
+                options.push(self.builder.join(&[sep, *value, rest]));
+            }
+        }
+        let node = self.builder.select(&options);
+        cache.insert(used, node);
+        node
+    }
+
+    /// Grammars for an array item that doesn't count and that counts towards 'contains'.
+    fn contains_item(
+        &mut self,
+        schema: &Schema,
+        counted: &Schema,
+        contains: &ArrayContains,
+    ) -> Result<(Option<NodeRef>, Option<NodeRef>)> {
+        let counted = self.gen_json_opt(counted)?;
+        if contains.max_contains.is_none() {
+            // without upper bound, any item can be skipped when counting
+            return Ok((self.gen_json_opt(schema)?, counted));
+        }
+        // otherwise, items that are not counted must not match 'contains';
+        // we can only check this with regexes
+        let mut contains_rx = vec![];
+        let mut contains_non_primitive = false;
+        let contains_options = match &contains.schema {
+            Schema::Any => return Ok((None, counted)),
+            Schema::AnyOf { options } => options.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        for option in contains_options {
+            match self.regex_compile_opt(option)? {
+                Some(Some(rx)) => contains_rx.push(rx),
+                Some(None) => contains_non_primitive = true,
+                None => {}
+            }
+        }
+
+        let unsupported = || {
+            anyhow!("maxContains is only supported when either 'contains' or array items are primitive types")
+        };
+        let mut regex_nodes = vec![];
+        let mut cfg_nodes = vec![];
+        let item_options = match schema {
+            Schema::AnyOf { options } => options.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        for option in item_options {
+            if matches!(option, Schema::Any) {
+                if contains_non_primitive {
+                    return Err(unsupported());
+                }
+                let primitives = [
+                    Schema::Null,
+                    Schema::Boolean,
+                    Schema::Number {
+                        minimum: None,
+                        maximum: None,
+                        exclusive_minimum: None,
+                        exclusive_maximum: None,
+                        multiple_of: None,
+                        integer: false,
+                    },
+                    Schema::String {
+                        min_length: 0,
+                        max_length: None,
+                        regex: None,
+                    },
+                ];
+                for primitive in primitives.iter() {
+                    regex_nodes.push(self.regex_compile(primitive)?.unwrap());
+                }
+                cfg_nodes.push(
+                    self.nested(|c| c.gen_json_array(&[], &Schema::Any, 0, None, None, false))?,
+                );
+                cfg_nodes.push(self.nested(|c| {
+                    c.gen_json_object(
+                        &IndexMap::new(),
+                        &IndexMap::new(),
+                        &Schema::Any,
+                        None,
+                        0,
+                        None,
+                        vec![],
+                    )
+                })?);
+                continue;
+            }
+            match self.regex_compile_opt(option)? {
+                Some(Some(rx)) => regex_nodes.push(rx),
+                Some(None) if !contains_non_primitive => {
+                    if let Some(node) = self.gen_json_opt(option)? {
+                        cfg_nodes.push(node);
+                    }
+                }
+                Some(None) => return Err(unsupported()),
+                None => {}
+            }
+        }
+        if !regex_nodes.is_empty() {
+            let rx = RegexAst::And(vec![
+                RegexAst::Or(regex_nodes),
+                RegexAst::Not(Box::new(RegexAst::Or(contains_rx))),
+            ]);
+            cfg_nodes.push(self.ast_lexeme(rx)?);
+        }
+        let plain = if cfg_nodes.is_empty() {
+            None
+        } else {
+            Some(self.builder.select(&cfg_nodes))
+        };
+        Ok((plain, counted))
+    }
+
+    fn gen_json_array_contains(
+        &mut self,
+        prefix_items: &[Schema],
+        item_schema: &Schema,
+        min_items: u64,
+        max_items: Option<u64>,
+        contains: &ArrayContains,
+    ) -> Result<NodeRef> {
+        if let Some(max_contains) = contains.max_contains {
+            if contains.min_contains > max_contains {
+                return Err(anyhow!(UnsatisfiableSchemaError {
+                    message: format!(
+                        "minContains ({}) is greater than maxContains ({})",
+                        contains.min_contains, max_contains
+                    ),
+                }));
+            }
+        }
+
+        // positions past n_fixed (if any) are all the same; at least one position is
+        // unrolled, so that the rest of the items all start with a separator
+        let n_fixed = prefix_items
+            .len()
+            .max(contains.min_contains as usize)
+            .max(1);
+        let n_fixed = max_items.map_or(n_fixed, |max| n_fixed.min(max as usize));
+        let mut positions = vec![];
+        for idx in 0..n_fixed {
+            let schema = prefix_items.get(idx).unwrap_or(item_schema);
+            let counted = contains.prefix_items.get(idx).unwrap_or(&contains.items);
+            positions.push(self.contains_item(schema, counted, contains)?);
+        }
+        let ends = self.contains_rest(
+            item_schema,
+            contains,
+            n_fixed,
+            (min_items as usize, max_items.map(|max| max as usize)),
+        )?;
+        let inner = self
+            .contains_sequence(
+                &positions,
+                &ends,
+                min_items as usize,
+                contains,
+                (0, 0),
+                &mut HashMap::default(),
+            )
+            .ok_or_else(|| {
+                anyhow!(UnsatisfiableSchemaError {
+                    message: format!(
+                        "array cannot contain between {} and {:?} matching items",
+                        contains.min_contains, contains.max_contains
+                    ),
+                })
+            })?;
+        let opener = self.builder.string("[");
+        let closer = self.builder.string("]");
+        Ok(self.builder.join(&[opener, inner, closer]))
+    }
+
+    /// Items from position 'idx' on, given 'count' of items matching 'contains' so far.
+    fn contains_sequence(
+        &mut self,
+        positions: &[(Option<NodeRef>, Option<NodeRef>)],
+        ends: &[Option<NodeRef>],
+        min_items: usize,
+        contains: &ArrayContains,
+        state: (usize, u64),
+        cache: &mut HashMap<(usize, u64), Option<NodeRef>>,
+    ) -> Option<NodeRef> {
+        let (idx, count) = state;
+        // with no upper bound, counts above the lower bound are all equivalent
+        let count = if contains.max_contains.is_none() {
+            count.min(contains.min_contains)
+        } else {
+            count
+        };
+        if let Some(node) = cache.get(&(idx, count)) {
+            return *node;
+        }
+        let min_left = contains.min_contains.saturating_sub(count) as usize;
+        let max_left = contains.max_contains.map(|max| (max - count) as usize);
+        let prefixed = idx > 0;
+        let node = if idx == positions.len() {
+            ends[count as usize]
+        } else {
+            let sep = self.item_separator(prefixed);
+            let (plain, counted) = positions[idx];
+            let mut options = vec![];
+            if idx >= min_items && min_left == 0 {
+                options.push(self.items_end(prefixed));
+            }
+            let counted = if max_left == Some(0) { None } else { counted };
+            for (item, next_count) in [(plain, count), (counted, count + 1)] {
+                let Some(item) = item else { continue };
+                if let Some(rest) = self.contains_sequence(
+                    positions,
+                    ends,
+                    min_items,
+                    contains,
+                    (idx + 1, next_count),
+                    cache,
+                ) {
+                    options.push(self.builder.join(&[sep, item, rest]));
+                }
+            }
+            if options.is_empty() {
+                None
+            } else {
+                Some(self.builder.select(&options))
+            }
+        };
+        cache.insert((idx, count), node);
+        node
+    }
+
+    /// Items past the 'n_fixed' unrolled positions, and the end of the array, indexed by
+    /// the count of items matching 'contains' in the unrolled positions.
+    fn contains_rest(
+        &mut self,
+        item_schema: &Schema,
+        contains: &ArrayContains,
+        n_fixed: usize,
+        items_bounds: (usize, Option<usize>),
+    ) -> Result<Vec<Option<NodeRef>>> {
+        let (min_items, max_items) = items_bounds;
+        // counts are tracked up to maxContains, or up to minContains when there's
+        // no upper bound (higher counts are all equivalent then)
+        let max_count = contains.max_contains.unwrap_or(contains.min_contains) as usize;
+        let saturate = contains.max_contains.is_none();
+        let prefixed = n_fixed > 0;
+        let end = self.items_end(prefixed);
+        let count_ok = |count: usize| count >= contains.min_contains as usize;
+        if max_items == Some(n_fixed) {
+            return Ok((0..=max_count)
+                .map(|count| count_ok(count).then_some(end))
+                .collect());
+        }
+
+        let (plain, counted) = self.contains_item(item_schema, &contains.items, contains)?;
+        let comma = self.item_separator(true);
+        let mut unit = vec![plain.map(|plain| self.builder.join(&[comma, plain]))];
+        if max_count > 0 {
+            unit.push(counted.map(|counted| self.builder.join(&[comma, counted])));
+        }
+        let min_rest = min_items.saturating_sub(n_fixed);
+        let mut rest = self.contains_repeat(&unit, max_count, saturate, min_rest, false);
+        if let Some(max_items) = max_items {
+            let n_optional = max_items - n_fixed - min_rest;
+            let optional = self.contains_repeat(&unit, max_count, saturate, n_optional, true);
+            rest = self.contains_concat(&rest, &optional, saturate);
+        }
+
+        let mut ends = vec![];
+        for count in 0..=max_count {
+            let mut options = vec![];
+            for (extra, node) in rest.iter().enumerate() {
+                let Some(node) = node else { continue };
+                let total = count + extra;
+                if total > max_count && !saturate {
+                    continue;
+                }
+                let total = total.min(max_count);
+                let tail = if max_items.is_some() {
+                    count_ok(total).then_some(end)
+                } else {
+                    let bounds = (
+                        contains.min_contains.saturating_sub(total as u64) as usize,
+                        contains.max_contains.map(|max| max as usize - total),
+                    );
+                    self.contains_tail(plain, counted, bounds)
+                };
+                if let Some(tail) = tail {
+                    options.push(self.builder.join(&[*node, tail]));
+                }
+            }
+            ends.push(self.select_opt(options));
+        }
+        Ok(ends)
+    }
+
+    fn select_opt(&mut self, options: Vec<NodeRef>) -> Option<NodeRef> {
+        if options.is_empty() {
+            None
+        } else {
+            Some(self.builder.select(&options))
+        }
+    }
+
+    /// Sequences of exactly (or 'at_most') 'n' copies of 'unit', indexed by the count of
+    /// items matching 'contains' (so 'unit' is [plain, counted]).
+    /// Like GrammarBuilder::repeat(), this is built from blocks of 2^k items,
+    /// to keep the grammar size logarithmic in 'n'.
+    fn contains_repeat(
+        &mut self,
+        unit: &[Option<NodeRef>],
+        max_count: usize,
+        saturate: bool,
+        n: usize,
+        at_most: bool,
+    ) -> Vec<Option<NodeRef>> {
+        let mut empty = vec![None; max_count + 1];
+        empty[0] = Some(self.builder.empty());
+        let mut block = unit.to_vec();
+        block.resize(max_count + 1, None);
+        // sequences of length up to the lower bits of 'n', and of less than 2^k
+        let mut result = empty.clone();
+        let mut shorter = empty;
+        let mut k = 0;
+        while n >> k != 0 {
+            if n & (1 << k) != 0 {
+                let longer = self.contains_concat(&block, &result, saturate);
+                result = if at_most {
+                    self.contains_union(&shorter, &longer)
+                } else {
+                    longer
+                };
+            }
+            k += 1;
+            if n >> k != 0 {
+                let longer = self.contains_concat(&block, &shorter, saturate);
+                shorter = self.contains_union(&shorter, &longer);
+                block = self.contains_concat(&block, &block, saturate);
+            }
+        }
+        result
+    }
+
+    fn contains_concat(
+        &mut self,
+        a: &[Option<NodeRef>],
+        b: &[Option<NodeRef>],
+        saturate: bool,
+    ) -> Vec<Option<NodeRef>> {
+        let max_count = a.len() - 1;
+        let mut options = vec![vec![]; max_count + 1];
+        for (count_a, node_a) in a.iter().enumerate() {
+            for (count_b, node_b) in b.iter().enumerate() {
+                let (Some(node_a), Some(node_b)) = (node_a, node_b) else {
+                    continue;
+                };
+                let count = count_a + count_b;
+                if count > max_count && !saturate {
+                    continue;
+                }
+                options[count.min(max_count)].push(self.builder.join(&[*node_a, *node_b]));
+            }
+        }
+        options
+            .into_iter()
+            .map(|options| self.select_opt(options))
+            .collect()
+    }
+
+    fn contains_union(
+        &mut self,
+        a: &[Option<NodeRef>],
+        b: &[Option<NodeRef>],
+    ) -> Vec<Option<NodeRef>> {
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| self.select_opt(a.iter().chain(b.iter()).copied().collect()))
+            .collect()
+    }
+
+    /// Unbounded sequence of items following other items, with between 'bounds' items
+    /// counting towards 'contains'.
+    fn contains_tail(
+        &mut self,
+        plain: Option<NodeRef>,
+        counted: Option<NodeRef>,
+        bounds: (usize, Option<usize>),
+    ) -> Option<NodeRef> {
+        let (min_left, max_left) = bounds;
+        let comma = self.item_separator(true);
+        let end = self.items_end(true);
+        let plain_star = match plain {
+            Some(plain) => {
+                let comma_plain = self.builder.join(&[comma, plain]);
+                self.builder.zero_or_more(comma_plain)
+            }
+            None => self.builder.empty(),
+        };
+        match counted {
+            None if min_left == 0 => Some(self.builder.join(&[plain_star, end])),
+            None => None,
+            Some(counted) => {
+                let comma_counted = self.builder.join(&[comma, counted, plain_star]);
+                // without maxContains, 'plain' items also include the ones matching 'contains'
+                let max_left = max_left.or(Some(min_left));
+                let counted_seq = self.builder.repeat(comma_counted, min_left, max_left);
+                Some(self.builder.join(&[plain_star, counted_seq, end]))
+            }
+        }
+    }
+}
+
+/// Maximum number of distinct values for which 'uniqueItems' is enforced exactly.
+const MAX_UNIQUE_VALUES: usize = 8;
+
+/// Maximum number of properties for which 'any_order' is enforced exactly
+/// (all subsets of used properties are tracked).
+const MAX_ANY_ORDER_PROPERTIES: usize = 8;
+const MAX_ANY_ORDER_STATES: u64 = 4096;
+
+/// Whether an object with 'used' (bitmask) of 'items' and 'count' properties in total
+/// can still be completed to have all the required ones, and within 'bounds'.
+fn any_order_feasible(
+    items: &[(NodeRef, bool)],
+    has_extra: bool,
+    bounds: (u64, Option<u64>),
+    used: u64,
+    count: u64,
+) -> bool {
+    let (min_count, max_count) = bounds;
+    let unused = items
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| used & (1 << idx) == 0);
+    let missing_required = unused
+        .clone()
+        .filter(|(_, (_, required))| *required)
+        .count() as u64;
+    let available = if has_extra {
+        u64::MAX
+    } else {
+        unused.count() as u64
+    };
+    let needed = missing_required.max(min_count.saturating_sub(count));
+    needed <= available && max_count.is_none_or(|max| count + needed <= max)
+}
+
+/// Maximum nesting of arrays and objects that is indented
+/// (each level needs separate copies of the grammar rules).
+const MAX_INDENT_DEPTH: usize = 16;
+
+fn regex_matches(rx: &RegexAst, s: &str) -> Result<bool> {
+    let mut builder = derivre::RegexBuilder::new();
+    let expr = builder.mk(rx)?;
+    Ok(builder.to_regex(expr).is_match(s))
 }
 
 fn always_non_empty(ast: &RegexAst) -> bool {
@@ -1,9 +1,13 @@
-use anyhow::Result;
-use referencing::{Registry, Resolver, Resource};
+use anyhow::{ensure, Result};
+use referencing::{Registry, Resolved, Resolver, Resource};
 use serde_json::Value;
 use std::{cell::RefCell, rc::Rc};
 
-use super::{schema::SchemaBuilderOptions, shared_context::SharedContext, RetrieveWrapper};
+use super::{
+    schema::SchemaBuilderOptions,
+    shared_context::{RefKind, SharedContext},
+    RetrieveWrapper,
+};
 
 const DEFAULT_DRAFT: Draft = Draft::Draft202012;
 const DEFAULT_ROOT_URI: &str = "json-schema:///";
@@ -17,6 +21,7 @@ fn draft_for(value: &Value) -> Draft {
 pub struct PreContext {
     registry: Registry,
     draft: Draft,
+    has_dynamic_refs: bool,
     pub base_uri: String,
 }
 
@@ -25,11 +30,24 @@ pub struct Context<'a> {
     pub draft: Draft,
     pub shared: Rc<RefCell<SharedContext>>,
     pub options: SchemaBuilderOptions,
+    /// If set, compiled references also depend on the dynamic scope.
+    has_dynamic_refs: bool,
+}
+
+fn has_dynamic_refs(value: &Value) -> bool {
+    match value {
+        Value::Object(obj) => obj
+            .iter()
+            .any(|(k, v)| k == "$dynamicRef" || k == "$recursiveRef" || has_dynamic_refs(v)),
+        Value::Array(arr) => arr.iter().any(has_dynamic_refs),
+        _ => false,
+    }
 }
 
 impl PreContext {
     pub fn new(contents: Value, retriever: Option<RetrieveWrapper>) -> Result<Self> {
         let draft = draft_for(&contents);
+        let has_dynamic_refs = has_dynamic_refs(&contents);
         let resource = draft.create_resource(contents);
         let base_uri = resource.id().unwrap_or(DEFAULT_ROOT_URI).to_string();
 
@@ -54,32 +72,109 @@ impl PreContext {
         Ok(PreContext {
             registry,
             draft,
+            has_dynamic_refs,
             base_uri,
         })
     }
 }
 
 impl<'a> Context<'a> {
-    pub fn new(pre_context: &'a PreContext) -> Result<Self> {
+    pub fn new(pre_context: &'a PreContext, options: SchemaBuilderOptions) -> Result<Self> {
         let resolver = pre_context.registry.try_resolver(&pre_context.base_uri)?;
         let ctx = Context {
             resolver,
             draft: pre_context.draft,
             shared: Rc::new(RefCell::new(SharedContext::new())),
-            options: SchemaBuilderOptions::default(),
+            options,
+            has_dynamic_refs: pre_context.has_dynamic_refs,
         };
 
         Ok(ctx)
     }
 
     pub fn in_subresource(&'a self, resource: ResourceRef) -> Result<Context<'a>> {
-        let resolver = self.resolver.in_subresource(resource)?;
-        Ok(Context {
+        let mut resolver = self.resolver.in_subresource(resource)?;
+        if self.has_dynamic_refs && resolver.base_uri() != self.resolver.base_uri() {
+            // looking up the embedded resource adds the current one to the dynamic scope
+            if let Ok(resolved) = self.resolver.lookup(resolver.base_uri().as_str()) {
+                resolver = resolved.resolver().clone();
+            }
+        }
+        Ok(self.with_resolver(resolver, resource.draft()))
+    }
+
+    fn with_resolver(&self, resolver: Resolver<'a>, draft: Draft) -> Context<'a> {
+        Context {
             resolver,
-            draft: resource.draft(),
+            draft,
             shared: Rc::clone(&self.shared),
             options: self.options.clone(),
-        })
+            has_dynamic_refs: self.has_dynamic_refs,
+        }
+    }
+
+    /// Resources in the dynamic scope (outermost first, without repetitions).
+    fn dynamic_scope(&self) -> Vec<String> {
+        let mut scope: Vec<String> = vec![];
+        let inner_first = std::iter::once(self.resolver.base_uri())
+            .chain(
+                self.resolver
+                    .dynamic_scope()
+                    .iter()
+                    .cloned()
+                    .map(Into::into),
+            )
+            .collect::<Vec<_>>();
+        for uri in inner_first.iter().rev() {
+            if !scope.iter().any(|u| u == uri.as_str()) {
+                scope.push(uri.as_str().to_string());
+            }
+        }
+        scope
+    }
+
+    /// Key under which the definition of given reference is stored.
+    /// For dynamic references the same reference may resolve to different schemas,
+    /// depending on the dynamic scope, so it becomes part of the key.
+    pub fn ref_key(&self, kind: RefKind, reference: &str) -> Result<String> {
+        let key = format!("{}{}", kind.key_prefix(), self.normalize_ref(reference)?);
+        if self.has_dynamic_refs {
+            Ok(format!("{} in [{}]", key, self.dynamic_scope().join(", ")))
+        } else {
+            Ok(key)
+        }
+    }
+
+    /// Resolve reference to the resource it points to and the context to compile it in.
+    pub fn lookup_ref(
+        &self,
+        kind: RefKind,
+        reference: &str,
+    ) -> Result<(ResourceRef<'a>, Context<'a>)> {
+        let resolved: Resolved<'a> = match kind {
+            // $dynamicAnchor's are resolved dynamically by the resolver
+            RefKind::Ref | RefKind::DynamicRef => self.resolver.lookup(reference)?,
+            RefKind::RecursiveRef => {
+                ensure!(
+                    reference == "#",
+                    "$recursiveRef must be \"#\", got {:?}",
+                    reference
+                );
+                self.resolver.lookup_recursive_ref()?
+            }
+        };
+        let (contents, mut resolver, _) = resolved.into_inner();
+        if kind == RefKind::DynamicRef && resolver.base_uri() != self.resolver.base_uri() {
+            // jumping to a $dynamicAnchor in another resource doesn't extend the scope
+            resolver = self
+                .resolver
+                .lookup(resolver.base_uri().as_str())?
+                .resolver()
+                .clone();
+        }
+        let resource = self.as_resource_ref(contents);
+        let ctx = self.with_resolver(resolver, resource.draft());
+        Ok((resource, ctx))
     }
 
     pub fn as_resource_ref<'r>(&'a self, contents: &'r Value) -> ResourceRef<'r> {
@@ -3,7 +3,11 @@ use serde_json::Value;
 use std::{cell::RefCell, rc::Rc, sync::Arc};
 
 use super::{
-    super::{schema::SchemaBuilderOptions, shared_context::SharedContext, RetrieveWrapper},
+    super::{
+        schema::SchemaBuilderOptions,
+        shared_context::{RefKind, SharedContext},
+        RetrieveWrapper,
+    },
     Draft, ResourceRef,
 };
 
@@ -42,12 +46,12 @@ impl PreContext {
 }
 
 impl<'a> Context<'a> {
-    pub fn new(pre_context: &'a PreContext) -> Result<Self> {
+    pub fn new(pre_context: &'a PreContext, options: SchemaBuilderOptions) -> Result<Self> {
         let ctx = Context {
             root_doc: Arc::clone(&pre_context.root_doc),
             draft: pre_context.draft,
             shared: Rc::new(RefCell::new(SharedContext::new())),
-            options: SchemaBuilderOptions::default(),
+            options,
             _marker: std::marker::PhantomData,
         };
 
@@ -69,6 +73,24 @@ impl<'a> Context<'a> {
         Ok(reference.to_string())
     }
 
+    /// Without dynamic scope tracking, $dynamicRef is treated as $ref
+    /// and $recursiveRef points to the root.
+    pub fn ref_key(&self, kind: RefKind, reference: &str) -> Result<String> {
+        Ok(format!("{}{}", kind.key_prefix(), reference))
+    }
+
+    pub fn lookup_ref(
+        &'a self,
+        kind: RefKind,
+        reference: &str,
+    ) -> Result<(ResourceRef<'a>, Context<'a>)> {
+        let reference = match kind {
+            RefKind::Ref | RefKind::DynamicRef => reference,
+            RefKind::RecursiveRef => "#",
+        };
+        Ok((self.lookup_resource(reference)?, self.clone()))
+    }
+
     pub fn lookup_resource(&'a self, reference: &str) -> Result<ResourceRef<'a>> {
         if reference == "#" || reference == "#/" {
             return Ok(self.as_resource_ref(self.root_doc.as_ref()));
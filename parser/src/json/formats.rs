@@ -1,4 +1,4 @@
-pub fn lookup_format(name: &str) -> Option<&str> {
+pub fn lookup_format(name: &str) -> Option<&'static str> {
     let r = match name {
         "date-time" => concat!(
             r"^(?P<date>",
@@ -44,6 +44,48 @@ pub fn lookup_format(name: &str) -> Option<&str> {
         "uuid" => {
             r"^(?P<time_low>[0-9a-fA-F]{8})-(?P<time_mid>[0-9a-fA-F]{4})-(?P<time_high_and_version>[0-9a-fA-F]{4})-(?P<clock_seq_and_reserved>[0-9a-fA-F]{2})(?P<clock_seq_low>[0-9a-fA-F]{2})-(?P<node>[0-9a-fA-F]{12})$"
         }
+        "idn-email" => {
+            r"^(?P<local_part>[^\s@\.]+(\.[^\s@\.]+)*)@(?P<domain>[^\s@\.\-\[\]]([^\s@\.\[\]]*[^\s@\.\-\[\]])?(\.[^\s@\.\-\[\]]([^\s@\.\[\]]*[^\s@\.\-\[\]])?)*)$"
+        }
+        "idn-hostname" => {
+            r"^[^\s@\.\-\[\]]([^\s@\.\[\]]*[^\s@\.\-\[\]])?(\.[^\s@\.\-\[\]]([^\s@\.\[\]]*[^\s@\.\-\[\]])?)*$"
+        }
+        "uri" => concat!(
+            r"^(?P<scheme>[a-zA-Z][a-zA-Z0-9+.\-]*):",
+            r"(?P<hier_part>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/\[\]]|%[0-9A-Fa-f]{2})*)",
+            r"(?:\?(?P<query>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|%[0-9A-Fa-f]{2})*))?",
+            r"(?:#(?P<fragment>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|%[0-9A-Fa-f]{2})*))?$"
+        ),
+        "uri-reference" => concat!(
+            r"^(?:",
+            r"(?P<scheme>[a-zA-Z][a-zA-Z0-9+.\-]*):(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/\[\]]|%[0-9A-Fa-f]{2})*|",
+            // relative reference; the first segment cannot contain a colon
+            r"(?:[A-Za-z0-9\-._~!$&'()*+,;=@]|%[0-9A-Fa-f]{2})*(?:/(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/\[\]]|%[0-9A-Fa-f]{2})*)?",
+            r")",
+            r"(?:\?(?P<query>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|%[0-9A-Fa-f]{2})*))?",
+            r"(?:#(?P<fragment>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|%[0-9A-Fa-f]{2})*))?$"
+        ),
+        // same as uri and uri-reference, but also allowing any non-ASCII characters
+        "iri" => concat!(
+            r"^(?P<scheme>[a-zA-Z][a-zA-Z0-9+.\-]*):",
+            r"(?P<hier_part>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/\[\]]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*)",
+            r"(?:\?(?P<query>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*))?",
+            r"(?:#(?P<fragment>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*))?$"
+        ),
+        "iri-reference" => concat!(
+            r"^(?:",
+            r"(?P<scheme>[a-zA-Z][a-zA-Z0-9+.\-]*):(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/\[\]]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*|",
+            r"(?:[A-Za-z0-9\-._~!$&'()*+,;=@]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*(?:/(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/\[\]]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*)?",
+            r")",
+            r"(?:\?(?P<query>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*))?",
+            r"(?:#(?P<fragment>(?:[A-Za-z0-9\-._~!$&'()*+,;=:@/?]|[^\x00-\x7F]|%[0-9A-Fa-f]{2})*))?$"
+        ),
+        "json-pointer" => r"^(?:/(?:[^/~]|~[01])*)*$",
+        "relative-json-pointer" => r"^(?P<up>0|[1-9][0-9]*)(?:#|(?:/(?:[^/~]|~[01])*)*)$",
+        // we can't check if the string is a valid regex
+        "regex" => r"^(?s:.*)$",
+        // OpenAPI; base64-encoded data
+        "byte" => r"^(?:[A-Za-z0-9+/]{4})*(?:[A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)?$",
         "unknown" => r"^(?s:.*)$",
         _ => return None,
     };
@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use regex_syntax::escape;
 
 /// coef * 10^-exp
@@ -78,11 +78,13 @@ fn mk_or(parts: Vec<String>) -> String {
     }
 }
 
-fn num_digits(n: i64) -> usize {
+fn num_digits(n: i128) -> usize {
     n.abs().to_string().len()
 }
 
-pub fn rx_int_range(left: Option<i64>, right: Option<i64>) -> Result<String> {
+/// Regex for integers in [left, right]; i128, so that i64 bounds can be negated
+/// and multiplied by 10 without overflow.
+pub fn rx_int_range(left: Option<i128>, right: Option<i128>) -> Result<String> {
     match (left, right) {
         (None, None) => Ok("-?(0|[1-9][0-9]*)".to_string()),
         (Some(left), None) => {
@@ -94,7 +96,7 @@ pub fn rx_int_range(left: Option<i64>, right: Option<i64>) -> Result<String> {
             } else {
                 let max_value = "9"
                     .repeat(num_digits(left))
-                    .parse::<i64>()
+                    .parse::<i128>()
                     .map_err(|e| anyhow!("Failed to parse max value for left {}: {}", left, e))?;
                 Ok(mk_or(vec![
                     rx_int_range(Some(left), Some(max_value))?,
@@ -146,8 +148,8 @@ pub fn rx_int_range(left: Option<i64>, right: Option<i64>) -> Result<String> {
                     return Ok(format!("({}[{}-{}])", lpref, lx, rx));
                 }
 
-                let mut left_rec = lpref.parse::<i64>().unwrap_or(0);
-                let mut right_rec = rpref.parse::<i64>().unwrap_or(0);
+                let mut left_rec = lpref.parse::<i128>().unwrap_or(0);
+                let mut right_rec = rpref.parse::<i128>().unwrap_or(0);
                 if left_rec >= right_rec {
                     return Err(anyhow!(
                         "Invalid recursive range: left_rec ({}) must be less than right_rec ({})",
@@ -175,7 +177,7 @@ pub fn rx_int_range(left: Option<i64>, right: Option<i64>) -> Result<String> {
 
                 Ok(mk_or(parts))
             } else {
-                let break_point = 10_i64
+                let break_point = 10_i128
                     .checked_pow(num_digits(left) as u32)
                     .ok_or_else(|| anyhow!("Overflow when calculating break point"))?
                     - 1;
@@ -263,12 +265,17 @@ fn lexi_0_to_x(x: &str, incl: bool) -> Result<String> {
             return Ok(format!("[0-{}][0-9]*", x0 - 1));
         }
 
+        let mut rest = lexi_0_to_x(x_rest, incl)?;
+        // a prefix of x is below x, unless only zeros follow
+        if !x_rest.trim_end_matches('0').is_empty() {
+            rest = format!("({})?", rest);
+        }
         let mut parts = vec![format!(
             "{}{}",
             x.chars()
                 .next()
                 .ok_or_else(|| anyhow!("String x is unexpectedly empty"))?,
-            lexi_0_to_x(x_rest, incl)?
+            rest
         )];
         if x0 > 0 {
             parts.push(format!("[0-{}][0-9]*", x0 - 1));
@@ -328,14 +335,21 @@ fn lexi_range(ld: &str, rd: &str, ld_incl: bool, rd_incl: bool) -> Result<String
                 parts.push(format!("[{}-{}][0-9]*", l0 + 1, r0 - 1));
             }
             let rd_rest = rd[1..].trim_end_matches('0');
-            if !rd_rest.is_empty() || rd_incl {
+            if !rd_rest.is_empty() {
                 parts.push(format!(
-                    "{}{}",
+                    "{}({})?",
                     rd.chars()
                         .next()
                         .ok_or_else(|| anyhow!("rd is unexpectedly empty"))?,
                     lexi_0_to_x(rd_rest, rd_incl)?
                 ));
+            } else if rd_incl {
+                parts.push(
+                    rd.chars()
+                        .next()
+                        .ok_or_else(|| anyhow!("rd is unexpectedly empty"))?
+                        .to_string(),
+                );
             }
             Ok(mk_or(parts))
         }
@@ -361,7 +375,7 @@ pub fn rx_float_range(
                     rx_float_range(Some(0.0), None, true, false)?,
                 ]))
             } else {
-                let left_int_part = left as i64;
+                let left_int_part = left as i128;
                 Ok(mk_or(vec![
                     rx_float_range(
                         Some(left),
@@ -439,13 +453,13 @@ pub fn rx_float_range(
                     return Err(anyhow!("Infinite numbers not supported"));
                 }
 
-                let mut left_rec: i64 = l
+                let mut left_rec: i128 = l
                     .split('.')
                     .next()
                     .ok_or_else(|| anyhow!("Failed to split left integer part"))?
                     .parse()
                     .map_err(|e| anyhow!("Failed to parse left integer part: {}", e))?;
-                let right_rec: i64 = r
+                let right_rec: i128 = r
                     .split('.')
                     .next()
                     .ok_or_else(|| anyhow!("Failed to split right integer part"))?
@@ -504,6 +518,73 @@ pub fn rx_float_range(
     }
 }
 
+/// Combine 'minimum' and 'exclusiveMinimum' into the tighter bound; returns (bound, is_exclusive).
+pub fn lower_bound(minimum: Option<f64>, exclusive_minimum: Option<f64>) -> (Option<f64>, bool) {
+    match (minimum, exclusive_minimum) {
+        (Some(min), Some(xmin)) => {
+            if xmin >= min {
+                (Some(xmin), true)
+            } else {
+                (Some(min), false)
+            }
+        }
+        (Some(min), None) => (Some(min), false),
+        (None, Some(xmin)) => (Some(xmin), true),
+        (None, None) => (None, false),
+    }
+}
+
+/// Combine 'maximum' and 'exclusiveMaximum' into the tighter bound; returns (bound, is_exclusive).
+pub fn upper_bound(maximum: Option<f64>, exclusive_maximum: Option<f64>) -> (Option<f64>, bool) {
+    match (maximum, exclusive_maximum) {
+        (Some(max), Some(xmax)) => {
+            if xmax <= max {
+                (Some(xmax), true)
+            } else {
+                (Some(max), false)
+            }
+        }
+        (Some(max), None) => (Some(max), false),
+        (None, Some(xmax)) => (Some(xmax), true),
+        (None, None) => (None, false),
+    }
+}
+
+/// Like rx_int_range(), but takes (possibly fractional or exclusive) float bounds.
+pub fn rx_int_bounds(
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    exclusive_minimum: bool,
+    exclusive_maximum: bool,
+) -> Result<String> {
+    // Convert before adding/subtracting 1 for exclusive bounds, which would be
+    // lost on big floats (2^63 - 1.0 == 2^63); conversion saturates on huge floats,
+    // and i128::MIN is avoided, so that it can be negated.
+    let to_int = |val: f64| (val as i128).max(-i128::MAX);
+    let minimum = minimum.map(|min_val| {
+        let ceil = to_int(min_val.ceil());
+        if exclusive_minimum && min_val.fract() == 0.0 {
+            ceil.saturating_add(1)
+        } else {
+            ceil
+        }
+    });
+    let maximum = maximum.map(|max_val| {
+        let floor = to_int(max_val.floor());
+        if exclusive_maximum && max_val.fract() == 0.0 {
+            floor.saturating_sub(1).max(-i128::MAX)
+        } else {
+            floor
+        }
+    });
+    rx_int_range(minimum, maximum).with_context(|| {
+        format!(
+            "Failed to generate regex for integer range: min={:?}, max={:?}",
+            minimum, maximum
+        )
+    })
+}
+
 pub fn check_number_bounds(
     minimum: Option<f64>,
     maximum: Option<f64>,
@@ -603,10 +684,10 @@ pub fn check_number_bounds(
 
 #[cfg(test)]
 mod test_ranges {
-    use super::{rx_float_range, rx_int_range};
+    use super::{rx_float_range, rx_int_bounds, rx_int_range};
     use regex::Regex;
 
-    fn do_test_int_range(rx: &str, left: Option<i64>, right: Option<i64>) {
+    fn do_test_int_range(rx: &str, left: Option<i128>, right: Option<i128>) {
         let re = Regex::new(&format!("^{}$", rx)).unwrap();
         for n in (left.unwrap_or(0) - 1000)..=(right.unwrap_or(0) + 1000) {
             let matches = re.is_match(&n.to_string());
@@ -683,7 +764,7 @@ mod test_ranges {
     ) {
         let re = Regex::new(&format!("^{}$", rx)).unwrap();
         let left_int = left.map(|x| {
-            let left_int = x.ceil() as i64;
+            let left_int = x.ceil() as i128;
             if !left_inclusive && x == left_int as f64 {
                 left_int + 1
             } else {
@@ -691,7 +772,7 @@ mod test_ranges {
             }
         });
         let right_int = right.map(|x| {
-            let right_int = x.floor() as i64;
+            let right_int = x.floor() as i128;
             if !right_inclusive && x == right_int as f64 {
                 right_int - 1
             } else {
@@ -818,6 +899,43 @@ mod test_ranges {
             }
         }
     }
+
+    fn check_matches(rx: &str, accepted: &[&str], rejected: &[&str]) {
+        let re = Regex::new(&format!("^{}$", rx)).unwrap();
+        for s in accepted {
+            assert!(re.is_match(s), "{} does not match {:?}", s, rx);
+        }
+        for s in rejected {
+            assert!(!re.is_match(s), "{} matches {:?}", s, rx);
+        }
+    }
+
+    #[test]
+    fn test_int64_bounds() {
+        let rx =
+            rx_int_bounds(Some(i64::MIN as f64), Some(-(i64::MIN as f64)), false, true).unwrap();
+        check_matches(
+            &rx,
+            &["-9223372036854775808", "9223372036854775807", "0", "-1"],
+            &[
+                "-9223372036854775809",
+                "9223372036854775808",
+                "10000000000000000000",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_float_range_prefix() {
+        let rx = rx_float_range(Some(1.0), Some(2.34), true, true).unwrap();
+        check_matches(&rx, &["2.3", "2.34", "2.300"], &["2.35", "2.341"]);
+        let rx = rx_float_range(Some(0.25), Some(0.5), false, false).unwrap();
+        check_matches(
+            &rx,
+            &["0.3", "0.4", "0.49"],
+            &["0.2", "0.25", "0.5", "0.50"],
+        );
+    }
 }
 
 #[cfg(test)]
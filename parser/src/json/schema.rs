@@ -1,3 +1,4 @@
+use crate::api::GrammarWarning;
 use crate::HashMap;
 use anyhow::{anyhow, bail, Result};
 use derivre::RegexAst;
@@ -8,29 +9,47 @@ use std::mem;
 use super::context::{Context, Draft, PreContext, ResourceRef};
 use super::formats::lookup_format;
 use super::numeric::Decimal;
+use super::shared_context::RefKind;
 use super::RetrieveWrapper;
 
 const TYPES: [&str; 6] = ["null", "boolean", "number", "string", "array", "object"];
 
 // Keywords that are implemented in this module
-pub(crate) const IMPLEMENTED: [&str; 24] = [
+pub(crate) const IMPLEMENTED: [&str; 40] = [
     // Core
     "anyOf",
     "oneOf",
     "allOf",
     "$ref",
+    "$dynamicRef",
+    "$recursiveRef",
     "const",
     "enum",
     "type",
+    // Conditional
+    "if",
+    "then",
+    "else",
+    "dependentRequired",
+    "dependentSchemas",
+    "dependencies",
     // Array
     "items",
     "additionalItems",
     "prefixItems",
     "minItems",
     "maxItems",
+    "uniqueItems",
+    "contains",
+    "minContains",
+    "maxContains",
     // Object
     "properties",
     "additionalProperties",
+    "patternProperties",
+    "propertyNames",
+    "minProperties",
+    "maxProperties",
     "required",
     // String
     "minLength",
@@ -48,8 +67,10 @@ pub(crate) const IMPLEMENTED: [&str; 24] = [
 // Keywords that are used for metadata or annotations, not directly driving validation.
 // Note that some keywords like $id and $schema affect the behavior of other keywords, but
 // they can safely be ignored if other keywords aren't present
-pub(crate) const META_AND_ANNOTATIONS: [&str; 15] = [
+pub(crate) const META_AND_ANNOTATIONS: [&str; 17] = [
     "$anchor",
+    "$dynamicAnchor",
+    "$recursiveAnchor",
     "$defs",
     "definitions",
     "$schema",
@@ -101,10 +122,16 @@ pub enum Schema {
         max_items: Option<u64>,
         prefix_items: Vec<Schema>,
         items: Option<Box<Schema>>,
+        contains: Option<Box<ArrayContains>>,
+        unique_items: bool,
     },
     Object {
         properties: IndexMap<String, Schema>,
+        pattern_properties: IndexMap<String, Schema>,
         additional_properties: Option<Box<Schema>>,
+        property_names: Option<Box<Schema>>,
+        min_properties: u64,
+        max_properties: Option<u64>,
         required: IndexSet<String>,
     },
     LiteralBool {
@@ -121,6 +148,42 @@ pub enum Schema {
     },
 }
 
+/// The 'contains' constraint of an array schema, together with its intersections
+/// with the item schemas (these are the items that count towards 'minContains').
+#[derive(Debug, Clone)]
+pub struct ArrayContains {
+    pub schema: Schema,
+    pub prefix_items: Vec<Schema>,
+    pub items: Schema,
+    pub min_contains: u64,
+    pub max_contains: Option<u64>,
+}
+
+impl ArrayContains {
+    fn new(
+        ctx: &Context,
+        schema: Schema,
+        prefix_items: &[Schema],
+        items: Option<&Schema>,
+        min_contains: u64,
+        max_contains: Option<u64>,
+    ) -> Result<Self> {
+        Ok(ArrayContains {
+            prefix_items: prefix_items
+                .iter()
+                .map(|item| item.clone().intersect(schema.clone(), ctx))
+                .collect::<Result<Vec<_>>>()?,
+            items: items
+                .cloned()
+                .unwrap_or(Schema::Any)
+                .intersect(schema.clone(), ctx)?,
+            schema,
+            min_contains,
+            max_contains,
+        })
+    }
+}
+
 impl Schema {
     pub fn false_schema() -> Schema {
         Schema::Unsatisfiable {
@@ -309,17 +372,19 @@ impl Schema {
                     max_items: max1,
                     prefix_items: mut prefix1,
                     items: items1,
+                    contains: contains1,
+                    unique_items: unique1,
                 },
                 Schema::Array {
                     min_items: min2,
                     max_items: max2,
                     prefix_items: mut prefix2,
                     items: items2,
+                    contains: contains2,
+                    unique_items: unique2,
                 },
-            ) => Schema::Array {
-                min_items: min1.max(min2),
-                max_items: opt_min(max1, max2),
-                prefix_items: {
+            ) => {
+                let prefix_items = {
                     let len = prefix1.len().max(prefix2.len());
                     prefix1.resize_with(len, || items1.as_deref().cloned().unwrap_or(Schema::Any));
                     prefix2.resize_with(len, || items2.as_deref().cloned().unwrap_or(Schema::Any));
@@ -328,48 +393,96 @@ impl Schema {
                         .zip(prefix2.into_iter())
                         .map(|(item1, item2)| item1.intersect(item2, ctx))
                         .collect::<Result<Vec<_>>>()?
-                },
-                items: match (items1, items2) {
+                };
+                let items = match (items1, items2) {
                     (None, None) => None,
                     (None, Some(item)) => Some(item),
                     (Some(item), None) => Some(item),
                     (Some(item1), Some(item2)) => Some(Box::new((*item1).intersect(*item2, ctx)?)),
-                },
-            },
+                };
+                let contains = match (contains1, contains2) {
+                    (None, None) => None,
+                    (Some(c), None) | (None, Some(c)) => Some(Box::new(ArrayContains::new(
+                        ctx,
+                        c.schema,
+                        &prefix_items,
+                        items.as_deref(),
+                        c.min_contains,
+                        c.max_contains,
+                    )?)),
+                    (Some(_), Some(_)) => {
+                        bail!("intersection of arrays with multiple 'contains' is not supported")
+                    }
+                };
+                Schema::Array {
+                    min_items: min1.max(min2),
+                    max_items: opt_min(max1, max2),
+                    prefix_items,
+                    items,
+                    contains,
+                    unique_items: unique1 || unique2,
+                }
+            }
             (
                 Schema::Object {
                     properties: props1,
+                    pattern_properties: pats1,
                     additional_properties: add1,
+                    property_names: names1,
+                    min_properties: minp1,
+                    max_properties: maxp1,
                     required: req1,
                 },
                 Schema::Object {
                     properties: mut props2,
+                    pattern_properties: pats2,
                     additional_properties: add2,
+                    property_names: names2,
+                    min_properties: minp2,
+                    max_properties: maxp2,
                     required: req2,
                 },
             ) => {
                 let mut new_props = IndexMap::new();
                 for (key, prop1) in props1.into_iter() {
-                    let prop2 = props2
-                        .shift_remove(&key)
-                        .or_else(|| add2.as_deref().cloned())
-                        .unwrap_or(Schema::Any);
+                    let prop2 = match props2.shift_remove(&key) {
+                        Some(prop2) => prop2,
+                        None => unnamed_property_schema(&pats2, add2.as_deref(), &key, ctx)?,
+                    };
                     new_props.insert(key, prop1.intersect(prop2, ctx)?);
                 }
                 for (key, prop2) in props2.into_iter() {
-                    let prop1 = add1.as_deref().cloned().unwrap_or(Schema::Any);
+                    let prop1 = unnamed_property_schema(&pats1, add1.as_deref(), &key, ctx)?;
                     new_props.insert(key, prop1.intersect(prop2, ctx)?);
                 }
+                let pattern_properties = intersect_pattern_properties(
+                    pats1,
+                    add1.as_deref(),
+                    pats2,
+                    add2.as_deref(),
+                    ctx,
+                )?;
                 let mut required = req1;
                 required.extend(req2);
                 Schema::Object {
                     properties: new_props,
+                    pattern_properties,
                     additional_properties: match (add1, add2) {
                         (None, None) => None,
                         (None, Some(add2)) => Some(add2),
                         (Some(add1), None) => Some(add1),
                         (Some(add1), Some(add2)) => Some(Box::new((*add1).intersect(*add2, ctx)?)),
                     },
+                    property_names: match (names1, names2) {
+                        (None, None) => None,
+                        (None, Some(names)) => Some(names),
+                        (Some(names), None) => Some(names),
+                        (Some(names1), Some(names2)) => {
+                            Some(Box::new((*names1).intersect(*names2, ctx)?))
+                        }
+                    },
+                    min_properties: minp1.max(minp2),
+                    max_properties: opt_min(maxp1, maxp2),
                     required,
                 }
             }
@@ -420,60 +533,327 @@ impl Schema {
             (
                 Schema::Object {
                     properties: props1,
+                    pattern_properties: pats1,
                     required: req1,
                     additional_properties: add1,
+                    min_properties: minp1,
+                    max_properties: maxp1,
+                    ..
                 },
                 Schema::Object {
                     properties: props2,
+                    pattern_properties: pats2,
                     required: req2,
                     additional_properties: add2,
+                    min_properties: minp2,
+                    max_properties: maxp2,
+                    ..
                 },
-            ) => req1.union(req2).any(|key| {
-                let prop1 = props1
-                    .get(key)
-                    .unwrap_or(add1.as_deref().unwrap_or(&Schema::Any));
-                let prop2 = props2
-                    .get(key)
-                    .unwrap_or(add2.as_deref().unwrap_or(&Schema::Any));
-                prop1.is_verifiably_disjoint_from(prop2)
-            }),
+            ) => {
+                // we don't try to match patterns here; any of them may apply
+                let unnamed1 = if pats1.is_empty() {
+                    add1.as_deref().unwrap_or(&Schema::Any)
+                } else {
+                    &Schema::Any
+                };
+                let unnamed2 = if pats2.is_empty() {
+                    add2.as_deref().unwrap_or(&Schema::Any)
+                } else {
+                    &Schema::Any
+                };
+                maxp1.is_some_and(|max| max < *minp2)
+                    || maxp2.is_some_and(|max| max < *minp1)
+                    || req1.union(req2).any(|key| {
+                        let prop1 = props1.get(key).unwrap_or(unnamed1);
+                        let prop2 = props2.get(key).unwrap_or(unnamed2);
+                        prop1.is_verifiably_disjoint_from(prop2)
+                    })
+            }
             _ => {
                 // Except for in the cases above, it should suffice to check that the types are different
                 mem::discriminant(self) != mem::discriminant(other)
             }
         }
     }
+
+    /// Return a schema matching exactly the instances that don't match this one,
+    /// or None if the complement can't be expressed.
+    fn negate(self, ctx: &Context) -> Result<Option<Schema>> {
+        ctx.increment()?;
+
+        let mut options = match &self {
+            Schema::Any => return Ok(Some(Schema::false_schema())),
+            Schema::Unsatisfiable { .. } => return Ok(Some(Schema::Any)),
+            Schema::AnyOf { options } => {
+                // ¬(A ∨ B) = ¬A ∧ ¬B
+                let mut negated = vec![];
+                for option in options.iter() {
+                    match option.clone().negate(ctx)? {
+                        Some(schema) => negated.push(schema),
+                        None => return Ok(None),
+                    }
+                }
+                return Ok(Some(intersect(ctx, negated)?));
+            }
+            Schema::OneOf { .. } | Schema::Ref { .. } => return Ok(None),
+            Schema::Null => other_types(ctx, "null")?,
+            Schema::Boolean => other_types(ctx, "boolean")?,
+            Schema::LiteralBool { .. } => other_types(ctx, "boolean")?,
+            Schema::Number { .. } => other_types(ctx, "number")?,
+            Schema::String { .. } => other_types(ctx, "string")?,
+            Schema::Array { .. } => other_types(ctx, "array")?,
+            Schema::Object { .. } => other_types(ctx, "object")?,
+        };
+
+        match self {
+            Schema::LiteralBool { value } => {
+                options.push(Schema::LiteralBool { value: !value });
+            }
+            Schema::Number {
+                minimum,
+                maximum,
+                exclusive_minimum,
+                exclusive_maximum,
+                multiple_of,
+                integer,
+            } => {
+                // a single integral value (e.g. from 'const') doesn't need the integer constraint
+                let single_integer = minimum.is_some_and(|min| min.fract() == 0.0)
+                    && minimum == maximum
+                    && exclusive_minimum.is_none()
+                    && exclusive_maximum.is_none();
+                if multiple_of.is_some() || (integer && !single_integer) {
+                    return Ok(None);
+                }
+                let number =
+                    |minimum, maximum, exclusive_minimum, exclusive_maximum| Schema::Number {
+                        minimum,
+                        maximum,
+                        exclusive_minimum,
+                        exclusive_maximum,
+                        multiple_of: None,
+                        integer: false,
+                    };
+                if let Some(min) = minimum {
+                    options.push(number(None, None, None, Some(min)));
+                }
+                if let Some(emin) = exclusive_minimum {
+                    options.push(number(None, Some(emin), None, None));
+                }
+                if let Some(max) = maximum {
+                    options.push(number(None, None, Some(max), None));
+                }
+                if let Some(emax) = exclusive_maximum {
+                    options.push(number(Some(emax), None, None, None));
+                }
+            }
+            Schema::String {
+                min_length,
+                max_length,
+                regex,
+            } => {
+                let mut parts = regex.into_iter().collect::<Vec<_>>();
+                if min_length != 0 || max_length.is_some() {
+                    parts.push(RegexAst::Regex(format!(
+                        "(?s:.{{{},{}}})",
+                        min_length,
+                        max_length.map_or("".to_string(), |v| v.to_string())
+                    )));
+                }
+                if !parts.is_empty() {
+                    options.push(Schema::String {
+                        min_length: 0,
+                        max_length: None,
+                        regex: Some(RegexAst::Not(Box::new(RegexAst::And(parts)))),
+                    });
+                }
+            }
+            Schema::Array {
+                min_items,
+                max_items,
+                prefix_items,
+                items,
+                contains,
+                unique_items,
+            } => {
+                // "some item doesn't match" can't be expressed for non-prefix items
+                if contains.is_some()
+                    || unique_items
+                    || items.is_some_and(|items| !matches!(*items, Schema::Any))
+                {
+                    return Ok(None);
+                }
+                let array = |min_items, max_items, prefix_items| Schema::Array {
+                    min_items,
+                    max_items,
+                    prefix_items,
+                    items: None,
+                    contains: None,
+                    unique_items: false,
+                };
+                if min_items > 0 {
+                    options.push(array(0, Some(min_items - 1), vec![]));
+                }
+                if let Some(max) = max_items {
+                    options.push(array(max + 1, None, vec![]));
+                }
+                for (idx, item) in prefix_items.into_iter().enumerate() {
+                    if matches!(item, Schema::Any) {
+                        continue;
+                    }
+                    let Some(negated) = item.negate(ctx)? else {
+                        return Ok(None);
+                    };
+                    let mut prefix = vec![Schema::Any; idx];
+                    prefix.push(negated);
+                    options.push(array(idx as u64 + 1, None, prefix));
+                }
+            }
+            Schema::Object {
+                properties,
+                pattern_properties,
+                additional_properties,
+                property_names,
+                min_properties,
+                max_properties,
+                required,
+            } => {
+                // "some unnamed property doesn't match" can't be expressed
+                if !pattern_properties.is_empty()
+                    || property_names.is_some()
+                    || additional_properties.is_some_and(|add| !matches!(*add, Schema::Any))
+                {
+                    return Ok(None);
+                }
+                let object =
+                    |properties, min_properties, max_properties, required| Schema::Object {
+                        properties,
+                        pattern_properties: IndexMap::new(),
+                        additional_properties: None,
+                        property_names: None,
+                        min_properties,
+                        max_properties,
+                        required,
+                    };
+                if min_properties > 0 {
+                    options.push(object(
+                        IndexMap::new(),
+                        0,
+                        Some(min_properties - 1),
+                        IndexSet::new(),
+                    ));
+                }
+                if let Some(max) = max_properties {
+                    options.push(object(IndexMap::new(), max + 1, None, IndexSet::new()));
+                }
+                for name in required.iter() {
+                    // a missing required property
+                    let properties = IndexMap::from([(name.clone(), Schema::false_schema())]);
+                    options.push(object(properties, 0, None, IndexSet::new()));
+                }
+                for (name, schema) in properties.into_iter() {
+                    if matches!(schema, Schema::Any) {
+                        continue;
+                    }
+                    // a present property with a non-matching value
+                    let Some(negated) = schema.negate(ctx)? else {
+                        return Ok(None);
+                    };
+                    let required = IndexSet::from([name.clone()]);
+                    let properties = IndexMap::from([(name, negated)]);
+                    options.push(object(properties, 0, None, required));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(Some(Schema::AnyOf { options }.normalize()))
+    }
+}
+
+/// Schemas for all the JSON types other than the given one.
+fn other_types(ctx: &Context, except: &str) -> Result<Vec<Schema>> {
+    TYPES
+        .iter()
+        .filter(|tp| **tp != except)
+        .map(|tp| compile_type(ctx, tp, &HashMap::default()))
+        .collect()
 }
 
 #[derive(Clone)]
 pub struct SchemaBuilderOptions {
     pub max_size: usize,
+    pub custom_formats: HashMap<String, String>,
+    /// Drop unsupported keywords (with a warning) instead of failing.
+    pub lenient: bool,
 }
 
 impl Default for SchemaBuilderOptions {
     fn default() -> Self {
-        SchemaBuilderOptions { max_size: 50_000 }
+        SchemaBuilderOptions {
+            max_size: 50_000,
+            custom_formats: HashMap::default(),
+            lenient: false,
+        }
     }
 }
 
 pub fn build_schema(
     contents: Value,
     retriever: Option<RetrieveWrapper>,
-) -> Result<(Schema, HashMap<String, Schema>)> {
+    options: SchemaBuilderOptions,
+) -> Result<(Schema, HashMap<String, Schema>, Vec<GrammarWarning>)> {
     if let Some(b) = contents.as_bool() {
         if b {
-            return Ok((Schema::Any, HashMap::default()));
+            return Ok((Schema::Any, HashMap::default(), vec![]));
         } else {
-            return Ok((Schema::false_schema(), HashMap::default()));
+            return Ok((Schema::false_schema(), HashMap::default(), vec![]));
         }
     }
 
     let pre_ctx = PreContext::new(contents, retriever)?;
-    let ctx = Context::new(&pre_ctx)?;
+    let ctx = Context::new(&pre_ctx, options)?;
 
     let root_resource = ctx.lookup_resource(&pre_ctx.base_uri)?;
     let schema = compile_resource(&ctx, root_resource)?;
-    Ok((schema, ctx.take_defs()))
+    let warnings = ctx
+        .take_ignored_keywords()
+        .into_iter()
+        .map(|(addr, keyword)| GrammarWarning {
+            message: format!("unsupported keyword {:?} ignored", keyword),
+            // keywords in retrieved schemas are not found in the root one
+            json_pointer: rev_path_to(root_resource.contents(), addr).map(|path| {
+                // skip the keyword itself
+                path.iter()
+                    .skip(1)
+                    .rev()
+                    .map(|p| format!("/{}", p.replace('~', "~0").replace('/', "~1")))
+                    .collect()
+            }),
+            keyword: Some(keyword),
+        })
+        .collect();
+    Ok((schema, ctx.take_defs(), warnings))
+}
+
+/// Find the path (in reverse order) to the value at given address inside of `root`.
+fn rev_path_to(root: &Value, addr: usize) -> Option<Vec<String>> {
+    if root as *const Value as usize == addr {
+        return Some(vec![]);
+    }
+    match root {
+        Value::Object(obj) => obj.iter().find_map(|(k, v)| {
+            let mut path = rev_path_to(v, addr)?;
+            path.push(k.clone());
+            Some(path)
+        }),
+        Value::Array(arr) => arr.iter().enumerate().find_map(|(i, v)| {
+            let mut path = rev_path_to(v, addr)?;
+            path.push(i.to_string());
+            Some(path)
+        }),
+        _ => None,
+    }
 }
 
 fn compile_resource(ctx: &Context, resource: ResourceRef) -> Result<Schema> {
@@ -516,20 +896,69 @@ fn only_meta_and_annotations(schemadict: &HashMap<&str, &Value>) -> bool {
 fn compile_contents_map(ctx: &Context, mut schemadict: HashMap<&str, &Value>) -> Result<Schema> {
     ctx.increment()?;
 
-    // We don't need to compile the schema if it's just meta and annotations
-    if only_meta_and_annotations(&schemadict) {
-        return Ok(Schema::Any);
-    }
-
-    // Check for unimplemented keys and bail if any are found
+    // Check for unimplemented keys and bail if any are found (or drop them in lenient mode)
     let mut unimplemented_keys = schemadict
         .keys()
+        .copied()
         .filter(|k| !ctx.is_valid_keyword(k))
         .collect::<Vec<_>>();
     if !unimplemented_keys.is_empty() {
         // ensure consistent order for tests
         unimplemented_keys.sort();
-        bail!("Unimplemented keys: {:?}", unimplemented_keys);
+        if !ctx.options.lenient {
+            bail!("Unimplemented keys: {:?}", unimplemented_keys);
+        }
+        for k in unimplemented_keys {
+            ctx.record_ignored_keyword(k, schemadict.remove(k).unwrap());
+        }
+    }
+
+    // We don't need to compile the schema if it's just meta and annotations
+    if only_meta_and_annotations(&schemadict) {
+        return Ok(Schema::Any);
+    }
+
+    if let Some(if_schema) = schemadict.remove("if") {
+        let then_schema = schemadict.remove("then");
+        let else_schema = schemadict.remove("else");
+        let siblings = compile_contents_map(ctx, schemadict)?;
+        // Short-circuit if schema is already unsatisfiable
+        if matches!(siblings, Schema::Unsatisfiable { .. }) {
+            return Ok(siblings);
+        }
+        let compile_opt = |value: Option<&Value>| match value {
+            None => Ok(Schema::Any),
+            Some(value) => compile_resource(ctx, ctx.as_resource_ref(value)),
+        };
+        let conditional = compile_conditional(
+            ctx,
+            compile_opt(Some(if_schema))?,
+            compile_opt(then_schema)?,
+            compile_opt(else_schema)?,
+        )?;
+        return siblings.intersect(conditional, ctx);
+    }
+
+    let dependencies = ["dependentRequired", "dependentSchemas", "dependencies"]
+        .into_iter()
+        .filter_map(|key| schemadict.remove(key).map(|value| (key, value)))
+        .collect::<Vec<_>>();
+    if !dependencies.is_empty() {
+        let siblings = compile_contents_map(ctx, schemadict)?;
+        // Short-circuit if schema is already unsatisfiable
+        if matches!(siblings, Schema::Unsatisfiable { .. }) {
+            return Ok(siblings);
+        }
+        let mut schemas = vec![siblings];
+        for (key, value) in dependencies {
+            let value = value
+                .as_object()
+                .ok_or_else(|| anyhow!("{} must be an object", key))?;
+            for (name, dependency) in value.iter() {
+                schemas.push(compile_dependency(ctx, key, name, dependency)?);
+            }
+        }
+        return intersect(ctx, schemas);
     }
 
     if let Some(instance) = schemadict.remove("const") {
@@ -613,19 +1042,30 @@ fn compile_contents_map(ctx: &Context, mut schemadict: HashMap<&str, &Value>) ->
         return Ok(Schema::OneOf { options }.normalize());
     }
 
-    if let Some(reference) = schemadict.remove("$ref") {
-        let reference = reference
-            .as_str()
-            .ok_or_else(|| anyhow!("$ref must be a string, got {}", limited_str(reference)))?
-            .to_string();
+    for (keyword, kind) in [
+        ("$ref", RefKind::Ref),
+        ("$dynamicRef", RefKind::DynamicRef),
+        ("$recursiveRef", RefKind::RecursiveRef),
+    ] {
+        if let Some(reference) = schemadict.remove(keyword) {
+            let reference = reference
+                .as_str()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} must be a string, got {}",
+                        keyword,
+                        limited_str(reference)
+                    )
+                })?
+                .to_string();
 
-        let uri: String = ctx.normalize_ref(&reference)?;
-        let siblings = compile_contents_map(ctx, schemadict)?;
-        if matches!(siblings, Schema::Any) {
-            define_ref(ctx, &uri)?;
-            return Ok(Schema::Ref { uri });
-        } else {
-            return intersect_ref(ctx, &uri, siblings, false);
+            let uri = define_ref(ctx, kind, &reference)?;
+            let siblings = compile_contents_map(ctx, schemadict)?;
+            if matches!(siblings, Schema::Any) {
+                return Ok(Schema::Ref { uri });
+            } else {
+                return intersect_ref(ctx, &uri, siblings, false);
+            }
         }
     }
 
@@ -652,18 +1092,94 @@ fn compile_contents_map(ctx: &Context, mut schemadict: HashMap<&str, &Value>) ->
     Ok(Schema::AnyOf { options })
 }
 
-fn define_ref(ctx: &Context, ref_uri: &str) -> Result<()> {
-    if !ctx.been_seen(ref_uri) {
-        ctx.mark_seen(ref_uri);
-        let resource = ctx.lookup_resource(ref_uri)?;
-        let resolved_schema = compile_resource(ctx, resource)?;
-        ctx.insert_ref(ref_uri, resolved_schema);
+/// Rewrite 'if A then B else C' as (A ∧ B) ∨ (¬A ∧ C).
+fn compile_conditional(
+    ctx: &Context,
+    if_schema: Schema,
+    then_schema: Schema,
+    else_schema: Schema,
+) -> Result<Schema> {
+    if matches!(then_schema, Schema::Any) {
+        // A ∨ (¬A ∧ C) = A ∨ C, so no negation is needed
+        return Ok(Schema::AnyOf {
+            options: vec![if_schema, else_schema],
+        }
+        .normalize());
+    }
+    let not_if = if_schema.clone().negate(ctx)?.ok_or_else(|| {
+        anyhow!("'then' is only supported when the negation of the 'if' schema can be expressed")
+    })?;
+    Ok(Schema::AnyOf {
+        options: vec![
+            if_schema.intersect(then_schema, ctx)?,
+            not_if.intersect(else_schema, ctx)?,
+        ],
     }
-    Ok(())
+    .normalize())
+}
+
+/// A 'dependentRequired' or 'dependentSchemas' entry holds when the named
+/// property is absent, or when the dependency is satisfied.
+fn compile_dependency(ctx: &Context, key: &str, name: &str, dependency: &Value) -> Result<Schema> {
+    let object = |properties, required| Schema::Object {
+        properties,
+        pattern_properties: IndexMap::new(),
+        additional_properties: None,
+        property_names: None,
+        min_properties: 0,
+        max_properties: None,
+        required,
+    };
+    let satisfied = match dependency {
+        // 'dependencies' (draft 7 and earlier) mixes both forms
+        Value::Array(names) if key != "dependentSchemas" => {
+            let required = names
+                .iter()
+                .map(|item| {
+                    item.as_str().map(|s| s.to_string()).ok_or_else(|| {
+                        anyhow!(
+                            "Expected string in '{}' for {:?}, got {}",
+                            key,
+                            name,
+                            limited_str(item)
+                        )
+                    })
+                })
+                .collect::<Result<IndexSet<String>>>()?;
+            object(IndexMap::new(), required)
+        }
+        _ if key == "dependentRequired" => {
+            bail!(
+                "Expected array in 'dependentRequired' for {:?}, got {}",
+                name,
+                limited_str(dependency)
+            )
+        }
+        _ => compile_resource(ctx, ctx.as_resource_ref(dependency))?,
+    };
+    let mut options = other_types(ctx, "object")?;
+    options.push(object(
+        IndexMap::from([(name.to_string(), Schema::false_schema())]),
+        IndexSet::new(),
+    ));
+    options.push(satisfied);
+    Ok(Schema::AnyOf { options }.normalize())
+}
+
+/// Compile the target of a reference (unless already done) and return
+/// the key it's stored under.
+fn define_ref(ctx: &Context, kind: RefKind, reference: &str) -> Result<String> {
+    let ref_uri = ctx.ref_key(kind, reference)?;
+    if !ctx.been_seen(&ref_uri) {
+        ctx.mark_seen(&ref_uri);
+        let (resource, ref_ctx) = ctx.lookup_ref(kind, reference)?;
+        let resolved_schema = compile_resource(&ref_ctx, resource)?;
+        ctx.insert_ref(&ref_uri, resolved_schema);
+    }
+    Ok(ref_uri)
 }
 
 fn intersect_ref(ctx: &Context, ref_uri: &str, schema: Schema, ref_first: bool) -> Result<Schema> {
-    define_ref(ctx, ref_uri)?;
     let resolved_schema = ctx
         .get_ref_cloned(ref_uri)
         // The ref might not have been defined if we're in a recursive loop and every ref in the loop
@@ -718,6 +1234,8 @@ fn compile_const(instance: &Value) -> Result<Schema> {
                 max_items: Some(prefix_items.len() as u64),
                 prefix_items,
                 items: Some(Box::new(Schema::false_schema())),
+                contains: None,
+                unique_items: false,
             })
         }
         Value::Object(mapping) => {
@@ -728,7 +1246,11 @@ fn compile_const(instance: &Value) -> Result<Schema> {
             let required = properties.keys().cloned().collect();
             Ok(Schema::Object {
                 properties,
+                pattern_properties: IndexMap::new(),
                 additional_properties: Some(Box::new(Schema::false_schema())),
+                property_names: None,
+                min_properties: 0,
+                max_properties: None,
                 required,
             })
         }
@@ -750,8 +1272,10 @@ fn compile_type(ctx: &Context, tp: &str, schema: &HashMap<&str, &Value>) -> Resu
             get("exclusiveMaximum"),
             tp == "integer",
             get("multipleOf"),
+            get("format"),
         ),
         "string" => compile_string(
+            ctx,
             get("minLength"),
             get("maxLength"),
             get("pattern"),
@@ -764,11 +1288,19 @@ fn compile_type(ctx: &Context, tp: &str, schema: &HashMap<&str, &Value>) -> Resu
             get("prefixItems"),
             get("items"),
             get("additionalItems"),
+            get("uniqueItems"),
+            get("contains"),
+            get("minContains"),
+            get("maxContains"),
         ),
         "object" => compile_object(
             ctx,
             get("properties"),
+            get("patternProperties"),
             get("additionalProperties"),
+            get("propertyNames"),
+            get("minProperties"),
+            get("maxProperties"),
             get("required"),
         ),
         _ => bail!("Invalid type: {}", tp),
@@ -782,6 +1314,7 @@ fn compile_numeric(
     exclusive_maximum: Option<&Value>,
     integer: bool,
     multiple_of: Option<&Value>,
+    format: Option<&Value>,
 ) -> Result<Schema> {
     let minimum = match minimum {
         None => None,
@@ -832,6 +1365,20 @@ fn compile_numeric(
             Some(Decimal::try_from(f.abs())?)
         }
     };
+    // OpenAPI numeric formats; i64::MAX is not representable as f64,
+    // so int64 uses the exact exclusive bound of 2^63 instead;
+    // float and double are left unbounded, as any bound would rule out exponent notation
+    let two_63 = -(i64::MIN as f64);
+    let (format_minimum, format_maximum, format_exclusive_maximum, format_integer) =
+        match format.and_then(|f| f.as_str()) {
+            Some("int32") => (Some(i32::MIN as f64), Some(i32::MAX as f64), None, true),
+            Some("int64") => (Some(i64::MIN as f64), None, Some(two_63), true),
+            _ => (None, None, None, false),
+        };
+    let minimum = opt_max(minimum, format_minimum);
+    let maximum = opt_min(maximum, format_maximum);
+    let exclusive_maximum = opt_min(exclusive_maximum, format_exclusive_maximum);
+    let integer = integer || format_integer;
     Ok(Schema::Number {
         minimum,
         maximum,
@@ -842,7 +1389,11 @@ fn compile_numeric(
     })
 }
 
-fn pattern_to_regex(pattern: &str) -> RegexAst {
+pub(crate) fn pattern_to_regex(pattern: &str) -> RegexAst {
+    RegexAst::Regex(pattern_to_regex_str(pattern))
+}
+
+fn pattern_to_regex_str(pattern: &str) -> String {
     let left_anchored = pattern.starts_with('^');
     let right_anchored = pattern.ends_with('$');
     let trimmed = pattern.trim_start_matches('^').trim_end_matches('$');
@@ -857,10 +1408,82 @@ fn pattern_to_regex(pattern: &str) -> RegexAst {
     if !right_anchored {
         result.push_str(".*");
     }
-    RegexAst::Regex(result)
+    result
+}
+
+fn matching_pattern_schemas(
+    pattern_properties: &IndexMap<String, Schema>,
+    key: &str,
+) -> Result<Vec<Schema>> {
+    let mut matching = vec![];
+    for (pattern, schema) in pattern_properties.iter() {
+        let mut rx = derivre::Regex::new(&pattern_to_regex_str(pattern))
+            .map_err(|e| anyhow!("invalid pattern {:?}: {}", pattern, e))?;
+        if rx.is_match(key) {
+            matching.push(schema.clone());
+        }
+    }
+    Ok(matching)
+}
+
+/// Schema for the value of a property that is not listed in `properties`:
+/// the intersection of all matching `patternProperties`, or `additionalProperties`
+/// if no pattern matches.
+fn unnamed_property_schema(
+    pattern_properties: &IndexMap<String, Schema>,
+    additional_properties: Option<&Schema>,
+    key: &str,
+    ctx: &Context,
+) -> Result<Schema> {
+    let matching = matching_pattern_schemas(pattern_properties, key)?;
+    if matching.is_empty() {
+        Ok(additional_properties.cloned().unwrap_or(Schema::Any))
+    } else {
+        intersect(ctx, matching)
+    }
+}
+
+fn intersect_pattern_properties(
+    mut pats1: IndexMap<String, Schema>,
+    add1: Option<&Schema>,
+    mut pats2: IndexMap<String, Schema>,
+    add2: Option<&Schema>,
+    ctx: &Context,
+) -> Result<IndexMap<String, Schema>> {
+    // Keys matching a pattern on one side and no pattern on the other side
+    // are subject to additionalProperties of the other side.
+    if pats2.is_empty() {
+        if let Some(add2) = add2 {
+            for schema in pats1.values_mut() {
+                *schema = mem::replace(schema, Schema::Any).intersect(add2.clone(), ctx)?;
+            }
+        }
+        return Ok(pats1);
+    }
+    if pats1.is_empty() {
+        if let Some(add1) = add1 {
+            for schema in pats2.values_mut() {
+                *schema = add1
+                    .clone()
+                    .intersect(mem::replace(schema, Schema::Any), ctx)?;
+            }
+        }
+        return Ok(pats2);
+    }
+    if pats1.len() != pats2.len() || pats1.keys().any(|k| !pats2.contains_key(k)) {
+        bail!("intersection of objects with different patternProperties is not supported");
+    }
+    pats1
+        .into_iter()
+        .map(|(pattern, schema1)| {
+            let schema2 = pats2.shift_remove(&pattern).unwrap();
+            Ok((pattern, schema1.intersect(schema2, ctx)?))
+        })
+        .collect()
 }
 
 fn compile_string(
+    ctx: &Context,
     min_length: Option<&Value>,
     max_length: Option<&Value>,
     pattern: Option<&Value>,
@@ -896,8 +1519,14 @@ fn compile_string(
                 .as_str()
                 .ok_or_else(|| anyhow!("Expected string for 'format', got {}", limited_str(val)))?
                 .to_string();
-            let fmt = lookup_format(&key).ok_or_else(|| anyhow!("Unknown format: {}", key))?;
-            pattern_to_regex(fmt)
+            if let Some(rx) = ctx.options.custom_formats.get(&key) {
+                // custom formats always match the whole string
+                let rx = rx.trim_start_matches('^').trim_end_matches('$');
+                pattern_to_regex(&format!("^{}$", rx))
+            } else {
+                let fmt = lookup_format(&key).ok_or_else(|| anyhow!("Unknown format: {}", key))?;
+                pattern_to_regex(fmt)
+            }
         }),
     };
     let regex = match (pattern_rx, format_rx) {
@@ -913,6 +1542,7 @@ fn compile_string(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compile_array(
     ctx: &Context,
     min_items: Option<&Value>,
@@ -920,6 +1550,10 @@ fn compile_array(
     prefix_items: Option<&Value>,
     items: Option<&Value>,
     additional_items: Option<&Value>,
+    unique_items: Option<&Value>,
+    contains: Option<&Value>,
+    min_contains: Option<&Value>,
+    max_contains: Option<&Value>,
 ) -> Result<Schema> {
     let (prefix_items, items) = {
         // Note that draft detection falls back to Draft202012 if the draft is unknown, so let's relax the draft constraint a bit
@@ -964,21 +1598,63 @@ fn compile_array(
         None => None,
         Some(val) => Some(Box::new(compile_resource(ctx, ctx.as_resource_ref(val))?)),
     };
+    let unique_items = match unique_items {
+        None => false,
+        Some(val) => val
+            .as_bool()
+            .ok_or_else(|| anyhow!("Expected bool for 'uniqueItems', got {}", limited_str(val)))?,
+    };
+    let min_contains = match min_contains {
+        None => 1,
+        Some(val) => val
+            .as_u64()
+            .ok_or_else(|| anyhow!("Expected u64 for 'minContains', got {}", limited_str(val)))?,
+    };
+    let max_contains =
+        match max_contains {
+            None => None,
+            Some(val) => Some(val.as_u64().ok_or_else(|| {
+                anyhow!("Expected u64 for 'maxContains', got {}", limited_str(val))
+            })?),
+        };
+    let contains = match contains {
+        // minContains: 0 without maxContains doesn't constrain anything
+        Some(_) if min_contains == 0 && max_contains.is_none() => None,
+        None => None,
+        Some(val) => {
+            let schema = compile_resource(ctx, ctx.as_resource_ref(val))?;
+            Some(Box::new(ArrayContains::new(
+                ctx,
+                schema,
+                &prefix_items,
+                items.as_deref(),
+                min_contains,
+                max_contains,
+            )?))
+        }
+    };
     Ok(Schema::Array {
         min_items,
         max_items,
         prefix_items,
         items,
+        contains,
+        unique_items,
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compile_object(
     ctx: &Context,
     properties: Option<&Value>,
+    pattern_properties: Option<&Value>,
     additional_properties: Option<&Value>,
+    property_names: Option<&Value>,
+    min_properties: Option<&Value>,
+    max_properties: Option<&Value>,
     required: Option<&Value>,
 ) -> Result<Schema> {
-    let properties = match properties {
+    let mut properties = match properties {
         None => IndexMap::new(),
         Some(val) => val
             .as_object()
@@ -987,10 +1663,49 @@ fn compile_object(
             .map(|(k, v)| compile_resource(ctx, ctx.as_resource_ref(v)).map(|v| (k.clone(), v)))
             .collect::<Result<IndexMap<String, Schema>>>()?,
     };
+    let pattern_properties = match pattern_properties {
+        None => IndexMap::new(),
+        Some(val) => val
+            .as_object()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Expected object for 'patternProperties', got {}",
+                    limited_str(val)
+                )
+            })?
+            .iter()
+            .map(|(k, v)| compile_resource(ctx, ctx.as_resource_ref(v)).map(|v| (k.clone(), v)))
+            .collect::<Result<IndexMap<String, Schema>>>()?,
+    };
     let additional_properties = match additional_properties {
         None => None,
         Some(val) => Some(Box::new(compile_resource(ctx, ctx.as_resource_ref(val))?)),
     };
+    let property_names = match property_names {
+        None => None,
+        Some(val) => {
+            // property names are always strings
+            let names = compile_resource(ctx, ctx.as_resource_ref(val))?;
+            let string = Schema::String {
+                min_length: 0,
+                max_length: None,
+                regex: None,
+            };
+            Some(Box::new(names.intersect(string, ctx)?))
+        }
+    };
+    let min_properties = match min_properties {
+        None => 0,
+        Some(val) => val
+            .as_u64()
+            .ok_or_else(|| anyhow!("Expected u64 for 'minProperties', got {}", limited_str(val)))?,
+    };
+    let max_properties = match max_properties {
+        None => None,
+        Some(val) => Some(val.as_u64().ok_or_else(|| {
+            anyhow!("Expected u64 for 'maxProperties', got {}", limited_str(val))
+        })?),
+    };
     let required = match required {
         None => IndexSet::new(),
         Some(val) => val
@@ -1009,9 +1724,33 @@ fn compile_object(
             })
             .collect::<Result<IndexSet<String>>>()?,
     };
+    // Both 'properties' and matching 'patternProperties' apply to named properties;
+    // required properties that only match patterns are added as named properties.
+    if !pattern_properties.is_empty() {
+        for (name, schema) in properties.iter_mut() {
+            let mut matching = matching_pattern_schemas(&pattern_properties, name)?;
+            if !matching.is_empty() {
+                matching.insert(0, mem::replace(schema, Schema::Any));
+                *schema = intersect(ctx, matching)?;
+            }
+        }
+        for name in required.iter() {
+            if properties.contains_key(name) {
+                continue;
+            }
+            let matching = matching_pattern_schemas(&pattern_properties, name)?;
+            if !matching.is_empty() {
+                properties.insert(name.clone(), intersect(ctx, matching)?);
+            }
+        }
+    }
     Ok(Schema::Object {
         properties,
+        pattern_properties,
         additional_properties,
+        property_names,
+        min_properties,
+        max_properties,
         required,
     })
 }
@@ -1073,7 +1812,7 @@ fn opt_min<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
 mod test_retriever {
     use crate::json::{Retrieve, RetrieveWrapper};
 
-    use super::{build_schema, Schema};
+    use super::{build_schema, Schema, SchemaBuilderOptions};
     use serde_json::{json, Value};
     use std::{fmt, sync::Arc};
 
@@ -1117,7 +1856,8 @@ mod test_retriever {
             .collect(),
         };
         let wrapper = RetrieveWrapper::new(Arc::new(retriever));
-        let (schema, defs) = build_schema(schema, Some(wrapper)).unwrap();
+        let (schema, defs, _warnings) =
+            build_schema(schema, Some(wrapper), SchemaBuilderOptions::default()).unwrap();
         match schema {
             Schema::Ref { uri } => {
                 assert_eq!(uri, key);
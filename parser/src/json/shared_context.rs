@@ -1,15 +1,37 @@
 use crate::{HashMap, HashSet};
 use anyhow::{bail, Result};
+use serde_json::Value;
 
 use super::{
     context::Context,
     schema::{Schema, IMPLEMENTED, META_AND_ANNOTATIONS},
 };
 
+/// Which keyword a reference comes from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RefKind {
+    Ref,
+    DynamicRef,
+    RecursiveRef,
+}
+
+impl RefKind {
+    /// Prefix of definition keys for references of this kind.
+    pub fn key_prefix(&self) -> &'static str {
+        match self {
+            RefKind::Ref => "",
+            RefKind::DynamicRef => "$dynamicRef:",
+            RefKind::RecursiveRef => "$recursiveRef:",
+        }
+    }
+}
+
 pub struct SharedContext {
     defs: HashMap<String, Schema>,
     seen: HashSet<String>,
     n_compiled: usize,
+    // address of the keyword's value and the keyword
+    ignored_keywords: Vec<(usize, String)>,
 }
 
 impl SharedContext {
@@ -18,6 +40,7 @@ impl SharedContext {
             defs: HashMap::default(),
             seen: HashSet::default(),
             n_compiled: 0,
+            ignored_keywords: vec![],
         }
     }
 }
@@ -64,4 +87,18 @@ impl Context<'_> {
     pub fn take_defs(&self) -> HashMap<String, Schema> {
         std::mem::take(&mut self.shared.borrow_mut().defs)
     }
+
+    /// Record that `keyword` (with given value) was dropped in lenient mode.
+    pub fn record_ignored_keyword(&self, keyword: &str, value: &Value) {
+        let entry = (value as *const Value as usize, keyword.to_string());
+        let mut shared = self.shared.borrow_mut();
+        // the same sub-schema may be compiled more than once
+        if !shared.ignored_keywords.contains(&entry) {
+            shared.ignored_keywords.push(entry);
+        }
+    }
+
+    pub fn take_ignored_keywords(&self) -> Vec<(usize, String)> {
+        std::mem::take(&mut self.shared.borrow_mut().ignored_keywords)
+    }
 }
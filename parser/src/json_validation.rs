@@ -30,3 +30,13 @@ pub fn validate_schema(schema: &Value) -> Result<()> {
         .validate(schema)
         .map_err(|e| anyhow!("Invalid schema: {}", e))
 }
+
+pub fn validate_value(schema: &Value, value: &Value) -> Result<()> {
+    let validator = Validator::options()
+        .with_retriever(DummyResolver {})
+        .build(schema)
+        .map_err(|e| anyhow!("Invalid schema: {}", e))?;
+    validator
+        .validate(value)
+        .map_err(|e| anyhow!("JSON does not match schema: {}", e))
+}
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use crate::api::RegexExt;
 
 use super::lexer::Location;
@@ -69,8 +71,7 @@ pub enum Statement {
     Declare(Vec<String>),
 }
 
-/// Represents parameters for a rule.
-#[allow(dead_code)]
+/// Represents parameters for a rule (which makes it a template).
 #[derive(Debug, Clone)]
 pub struct RuleParams(pub Vec<String>);
 
@@ -119,6 +120,10 @@ pub enum Atom {
     Group(Expansions),
     Maybe(Expansions),
     Value(Value),
+    /// `a & b`; only valid in terminals
+    Intersection(Vec<Expansion>),
+    /// `~a`; only valid in terminals
+    Complement(Box<Expr>),
 }
 
 /// Represents different values in the grammar.
@@ -132,11 +137,36 @@ pub enum Value {
     SpecialToken(String),
     Json(serde_json::Value),
     RegexExt(RegexExt),
-    #[allow(dead_code)]
-    TemplateUsage {
-        name: String,
-        values: Vec<Value>,
-    },
+    TemplateUsage { name: String, values: Vec<Value> },
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::LiteralRange(a, b) => write!(f, "{:?}..{:?}", a, b),
+            Value::Name(n) => write!(f, "{}", n),
+            Value::LiteralString(s, flags) => write!(f, "{:?}{}", s, flags),
+            Value::LiteralRegex(rx, flags) => write!(f, "/{}/{}", rx, flags),
+            Value::GrammarRef(g) => write!(f, "{}", g),
+            Value::SpecialToken(s) => write!(f, "{}", s),
+            Value::Json(v) => write!(f, "%json {}", v),
+            Value::RegexExt(r) => write!(
+                f,
+                "%regex {}",
+                serde_json::to_string(r).map_err(|_| std::fmt::Error)?
+            ),
+            Value::TemplateUsage { name, values } => {
+                write!(f, "{}{{", name)?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
 }
 
 /// Represents an operator.
@@ -0,0 +1,187 @@
+use anyhow::{anyhow, bail, Result};
+use derivre::{RegexAst, RegexBuilder};
+use regex_syntax::ast::{parse::Parser, Ast, GroupKind};
+
+use super::compiler::lark_regex_quote;
+
+enum Piece {
+    Regex(String),
+    Capture(String, Vec<Piece>),
+}
+
+fn has_named_group(ast: &Ast) -> bool {
+    match ast {
+        Ast::Group(g) => matches!(g.kind, GroupKind::CaptureName { .. }) || has_named_group(&g.ast),
+        Ast::Repetition(r) => has_named_group(&r.ast),
+        Ast::Alternation(a) => a.asts.iter().any(has_named_group),
+        Ast::Concat(c) => c.asts.iter().any(has_named_group),
+        _ => false,
+    }
+}
+
+fn push_regex(out: &mut Vec<Piece>, rx: String) {
+    if let Some(Piece::Regex(prev)) = out.last_mut() {
+        prev.push_str(&rx);
+    } else {
+        out.push(Piece::Regex(rx));
+    }
+}
+
+/// Split `ast` into regexes and named groups; only groups that are not
+/// under repetition, alternation or flags are lifted.
+fn lift(ast: &Ast, out: &mut Vec<Piece>) -> Result<()> {
+    if !has_named_group(ast) {
+        push_regex(out, ast.to_string());
+        return Ok(());
+    }
+    match ast {
+        Ast::Group(g) => match &g.kind {
+            GroupKind::CaptureName { name, .. } => {
+                let mut inner = vec![];
+                lift(&g.ast, &mut inner)?;
+                out.push(Piece::Capture(name.name.clone(), inner));
+            }
+            GroupKind::NonCapturing(flags) if !flags.items.is_empty() => {
+                push_regex(out, ast.to_string());
+            }
+            _ if matches!(*g.ast, Ast::Concat(_) | Ast::Group(_)) => lift(&g.ast, out)?,
+            _ => push_regex(out, ast.to_string()),
+        },
+        Ast::Concat(c) => {
+            if let Some(flags) = c.asts.iter().find(|a| matches!(a, Ast::Flags(_))) {
+                bail!(
+                    "inline flags {} cannot be used next to capture groups; use (?flags:...) instead",
+                    flags
+                );
+            }
+            for a in &c.asts {
+                lift(a, out)?;
+            }
+        }
+        _ => push_regex(out, ast.to_string()),
+    }
+    Ok(())
+}
+
+/// Collect the lexemes in `pieces`, with the innermost capture enclosing each.
+fn leaves<'a>(
+    pieces: &'a [Piece],
+    group: Option<&'a str>,
+    out: &mut Vec<(&'a str, Option<&'a str>)>,
+) {
+    for p in pieces {
+        match p {
+            Piece::Regex(rx) => out.push((rx, group)),
+            Piece::Capture(name, inner) if inner.is_empty() => out.push(("", Some(name))),
+            Piece::Capture(name, inner) => leaves(inner, Some(name), out),
+        }
+    }
+}
+
+/// Check if some match of `a`, followed by the first byte of a match of `b`,
+/// is still a prefix of a match of `a`; the greedy lexer would then not stop
+/// where the regex would.
+fn can_extend_into(builder: &mut RegexBuilder, a: &str, b: &str) -> Result<bool> {
+    let ea = builder.mk_regex(a)?;
+    let eb = builder.mk_regex(b)?;
+    let mut rx_b = builder.to_regex(eb);
+    let init = rx_b.initial_state();
+    let mut first = vec![0u32; 8];
+    for byte in 0..=255u8 {
+        if !rx_b.transition(init, byte).is_dead() {
+            first[byte as usize / 32] |= 1 << (byte % 32);
+        }
+    }
+    if first.iter().all(|&w| w == 0) {
+        return Ok(false);
+    }
+    let extended = RegexAst::Concat(vec![
+        RegexAst::ExprRef(ea),
+        RegexAst::ByteSet(first),
+        RegexAst::Repeat(Box::new(RegexAst::ByteSet(vec![u32::MAX; 8])), 0, u32::MAX),
+    ]);
+    let expr = builder.mk(&RegexAst::And(vec![extended, RegexAst::ExprRef(ea)]))?;
+    let mut regex = builder
+        .to_regex_limited(expr, 10_000)
+        .map_err(|_| anyhow!("unable to check if /{}/ can extend into /{}/", a, b))?;
+    Ok(!regex.always_empty())
+}
+
+/// Each lexeme is matched greedily, so it must not be able to extend into
+/// whatever lexeme can follow it.
+fn check_overlaps(pieces: &[Piece]) -> Result<()> {
+    let mut lexemes = vec![];
+    leaves(pieces, None, &mut lexemes);
+    let mut builder = RegexBuilder::new();
+    for (i, (a, group_a)) in lexemes.iter().enumerate() {
+        for (b, group_b) in &lexemes[i + 1..] {
+            if can_extend_into(&mut builder, a, b)? {
+                let group = group_b.or(*group_a).unwrap_or_default();
+                bail!(
+                    "capture group {:?} cannot be split into lexemes: /{}/ can extend into /{}/",
+                    group,
+                    a,
+                    b
+                );
+            }
+            let eb = builder.mk_regex(b)?;
+            if !builder.is_nullable(eb) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct LarkWriter {
+    rules: Vec<String>,
+}
+
+impl LarkWriter {
+    fn expansion(&mut self, pieces: &[Piece]) -> String {
+        let mut res = vec![];
+        for p in pieces {
+            match p {
+                Piece::Regex(rx) if rx.is_empty() => res.push("\"\"".to_string()),
+                Piece::Regex(rx) => res.push(format!("/{}/", lark_regex_quote(rx))),
+                Piece::Capture(name, inner) => {
+                    let rule_name = format!("capture_{}", self.rules.len());
+                    self.rules.push(String::new());
+                    let idx = self.rules.len() - 1;
+                    let body = self.expansion(inner);
+                    self.rules[idx] = format!(
+                        "{}[capture={}]: {}",
+                        rule_name,
+                        serde_json::to_string(name).unwrap(),
+                        body
+                    );
+                    res.push(rule_name);
+                }
+            }
+        }
+        if res.is_empty() {
+            "\"\"".to_string()
+        } else {
+            res.join(" ")
+        }
+    }
+}
+
+/// Convert a regex into a Lark grammar, where named groups (`(?P<name>...)`)
+/// in the top-level concatenation become rules with `capture="name"`.
+/// Each part of the regex becomes a separate lexeme, and is thus matched greedily;
+/// an error is returned when this would change the accepted language.
+pub fn regex_with_captures_to_lark(rx: &str) -> Result<String> {
+    let ast = Parser::new().parse(rx)?;
+    let mut pieces = vec![];
+    lift(&ast, &mut pieces)?;
+    check_overlaps(&pieces)?;
+    let mut writer = LarkWriter { rules: vec![] };
+    let start = writer.expansion(&pieces);
+    let mut lark = format!("start: {}\n", start);
+    for r in writer.rules {
+        lark.push_str(&r);
+        lark.push('\n');
+    }
+    Ok(lark)
+}
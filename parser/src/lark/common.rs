@@ -2,6 +2,12 @@
 
 use anyhow::Result;
 
+use crate::json::{
+    compiler::CHAR_REGEX,
+    formats::lookup_format,
+    numeric::{rx_float_range, rx_int_range},
+};
+
 const COMMON_REGEX: &[(&str, &str)] = &[
     ("common.DIGIT", r#"[0-9]"#),
     ("common.HEXDIGIT", r#"[a-fA-F0-9]"#),
@@ -40,21 +46,102 @@ const COMMON_REGEX: &[(&str, &str)] = &[
     ("common.CPP_COMMENT", r#"//[^\n]*"#),
     ("common.C_COMMENT", r#"\/\*[^*]*\*+(?:[^/*][^*]*\*+)*\/"#),
     ("common.SQL_COMMENT", r#"--[^\n]*"#),
+    // the ones below are not in lark's common.lark
+    ("common.C_NAME", r#"[_A-Za-z][_A-Za-z0-9]*"#),
+    ("common.C_STRING", r#"(u8|u|U|L)?\"([^\"\\\n]|\\(.|\n))*\""#),
+    (
+        "common.PYTHON_NAME",
+        r#"[_\p{L}\p{Nl}][_\p{L}\p{Nl}\p{Mn}\p{Mc}\p{Nd}\p{Pc}]*"#,
+    ),
+    (
+        "common.PYTHON_STRING",
+        concat!(
+            r#"([rRuUbBfF]|[rR][bBfF]|[bBfF][rR])?("#,
+            r#"'([^'\\\n]|\\(.|\n))*'|"#,
+            r#"\"([^\"\\\n]|\\(.|\n))*\"|"#,
+            r#"'''([^'\\]|\\(.|\n)|'([^'\\]|\\(.|\n))|''([^'\\]|\\(.|\n)))*'''|"#,
+            r#"\"\"\"([^\"\\]|\\(.|\n)|\"([^\"\\]|\\(.|\n))|\"\"([^\"\\]|\\(.|\n)))*\"\"\""#,
+            r#")"#
+        ),
+    ),
+    (
+        "common.URI",
+        r#"[A-Za-z][A-Za-z0-9+.\-]*:([A-Za-z0-9\-._~:/?#\[\]@!$&'()*+,;=]|%[0-9A-Fa-f]{2})*"#,
+    ),
+    (
+        "common.SEMVER",
+        concat!(
+            r#"(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)"#,
+            r#"(-(0|[1-9][0-9]*|[0-9]*[a-zA-Z\-][0-9a-zA-Z\-]*)(\.(0|[1-9][0-9]*|[0-9]*[a-zA-Z\-][0-9a-zA-Z\-]*))*)?"#,
+            r#"(\+[0-9a-zA-Z\-]+(\.[0-9a-zA-Z\-]+)*)?"#
+        ),
+    ),
+    (
+        "common.HEX_COLOR",
+        r#"#([0-9a-fA-F]{3}|[0-9a-fA-F]{4}|[0-9a-fA-F]{6}|[0-9a-fA-F]{8})"#,
+    ),
+];
+
+/// Terminals reusing JSON schema "format" regexes.
+const COMMON_FORMATS: &[(&str, &str)] = &[
+    ("common.RFC3339_DATE_TIME", "date-time"),
+    ("common.RFC3339_DATE", "date"),
+    ("common.RFC3339_TIME", "time"),
+    ("common.DURATION", "duration"),
+    ("common.EMAIL", "email"),
+    ("common.HOSTNAME", "hostname"),
+    ("common.IPV4", "ipv4"),
+    ("common.IPV6", "ipv6"),
+    ("common.UUID", "uuid"),
 ];
 
-pub fn lookup_common_regex(name: &str) -> Result<&str> {
-    COMMON_REGEX
-        .iter()
-        .find_map(|(n, r)| if *n == name { Some(*r) } else { None })
-        .ok_or_else(|| {
-            anyhow::anyhow!(
-                "Unknown common regex: {}; following are available: {}",
-                name,
-                COMMON_REGEX
-                    .iter()
-                    .map(|(n, _)| *n)
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            )
-        })
+/// Format regexes are anchored and use named groups; we need neither.
+fn format_to_regex(fmt: &str) -> String {
+    let fmt = fmt.strip_prefix('^').unwrap_or(fmt);
+    let mut fmt = fmt.strip_suffix('$').unwrap_or(fmt);
+    let mut res = String::new();
+    while let Some(idx) = fmt.find("(?P<") {
+        res.push_str(&fmt[..idx]);
+        res.push_str("(?:");
+        fmt = &fmt[idx..];
+        fmt = &fmt[fmt.find('>').unwrap() + 1..];
+    }
+    res.push_str(fmt);
+    res
+}
+
+fn lookup_common_regex_inner(name: &str) -> Result<Option<String>> {
+    if let Some((_, r)) = COMMON_REGEX.iter().find(|(n, _)| *n == name) {
+        return Ok(Some(r.to_string()));
+    }
+    if let Some((_, f)) = COMMON_FORMATS.iter().find(|(n, _)| *n == name) {
+        return Ok(Some(format_to_regex(lookup_format(f).unwrap())));
+    }
+    let r = match name {
+        "common.JSON_STRING" => format!("\"{}*\"", CHAR_REGEX),
+        "common.JSON_INT" => rx_int_range(None, None)?,
+        "common.JSON_NUMBER" => rx_float_range(None, None, true, true)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(r))
+}
+
+pub fn lookup_common_regex(name: &str) -> Result<String> {
+    lookup_common_regex_inner(name)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Unknown common regex: {}; following are available: {}",
+            name,
+            COMMON_REGEX
+                .iter()
+                .chain(COMMON_FORMATS.iter())
+                .map(|(n, _)| *n)
+                .chain([
+                    "common.JSON_STRING",
+                    "common.JSON_INT",
+                    "common.JSON_NUMBER"
+                ])
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
 }
@@ -1,4 +1,5 @@
 use crate::{
+    earley::lexerspec::RegexSource,
     grammar_builder::{GrammarResult, RegexId},
     substring::substring,
     HashMap, HashSet,
@@ -7,8 +8,17 @@ use anyhow::{anyhow, bail, ensure, Result};
 use derivre::RegexAst;
 
 use crate::{
-    api::{GenGrammarOptions, GenOptions, GrammarId, LLGuidanceOptions, NodeProps, RegexExt},
-    json::json_merge,
+    api::{
+        GenGrammarOptions, GenOptions, GrammarId, GrammarLint, LLGuidanceOptions, LintKind,
+        NodeProps, RegexExt,
+    },
+    json::{
+        compiler::json_quote_options,
+        json_merge,
+        numeric::{
+            check_number_bounds, lower_bound, rx_float_range, rx_int_bounds, upper_bound, Decimal,
+        },
+    },
     substring::{chunk_into_chars, chunk_into_words},
     GrammarBuilder, JsonCompileOptions, NodeRef,
 };
@@ -18,12 +28,21 @@ use super::{
     common::lookup_common_regex,
     lexer::Location,
     parser::{parse_lark, ParsedLark},
+    resolver::ImportResolver,
 };
+use std::sync::Arc;
+use toktrie::bytes::limit_str;
+
+/// Maximum nesting of template instantiations.
+const MAX_TEMPLATE_DEPTH: usize = 32;
 
 #[derive(Debug)]
 struct Grammar {
     rules: HashMap<String, Rule>,
+    templates: HashMap<String, Rule>,
     tokens: HashMap<String, TokenDef>,
+    // rules imported only as dependencies of what was imported explicitly
+    dependencies: HashSet<String>,
     ignore: Vec<Expansions>,
     llguidance_options: serde_json::Value,
 }
@@ -32,7 +51,9 @@ impl Default for Grammar {
     fn default() -> Self {
         Self {
             rules: HashMap::default(),
+            templates: HashMap::default(),
             tokens: HashMap::default(),
+            dependencies: HashSet::default(),
             ignore: vec![],
             llguidance_options: serde_json::Value::Object(serde_json::Map::new()),
         }
@@ -45,8 +66,11 @@ struct Compiler {
     grammar: Grammar,
     node_ids: HashMap<String, NodeRef>,
     regex_ids: HashMap<String, RegexId>,
+    token_priorities: HashMap<String, i32>,
     in_progress: HashSet<String>,
+    template_depth: usize,
     pending_json_grammars: Vec<(NodeRef, Location, serde_json::Value)>,
+    rule_events: bool,
 }
 
 fn compile_lark(builder: GrammarBuilder, parsed: ParsedLark) -> Result<GrammarResult> {
@@ -56,8 +80,11 @@ fn compile_lark(builder: GrammarBuilder, parsed: ParsedLark) -> Result<GrammarRe
         grammar: Grammar::default(),
         node_ids: HashMap::default(),
         regex_ids: HashMap::default(),
+        token_priorities: HashMap::default(),
         in_progress: HashSet::default(),
+        template_depth: 0,
         pending_json_grammars: vec![],
+        rule_events: false,
     };
     c.execute()
 }
@@ -135,6 +162,17 @@ impl Compiler {
                 let id = self.do_token_expansions(expansions)?;
                 Ok(self.builder.regex.optional(id))
             }
+            Atom::Intersection(conjuncts) => {
+                let args = conjuncts
+                    .into_iter()
+                    .map(|expansion| self.do_token_expansion(expansion))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(self.builder.regex.and(args))
+            }
+            Atom::Complement(expr) => {
+                let id = self.do_token_expr(*expr)?;
+                Ok(self.builder.regex.not(id))
+            }
             Atom::Value(value) => match value {
                 Value::LiteralRange(a, b) => {
                     ensure!(
@@ -193,7 +231,9 @@ impl Compiler {
                         g
                     );
                 }
-                Value::TemplateUsage { .. } => bail!("template usage not supported yet"),
+                Value::TemplateUsage { name, .. } => {
+                    bail!("templates (like {:?}) cannot be used in terminals", name)
+                }
             },
         }
     }
@@ -237,22 +277,25 @@ impl Compiler {
         let options = expansions
             .1
             .into_iter()
-            .map(|alias| {
-                let args = alias
-                    .expansion
-                    .0
-                    .into_iter()
-                    .map(|e| self.do_token_expr(e))
-                    .collect::<Result<Vec<_>>>()?;
-                Ok(self.builder.regex.concat(args))
-            })
+            .map(|alias| self.do_token_expansion(alias.expansion))
             .collect::<Result<Vec<_>>>()
             .map_err(|e| expansions.0.augment(e))?;
         Ok(self.builder.regex.select(options))
     }
 
-    fn lift_regex(&mut self, rx_id: RegexId) -> Result<NodeRef> {
-        Ok(self.builder.lexeme(rx_id))
+    fn do_token_expansion(&mut self, expansion: Expansion) -> Result<RegexId> {
+        let args = expansion
+            .0
+            .into_iter()
+            .map(|e| self.do_token_expr(e))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(self.builder.regex.concat(args))
+    }
+
+    fn lift_regex(&mut self, rx_id: RegexId, priority: i32) -> Result<NodeRef> {
+        Ok(self
+            .builder
+            .lexeme_ext_with_priority(rx_id, None, priority, NodeProps::default()))
     }
 
     fn get_grammar_id(g: &str) -> Result<GrammarId> {
@@ -272,11 +315,18 @@ impl Compiler {
                 let id = self.do_expansions(expansions)?;
                 Ok(self.builder.optional(id))
             }
+            Atom::Intersection(_) | Atom::Complement(_) => {
+                // only terminals can be intersected or complemented
+                let rx = self.do_token_atom(expr)?;
+                self.lift_regex(rx, 0)
+            }
             Atom::Value(value) => {
                 match &value {
                     Value::Name(n) => {
                         if self.is_rule(n) {
                             return self.do_rule(n);
+                        } else if self.grammar.templates.contains_key(n) {
+                            bail!("template {:?} used without arguments", n);
                         } else {
                             // OK -> treat as token
                         }
@@ -346,11 +396,20 @@ impl Compiler {
                         // treat as token
                     }
                     Value::TemplateUsage { .. } => {
-                        bail!("template usage not supported yet");
+                        // consume value
+                        let (name, values) = match value {
+                            Value::TemplateUsage { name, values } => (name, values),
+                            _ => unreachable!(),
+                        };
+                        return self.do_template(&name, values);
                     }
                 };
+                let priority = match &value {
+                    Value::Name(n) => self.token_priorities.get(n).copied().unwrap_or(0),
+                    _ => 0,
+                };
                 let rx = self.do_token_atom(Atom::Value(value))?;
-                Ok(self.lift_regex(rx)?)
+                self.lift_regex(rx, priority)
             }
         }
     }
@@ -424,15 +483,67 @@ impl Compiler {
         self.in_progress.insert(name.to_string());
 
         let id = self.do_rule_core(name)?;
+        self.builder.set_source_name(id, name);
 
-        if let Some(placeholder) = self.node_ids.get(name) {
-            self.builder.set_placeholder(*placeholder, id);
+        if let Some(placeholder) = self.node_ids.get(name).copied() {
+            self.builder.set_placeholder(placeholder, id);
+            self.builder.set_source_name(placeholder, name);
         }
         self.node_ids.insert(name.to_string(), id);
         self.in_progress.remove(name);
         Ok(id)
     }
 
+    /// Instantiate a template as a rule named after the arguments;
+    /// do_rule() then takes care of memoization and recursion.
+    fn do_template(&mut self, name: &str, args: Vec<Value>) -> Result<NodeRef> {
+        let usage = Value::TemplateUsage {
+            name: name.to_string(),
+            values: args,
+        };
+        let inst_name = usage.to_string();
+        if !self.is_rule(&inst_name) {
+            let args = match usage {
+                Value::TemplateUsage { values, .. } => values,
+                _ => unreachable!(),
+            };
+            let template = self
+                .grammar
+                .templates
+                .get(name)
+                .ok_or_else(|| anyhow!("template {:?} not found", name))?;
+            let params = &template.params.as_ref().unwrap().0;
+            ensure!(
+                params.len() == args.len(),
+                "template {:?} expects {} arguments, got {}",
+                name,
+                params.len(),
+                args.len()
+            );
+            let subst = params.iter().cloned().zip(args).collect::<HashMap<_, _>>();
+            let mut rule = template.clone();
+            rule.name = inst_name.clone();
+            rule.params = None;
+            rule.expansions = subst_expansions(rule.expansions, &subst);
+            rule.stop = rule.stop.map(|v| subst_value(v, &subst));
+            rule.suffix = rule.suffix.map(|v| subst_value(v, &subst));
+            self.grammar.rules.insert(inst_name.clone(), rule);
+
+            // a template can instantiate itself with ever bigger arguments
+            ensure!(
+                self.template_depth < MAX_TEMPLATE_DEPTH,
+                "template expansion too deep (more than {} levels) at {}",
+                MAX_TEMPLATE_DEPTH,
+                limit_str(&inst_name, 100)
+            );
+            self.template_depth += 1;
+            let r = self.do_rule(&inst_name);
+            self.template_depth -= 1;
+            return r;
+        }
+        self.do_rule(&inst_name)
+    }
+
     fn do_rule_core(&mut self, name: &str) -> Result<NodeRef> {
         let rule = self
             .grammar
@@ -443,6 +554,7 @@ impl Compiler {
         let props = NodeProps {
             max_tokens: rule.max_tokens,
             capture_name: rule.capture_name.clone(),
+            rule_name: self.rule_events.then(|| name.to_string()),
             ..Default::default()
         };
 
@@ -497,7 +609,12 @@ impl Compiler {
                                 e
                             )
                         })?;
-                        return Ok(self.builder.lexeme_ext(rx_id, rule.temperature, props));
+                        return Ok(self.builder.lexeme_ext_with_priority(
+                            rx_id,
+                            rule.temperature,
+                            rule.priority.unwrap_or(0),
+                            props,
+                        ));
                     }
                 }
             }
@@ -515,7 +632,7 @@ impl Compiler {
                         ..Default::default()
                     },
                 )
-            } else if rule.capture_name.is_some() {
+            } else if props.capture_name.is_some() || props.rule_name.is_some() {
                 self.builder.join_props(&[inner], props)
             } else {
                 inner
@@ -526,9 +643,14 @@ impl Compiler {
 
     fn execute(mut self) -> Result<GrammarResult> {
         let mut grm = Grammar::default();
+        let mut importer = Importer {
+            resolver: self.builder.import_resolver(),
+            stack: vec![],
+        };
         for item in std::mem::take(&mut self.parsed.items) {
             let loc = item.location().clone();
-            grm.process_item(item).map_err(|e| loc.augment(e))?;
+            grm.process_item(item, &mut importer)
+                .map_err(|e| loc.augment(e))?;
         }
         let start_name = "start";
         ensure!(
@@ -537,6 +659,11 @@ impl Compiler {
             start_name
         );
         let ignore = std::mem::take(&mut grm.ignore);
+        self.token_priorities = grm
+            .tokens
+            .values()
+            .filter_map(|t| t.priority.map(|p| (t.name.clone(), p)))
+            .collect();
         self.grammar = grm;
 
         let opts: LLGuidanceOptions =
@@ -547,14 +674,39 @@ impl Compiler {
             .into_iter()
             .map(|exp| Ok(RegexAst::ExprRef(self.do_token_expansions(exp)?)))
             .collect::<Result<Vec<_>>>()?;
+        self.rule_events = opts.rule_events;
         let id = self.builder.add_grammar(opts, RegexAst::Or(ignore))?;
 
         let start = self.do_rule(start_name)?;
         self.builder.set_start_node(start);
 
+        // do_rule() removes compiled rules, so only the unreachable ones are left
+        let mut unused = self
+            .grammar
+            .rules
+            .keys()
+            .filter(|n| !self.grammar.dependencies.contains(*n))
+            .cloned()
+            .collect::<Vec<_>>();
+        unused.sort();
+        for name in unused {
+            self.builder.add_lint(GrammarLint {
+                kind: LintKind::Unreachable,
+                rule: name,
+                message: "rule is not reachable from start".to_string(),
+            });
+        }
+
         let mut builder = self.builder;
-        for (gg, loc, json_schema) in self.pending_json_grammars {
-            let opts = JsonCompileOptions::default();
+        for (gg, loc, mut json_schema) in self.pending_json_grammars {
+            let mut opts = JsonCompileOptions::default();
+            if let Some(x_guidance) = json_schema
+                .as_object_mut()
+                .and_then(|o| o.remove("x-guidance"))
+            {
+                opts = serde_json::from_value(x_guidance)
+                    .map_err(|e| loc.augment(anyhow!("invalid x-guidance: {}", e)))?;
+            }
             let res = opts
                 .json_to_llg_no_validate(builder, json_schema)
                 .map_err(|e| loc.augment(anyhow!("failed to compile JSON schema: {}", e)))?;
@@ -594,23 +746,26 @@ impl Grammar {
         Ok(())
     }
 
-    fn do_statement(&mut self, loc: &Location, statement: Statement) -> Result<()> {
+    fn do_statement(
+        &mut self,
+        loc: &Location,
+        statement: Statement,
+        importer: &mut Importer,
+    ) -> Result<()> {
         match statement {
             Statement::Ignore(exp) => {
                 self.ignore.push(exp);
             }
             Statement::Import { path, alias } => {
-                let regex = lookup_common_regex(&path)?;
-                let local_name =
-                    alias.unwrap_or_else(|| path.split('.').last().unwrap().to_string());
-                self.add_token_def(loc, local_name, regex)?;
+                let (module, name) = path
+                    .rsplit_once('.')
+                    .ok_or_else(|| anyhow!("invalid import path: {:?}", path))?;
+                let local_name = alias.unwrap_or_else(|| name.to_string());
+                self.do_import(loc, importer, module, vec![(name.to_string(), local_name)])?;
             }
             Statement::MultiImport { path, names } => {
-                for n in names {
-                    let qname = format!("{}.{}", path, n);
-                    let regex = lookup_common_regex(&qname)?;
-                    self.add_token_def(loc, n.to_string(), regex)?;
-                }
+                let names = names.into_iter().map(|n| (n.clone(), n)).collect();
+                self.do_import(loc, importer, &path, names)?;
             }
             Statement::LLGuidance(json_value) => {
                 // merge-in at the JSON level
@@ -629,21 +784,126 @@ impl Grammar {
         Ok(())
     }
 
-    fn process_item(&mut self, item: Item) -> Result<()> {
+    /// Import `names` (pairs of name in module and local name) from given module.
+    /// Whatever the imported rules and tokens depend on is also added,
+    /// with names prefixed by the module path (`foo.bar.baz` -> `foo__bar__baz`).
+    fn do_import(
+        &mut self,
+        loc: &Location,
+        importer: &mut Importer,
+        module: &str,
+        names: Vec<(String, String)>,
+    ) -> Result<()> {
+        if module == "common" {
+            for (name, local_name) in names {
+                let regex = lookup_common_regex(&format!("common.{}", name))?;
+                self.add_token_def(loc, local_name, &regex)?;
+            }
+            return Ok(());
+        }
+
+        let imported = importer.load(module)?;
+
+        let prefix = module.replace('.', "__");
+        let mut renames: HashMap<String, Value> = imported
+            .tokens
+            .keys()
+            .map(|n| (n.clone(), format!("{}__{}", prefix.to_uppercase(), n)))
+            .chain(
+                imported
+                    .rules
+                    .keys()
+                    .chain(imported.templates.keys())
+                    .map(|n| (n.clone(), format!("{}__{}", prefix, n))),
+            )
+            .map(|(n, local)| (n, Value::Name(local)))
+            .collect();
+        let mut local_names = HashSet::default();
+        for (name, local_name) in &names {
+            ensure!(
+                renames.contains_key(name),
+                "{:?} not found in module {:?}",
+                name,
+                module
+            );
+            renames.insert(name.clone(), Value::Name(local_name.clone()));
+            local_names.insert(local_name.clone());
+        }
+        let rename = |name: &str| match &renames[name] {
+            Value::Name(n) => n.clone(),
+            _ => unreachable!(),
+        };
+
+        for (_, token) in imported.tokens {
+            let name = rename(&token.name);
+            if self.tokens.contains_key(&name) && !local_names.contains(&name) {
+                // already added by an earlier import from the same module
+                continue;
+            }
+            ensure!(
+                !self.tokens.contains_key(&name),
+                "duplicate token (in import): {:?}",
+                name
+            );
+            let t = TokenDef {
+                name,
+                expansions: subst_expansions(token.expansions, &renames),
+                ..token
+            };
+            self.tokens.insert(t.name.clone(), t);
+        }
+
+        for (_, rule) in imported.rules.into_iter().chain(imported.templates) {
+            let name = rename(&rule.name);
+            let exists = self.rules.contains_key(&name) || self.templates.contains_key(&name);
+            if exists && !local_names.contains(&name) {
+                continue;
+            }
+            ensure!(!exists, "duplicate rule (in import): {:?}", name);
+            // template parameters shadow names from the module
+            let mut renames = renames.clone();
+            if let Some(params) = &rule.params {
+                for p in &params.0 {
+                    renames.remove(p);
+                }
+            }
+            let r = Rule {
+                name,
+                expansions: subst_expansions(rule.expansions, &renames),
+                stop: rule.stop.map(|v| subst_value(v, &renames)),
+                suffix: rule.suffix.map(|v| subst_value(v, &renames)),
+                ..rule
+            };
+            if !local_names.contains(&r.name) {
+                self.dependencies.insert(r.name.clone());
+            }
+            if r.params.is_some() {
+                self.templates.insert(r.name.clone(), r);
+            } else {
+                self.rules.insert(r.name.clone(), r);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_item(&mut self, item: Item, importer: &mut Importer) -> Result<()> {
         match item {
             Item::Rule(rule) => {
-                ensure!(rule.params.is_none(), "params not supported yet");
-                ensure!(rule.priority.is_none(), "priority not supported yet");
                 ensure!(
-                    !self.rules.contains_key(&rule.name),
+                    !self.rules.contains_key(&rule.name)
+                        && !self.templates.contains_key(&rule.name),
                     "duplicate rule: {:?}",
                     rule.name
                 );
-                self.rules.insert(rule.name.clone(), rule);
+                if rule.params.is_some() {
+                    self.templates.insert(rule.name.clone(), rule);
+                } else {
+                    self.rules.insert(rule.name.clone(), rule);
+                }
             }
             Item::Token(token_def) => {
                 ensure!(token_def.params.is_none(), "params not supported yet");
-                ensure!(token_def.priority.is_none(), "priority not supported yet");
                 ensure!(
                     !self.tokens.contains_key(&token_def.name),
                     "duplicate token: {:?}",
@@ -652,14 +912,104 @@ impl Grammar {
                 self.tokens.insert(token_def.name.clone(), token_def);
             }
             Item::Statement(loc, statement) => {
-                self.do_statement(&loc, statement)?;
+                self.do_statement(&loc, statement, importer)?;
             }
         }
         Ok(())
     }
 }
 
+struct Importer {
+    resolver: Arc<dyn ImportResolver>,
+    stack: Vec<String>,
+}
+
+impl Importer {
+    /// Parse given module, including its own imports.
+    /// `%ignore` and `%llguidance` statements of imported modules are not used.
+    fn load(&mut self, module: &str) -> Result<Grammar> {
+        ensure!(
+            !self.stack.iter().any(|m| m == module),
+            "circular import of module {:?}",
+            module
+        );
+        let lark = self
+            .resolver
+            .resolve(module)
+            .map_err(|e| anyhow!("failed to import module {:?}: {}", module, e))?;
+        let in_module = |e: anyhow::Error| anyhow!("in module {:?}: {}", module, e);
+        let parsed = parse_lark(&lark).map_err(in_module)?;
+        self.stack.push(module.to_string());
+        let mut grm = Grammar::default();
+        for item in parsed.items {
+            let loc = item.location().clone();
+            grm.process_item(item, self)
+                .map_err(|e| in_module(loc.augment(e)))?;
+        }
+        self.stack.pop();
+        Ok(grm)
+    }
+}
+
+fn subst_expansions(expansions: Expansions, subst: &HashMap<String, Value>) -> Expansions {
+    Expansions(
+        expansions.0,
+        expansions
+            .1
+            .into_iter()
+            .map(|alias| Alias {
+                expansion: subst_expansion(alias.expansion, subst),
+                alias: alias.alias,
+            })
+            .collect(),
+    )
+}
+
+fn subst_expansion(expansion: Expansion, subst: &HashMap<String, Value>) -> Expansion {
+    Expansion(
+        expansion
+            .0
+            .into_iter()
+            .map(|expr| subst_expr(expr, subst))
+            .collect(),
+    )
+}
+
+fn subst_expr(expr: Expr, subst: &HashMap<String, Value>) -> Expr {
+    let atom = match expr.atom {
+        Atom::Group(expansions) => Atom::Group(subst_expansions(expansions, subst)),
+        Atom::Maybe(expansions) => Atom::Maybe(subst_expansions(expansions, subst)),
+        Atom::Value(value) => Atom::Value(subst_value(value, subst)),
+        Atom::Intersection(conjuncts) => Atom::Intersection(
+            conjuncts
+                .into_iter()
+                .map(|expansion| subst_expansion(expansion, subst))
+                .collect(),
+        ),
+        Atom::Complement(expr) => Atom::Complement(Box::new(subst_expr(*expr, subst))),
+    };
+    Expr { atom, ..expr }
+}
+
+fn subst_value(value: Value, subst: &HashMap<String, Value>) -> Value {
+    match value {
+        Value::Name(n) => match subst.get(&n) {
+            Some(v) => v.clone(),
+            None => Value::Name(n),
+        },
+        Value::TemplateUsage { name, values } => Value::TemplateUsage {
+            name: match subst.get(&name) {
+                Some(Value::Name(n)) => n.clone(),
+                _ => name,
+            },
+            values: values.into_iter().map(|v| subst_value(v, subst)).collect(),
+        },
+        v => v,
+    }
+}
+
 fn compile_lark_regex(builder: &mut GrammarBuilder, l: RegexExt) -> Result<RegexId> {
+    let source = RegexSource::Ext(l.clone());
     let mut fields_set = vec![];
     if l.substring_chunks.is_some() {
         fields_set.push("substring_chunks");
@@ -670,6 +1020,21 @@ fn compile_lark_regex(builder: &mut GrammarBuilder, l: RegexExt) -> Result<Regex
     if l.substring_chars.is_some() {
         fields_set.push("substring_chars");
     }
+    if l.json_string.is_some() {
+        fields_set.push("json_string");
+    }
+    if l.type_.is_some() {
+        fields_set.push("type");
+    } else {
+        ensure!(
+            l.minimum.is_none()
+                && l.maximum.is_none()
+                && l.exclusive_minimum.is_none()
+                && l.exclusive_maximum.is_none()
+                && l.multiple_of.is_none(),
+            "numeric constraints on %regex require \"type\": \"integer\" or \"number\""
+        );
+    }
     if fields_set.is_empty() {
         bail!("no fields set on %regex");
     }
@@ -685,9 +1050,49 @@ fn compile_lark_regex(builder: &mut GrammarBuilder, l: RegexExt) -> Result<Regex
         substring(bld, chunk_into_chars(&s))?
     } else if let Some(s) = l.substring_chunks {
         substring(bld, s.iter().map(|s| s.as_str()).collect())?
+    } else if let Some(s) = l.json_string {
+        bld.mk(&RegexAst::JsonQuote(
+            Box::new(RegexAst::Regex(s)),
+            json_quote_options(),
+        ))?
+    } else if let Some(tp) = &l.type_ {
+        let integer = match tp.as_str() {
+            "integer" => true,
+            "number" => false,
+            _ => bail!(
+                "unknown %regex type: {:?}; expecting \"integer\" or \"number\"",
+                tp
+            ),
+        };
+        let (minimum, exclusive_minimum) = lower_bound(l.minimum, l.exclusive_minimum);
+        let (maximum, exclusive_maximum) = upper_bound(l.maximum, l.exclusive_maximum);
+        let multiple_of = match l.multiple_of {
+            None => None,
+            Some(m) => Some(Decimal::try_from(m.abs())?),
+        };
+        check_number_bounds(
+            minimum,
+            maximum,
+            exclusive_minimum,
+            exclusive_maximum,
+            false,
+            multiple_of.clone(),
+        )
+        .map_err(|e| anyhow!("%regex range is empty: {}", e))?;
+        let rx = if integer {
+            rx_int_bounds(minimum, maximum, exclusive_minimum, exclusive_maximum)?
+        } else {
+            rx_float_range(minimum, maximum, !exclusive_minimum, !exclusive_maximum)?
+        };
+        let mut ast = RegexAst::Regex(rx);
+        if let Some(d) = multiple_of {
+            ast = RegexAst::And(vec![ast, RegexAst::MultipleOf(d.coef, d.exp)]);
+        }
+        bld.mk(&ast)?
     } else {
         unreachable!()
     };
+    builder.regex.spec.add_regex_source(eref, source);
 
     Ok(eref)
 }
@@ -39,6 +39,7 @@ pub enum Token {
     LBracket,
     RBracket,
     Tilde,
+    Ampersand,
     // regexps
     Op, // + * ?
     String,
@@ -141,6 +142,7 @@ impl Token {
         (Token::LBracket, "["),
         (Token::RBracket, "]"),
         (Token::Tilde, "~"),
+        (Token::Ampersand, "&"),
         (Token::VBar, "|"),
         (Token::Equals, "="),
     ];
@@ -184,6 +186,7 @@ pub fn lex_lark(input: &str) -> Result<Vec<Lexeme>> {
                 false,
                 None,
                 usize::MAX,
+                0,
             )
             .unwrap();
         lexeme_idx_to_token.insert(l, *token);
@@ -1,7 +1,13 @@
 mod ast;
+mod captures;
 mod common;
 mod compiler;
 mod lexer;
 mod parser;
+mod render;
+mod resolver;
 
+pub use captures::regex_with_captures_to_lark;
 pub use compiler::{lark_regex_quote, lark_to_llguidance};
+pub(crate) use render::cgrammar_to_lark;
+pub use resolver::{ImportResolver, InMemoryImportResolver};
@@ -316,7 +316,19 @@ impl Parser {
 
     /// Parses an alias.
     fn parse_alias(&mut self) -> Result<Alias> {
-        let expansion = self.parse_expansion()?;
+        let mut expansion = self.parse_expansion()?;
+        if self.has_token(Token::Ampersand) {
+            // '&' binds weaker than concatenation, but stronger than '|'
+            let mut conjuncts = vec![expansion];
+            while self.match_token(Token::Ampersand) {
+                conjuncts.push(self.parse_expansion()?);
+            }
+            expansion = Expansion(vec![Expr {
+                atom: Atom::Intersection(conjuncts),
+                op: None,
+                range: None,
+            }]);
+        }
         let alias = if self.match_token(Token::Arrow) {
             Some(self.expect_token_val(Token::Rule)?)
         } else {
@@ -332,6 +344,7 @@ impl Parser {
             if self.has_token(Token::Newline)
                 || self.has_token(Token::VBar)
                 || self.has_token(Token::Arrow)
+                || self.has_token(Token::Ampersand)
                 || self.has_token(Token::RBrace)
                 || self.has_token(Token::RParen)
                 || self.has_token(Token::RBracket)
@@ -345,12 +358,22 @@ impl Parser {
 
     /// Parses an expression.
     fn parse_expr(&mut self) -> Result<Expr> {
+        if self.match_token(Token::Tilde) {
+            // prefix '~' applies to the following expression, including its operator
+            let inner = self.parse_expr()?;
+            return Ok(Expr {
+                atom: Atom::Complement(Box::new(inner)),
+                op: None,
+                range: None,
+            });
+        }
         let atom = self.parse_atom()?;
         let mut op = None;
         let mut range = None;
         if let Some(op_token) = self.match_token_with_value(Token::Op) {
             op = Some(Op(op_token.clone()));
-        } else if self.match_token(Token::Tilde) {
+        } else if self.has_tokens(&[Token::Tilde, Token::Number]) {
+            self.expect_token(Token::Tilde)?;
             let start_num = self.expect_token_val(Token::Number)?.parse::<i32>()?;
             let end_num = if self.match_token(Token::DotDot) {
                 Some(self.expect_token_val(Token::Number)?.parse::<i32>()?)
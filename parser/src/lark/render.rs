@@ -0,0 +1,654 @@
+use std::fmt::Write;
+
+use anyhow::{bail, ensure, Result};
+use derivre::{ExprRef, RegexAst};
+
+use crate::{
+    earley::{
+        lexerspec::{token_ranges_to_string, LexemeIdx, LexerSpec, RegexSource},
+        CGrammar, CSymIdx,
+    },
+    json::compiler::json_quote_options,
+    HashMap, HashSet,
+};
+
+use super::compiler::lark_regex_quote;
+
+// precedence of rendered terminal expressions; higher binds tighter
+const PREC_OR: u8 = 0;
+const PREC_AND: u8 = 1;
+const PREC_CONCAT: u8 = 2;
+const PREC_NOT: u8 = 3;
+const PREC_REPEAT: u8 = 4;
+const PREC_ATOM: u8 = 5;
+
+// an empty character class
+const NO_MATCH_RX: &str = "[a&&b]";
+
+static EMPTY_STRING: RegexAst = RegexAst::EmptyString;
+static NO_MATCH: RegexAst = RegexAst::NoMatch;
+
+type Rendered = (String, u8);
+
+/// Render a compiled grammar as Lark source accepted by llguidance.
+/// Lexemes are printed from the regex ASTs they were built from;
+/// unreachable rules are skipped.
+pub fn cgrammar_to_lark(grammar: &CGrammar) -> Result<String> {
+    ensure!(
+        grammar.lexer_spec().record_regex_sources,
+        "grammar was compiled without keeping regex sources; use GrammarInit::to_lark()"
+    );
+    Renderer {
+        grammar,
+        spec: grammar.lexer_spec(),
+        names: HashSet::default(),
+        sym_names: HashMap::default(),
+        lexeme_refs: HashMap::default(),
+        rx_uses: HashMap::default(),
+        rx_names: HashMap::default(),
+        terminals: String::new(),
+    }
+    .render()
+}
+
+struct Renderer<'a> {
+    grammar: &'a CGrammar,
+    spec: &'a LexerSpec,
+    names: HashSet<String>,
+    sym_names: HashMap<CSymIdx, String>,
+    lexeme_refs: HashMap<LexemeIdx, String>,
+    rx_uses: HashMap<ExprRef, usize>,
+    rx_names: HashMap<ExprRef, String>,
+    terminals: String,
+}
+
+fn paren((s, prec): Rendered, min_prec: u8) -> String {
+    if prec >= min_prec {
+        s
+    } else {
+        format!("({})", s)
+    }
+}
+
+fn quote_string(s: &str) -> String {
+    // Lark strings use JSON syntax, but don't allow a literal DEL
+    serde_json::to_string(s)
+        .unwrap()
+        .replace('\u{7f}', "\\u007f")
+}
+
+fn mangle(name: &str, upper: bool) -> String {
+    let mut res = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            res.push(if upper {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            });
+        } else if !res.is_empty() && !res.ends_with('_') {
+            res.push('_');
+        }
+    }
+    while res.ends_with('_') {
+        res.pop();
+    }
+    let prefix = if upper { "T" } else { "r" };
+    if res.is_empty() {
+        res = prefix.to_string();
+    } else if !res.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        res = format!("{}_{}", prefix, res);
+    }
+    res
+}
+
+fn byteset_class(bytes: impl Fn(usize) -> bool) -> Result<String> {
+    let mut res = "[".to_string();
+    let mut b = 0;
+    while b < 256 {
+        if bytes(b) {
+            let start = b;
+            while b + 1 < 256 && bytes(b + 1) {
+                b += 1;
+            }
+            ensure!(b < 0x80, "non-ASCII bytes cannot be rendered as Lark");
+            write!(res, "\\x{:02x}", start).unwrap();
+            if b > start {
+                write!(res, "-\\x{:02x}", b).unwrap();
+            }
+        }
+        b += 1;
+    }
+    if res.len() == 1 {
+        return Ok(NO_MATCH_RX.to_string());
+    }
+    res.push(']');
+    Ok(res)
+}
+
+fn byte_class(ast: &RegexAst) -> Result<String> {
+    match ast {
+        RegexAst::Byte(b) => byteset_class(|x| x == *b as usize),
+        RegexAst::ByteSet(bs) => byteset_class(|x| bs[x / 32] & (1 << (x % 32)) != 0),
+        _ => unreachable!(),
+    }
+}
+
+fn repeat_suffix(min: u32, max: u32) -> String {
+    match (min, max) {
+        (0, u32::MAX) => "*".to_string(),
+        (1, u32::MAX) => "+".to_string(),
+        (0, 1) => "?".to_string(),
+        (min, u32::MAX) => format!("{{{},}}", min),
+        (min, max) if min == max => format!("{{{}}}", min),
+        (min, max) => format!("{{{},{}}}", min, max),
+    }
+}
+
+fn is_empty_string(ast: &RegexAst) -> bool {
+    match ast {
+        RegexAst::EmptyString => true,
+        RegexAst::ExprRef(r) => *r == ExprRef::EMPTY_STRING,
+        RegexAst::Literal(s) | RegexAst::Regex(s) => s.is_empty(),
+        _ => false,
+    }
+}
+
+impl<'a> Renderer<'a> {
+    fn fresh_name(&mut self, base: String) -> String {
+        let mut name = base.clone();
+        let mut idx = 2;
+        while self.names.contains(&name) {
+            name = format!("{}_{}", base, idx);
+            idx += 1;
+        }
+        self.names.insert(name.clone());
+        name
+    }
+
+    fn source(&self, r: ExprRef) -> Result<&'a RegexSource> {
+        match self.spec.regex_sources.get(&r) {
+            Some(src) => Ok(src),
+            None => bail!("regex #{} was not built from an AST", r.as_usize()),
+        }
+    }
+
+    fn source_ast(&self, r: ExprRef) -> Result<&'a RegexAst> {
+        if r == ExprRef::EMPTY_STRING {
+            return Ok(&EMPTY_STRING);
+        }
+        if r == ExprRef::NO_MATCH {
+            return Ok(&NO_MATCH);
+        }
+        match self.source(r)? {
+            RegexSource::Ast(ast) => Ok(ast),
+            RegexSource::Ext(_) => bail!("%regex cannot be used here"),
+        }
+    }
+
+    fn count_uses(&mut self, ast: &RegexAst) {
+        if let RegexAst::ExprRef(r) = ast {
+            let n = self.rx_uses.entry(*r).or_insert(0);
+            *n += 1;
+            if *n == 1 {
+                if let Some(RegexSource::Ast(ast)) = self.spec.regex_sources.get(r) {
+                    self.count_uses(ast);
+                }
+            }
+        } else {
+            for arg in ast.get_args() {
+                self.count_uses(arg);
+            }
+        }
+    }
+
+    fn rx_ref(&mut self, r: ExprRef) -> Result<Rendered> {
+        if let Some(name) = self.rx_names.get(&r) {
+            return Ok((name.clone(), PREC_ATOM));
+        }
+        if r == ExprRef::EMPTY_STRING || r == ExprRef::NO_MATCH {
+            return self.rx(self.source_ast(r)?);
+        }
+        match self.source(r)? {
+            RegexSource::Ext(ext) => {
+                let mut v = serde_json::to_value(ext)?;
+                if let Some(obj) = v.as_object_mut() {
+                    obj.retain(|_, v| !v.is_null());
+                }
+                Ok((format!("%regex {}", v), PREC_ATOM))
+            }
+            RegexSource::Ast(ast) => {
+                let shared = self.rx_uses.get(&r).copied().unwrap_or(0) > 1;
+                if shared && !ast.get_args().is_empty() {
+                    // give regexes used more than once a name, to keep the output linear
+                    let name = self.fresh_name("RX".to_string());
+                    self.rx_names.insert(r, name.clone());
+                    let body = self.rx(ast)?.0;
+                    writeln!(self.terminals, "{}: {}", name, body).unwrap();
+                    Ok((name, PREC_ATOM))
+                } else {
+                    self.rx(ast)
+                }
+            }
+        }
+    }
+
+    fn rx_list(&mut self, args: &[RegexAst], sep: &str, prec: u8) -> Result<Rendered> {
+        if args.len() == 1 {
+            return self.rx(&args[0]);
+        }
+        let parts = args
+            .iter()
+            .map(|a| Ok(paren(self.rx(a)?, prec + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((parts.join(sep), prec))
+    }
+
+    /// Render a regex as a Lark terminal expression.
+    fn rx(&mut self, ast: &RegexAst) -> Result<Rendered> {
+        let atom = |s: String| Ok((s, PREC_ATOM));
+        match ast {
+            RegexAst::ExprRef(r) => self.rx_ref(*r),
+            RegexAst::EmptyString => atom("\"\"".to_string()),
+            RegexAst::NoMatch => atom(format!("/{}/", NO_MATCH_RX)),
+            RegexAst::Literal(s) => atom(quote_string(s)),
+            RegexAst::ByteLiteral(b) => match std::str::from_utf8(b) {
+                Ok(s) => atom(quote_string(s)),
+                Err(_) => bail!("non-UTF-8 literals cannot be rendered as Lark"),
+            },
+            RegexAst::Regex(s) if s.is_empty() => atom("\"\"".to_string()),
+            RegexAst::Regex(s) => atom(format!("/{}/", lark_regex_quote(s))),
+            RegexAst::Byte(_) | RegexAst::ByteSet(_) => atom(format!("/{}/", byte_class(ast)?)),
+            RegexAst::Or(args) if args.is_empty() => self.rx(&NO_MATCH),
+            RegexAst::Or(args) => self.rx_list(args, " | ", PREC_OR),
+            RegexAst::And(args) => {
+                ensure!(!args.is_empty(), "empty intersection");
+                self.rx_list(args, " & ", PREC_AND)
+            }
+            RegexAst::Concat(args) if args.is_empty() => self.rx(&EMPTY_STRING),
+            RegexAst::Concat(args) => self.rx_list(args, " ", PREC_CONCAT),
+            RegexAst::Not(arg) => {
+                let inner = paren(self.rx(arg)?, PREC_NOT);
+                Ok((format!("~{}", inner), PREC_NOT))
+            }
+            RegexAst::Repeat(_, 0, 0) => self.rx(&EMPTY_STRING),
+            RegexAst::Repeat(arg, min, max) => {
+                let inner = paren(self.rx(arg)?, PREC_ATOM);
+                Ok((
+                    format!("{}{}", inner, repeat_suffix(*min, *max)),
+                    PREC_REPEAT,
+                ))
+            }
+            RegexAst::MultipleOf(coef, exp) => {
+                // only used intersected with a number regex, so this is equivalent
+                let multiple_of = if *exp == 0 {
+                    coef.to_string()
+                } else {
+                    format!("{}e-{}", coef, exp)
+                };
+                atom(format!(
+                    "%regex {{\"type\": \"number\", \"multipleOf\": {}}}",
+                    multiple_of
+                ))
+            }
+            RegexAst::JsonQuote(arg, opts) => {
+                let expected = json_quote_options();
+                ensure!(
+                    opts.allowed_escapes == expected.allowed_escapes
+                        && opts.raw_mode == expected.raw_mode,
+                    "JSON quoting with custom options cannot be rendered as Lark"
+                );
+                self.json_string(arg)
+            }
+            RegexAst::LookAhead(_) => {
+                bail!("lookahead is only supported as stop= or suffix= of a lexeme")
+            }
+        }
+    }
+
+    /// Render JSON-quoted `ast`; quoting commutes with `|` and `&`,
+    /// since every JSON string decodes in exactly one way.
+    fn json_string(&mut self, ast: &RegexAst) -> Result<Rendered> {
+        match ast {
+            RegexAst::ExprRef(r) => {
+                let ast = self.source_ast(*r)?;
+                self.json_string(ast)
+            }
+            RegexAst::NoMatch => self.rx(ast),
+            RegexAst::Or(args) | RegexAst::And(args) if args.len() == 1 => {
+                self.json_string(&args[0])
+            }
+            RegexAst::Or(args) | RegexAst::And(args) if !args.is_empty() => {
+                let (sep, prec) = if matches!(ast, RegexAst::Or(_)) {
+                    (" | ", PREC_OR)
+                } else {
+                    (" & ", PREC_AND)
+                };
+                let parts = args
+                    .iter()
+                    .map(|a| Ok(paren(self.json_string(a)?, prec + 1)))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((parts.join(sep), prec))
+            }
+            // quoting is injective, so the complement among JSON strings
+            // is the quoted complement
+            RegexAst::Not(inner) => {
+                let inner = self.json_string(inner)?;
+                Ok((
+                    format!(
+                        "%regex {{\"json_string\": \"(?s:.*)\"}} & ~{}",
+                        paren(inner, PREC_NOT)
+                    ),
+                    PREC_AND,
+                ))
+            }
+            _ => {
+                let rx = self.regex_str(ast)?;
+                Ok((
+                    format!("%regex {{\"json_string\": {}}}", quote_string(&rx)),
+                    PREC_ATOM,
+                ))
+            }
+        }
+    }
+
+    /// Render `ast` as a single regex string.
+    fn regex_str(&self, ast: &RegexAst) -> Result<String> {
+        let group = |args: &[RegexAst], sep: &str| -> Result<String> {
+            let parts = args
+                .iter()
+                .map(|a| Ok(format!("(?:{})", self.regex_str(a)?)))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(parts.join(sep))
+        };
+        match ast {
+            RegexAst::ExprRef(r) => self.regex_str(self.source_ast(*r)?),
+            RegexAst::EmptyString => Ok(String::new()),
+            RegexAst::NoMatch => Ok(NO_MATCH_RX.to_string()),
+            RegexAst::Regex(s) => Ok(s.clone()),
+            RegexAst::Literal(s) => Ok(regex_syntax::escape(s)),
+            RegexAst::ByteLiteral(b) => match std::str::from_utf8(b) {
+                Ok(s) => Ok(regex_syntax::escape(s)),
+                Err(_) => bail!("non-UTF-8 literals cannot be rendered as Lark"),
+            },
+            RegexAst::Byte(_) | RegexAst::ByteSet(_) => byte_class(ast),
+            RegexAst::Concat(args) => group(args, ""),
+            RegexAst::Or(args) if args.is_empty() => Ok(NO_MATCH_RX.to_string()),
+            RegexAst::Or(args) => Ok(format!("(?:{})", group(args, "|")?)),
+            RegexAst::Repeat(arg, min, max) => Ok(format!(
+                "(?:{}){}",
+                self.regex_str(arg)?,
+                match (*min, *max) {
+                    (min, u32::MAX) => format!("{{{},}}", min),
+                    (min, max) => format!("{{{},{}}}", min, max),
+                }
+            )),
+            RegexAst::And(_)
+            | RegexAst::Not(_)
+            | RegexAst::LookAhead(_)
+            | RegexAst::MultipleOf(_, _)
+            | RegexAst::JsonQuote(_, _) => {
+                bail!("{} cannot be rendered inside a JSON string", ast.tag())
+            }
+        }
+    }
+
+    fn reachable(&self) -> Vec<CSymIdx> {
+        let mut seen = HashSet::default();
+        let mut res = vec![self.grammar.start()];
+        seen.insert(self.grammar.start());
+        let mut idx = 0;
+        while idx < res.len() {
+            let sym = res[idx];
+            idx += 1;
+            for rule in self.grammar.rules_of(sym) {
+                for &s in self.grammar.rule_rhs(*rule).0 {
+                    if seen.insert(s) {
+                        res.push(s);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the reference to use for a terminal symbol in rules,
+    /// defining the terminal (or a rule for lexemes with attributes) if needed.
+    fn lexeme_ref(&mut self, sym: CSymIdx) -> Result<String> {
+        let data = self.grammar.sym_data(sym);
+        let lx = data.lexeme.unwrap();
+        let spec = self.spec.lexeme_spec(lx);
+
+        if !spec.token_ranges.is_empty() {
+            let name = &spec.name;
+            if name.starts_with('<') && name.ends_with('>') && !name.starts_with("<[") {
+                return Ok(name.clone());
+            }
+            return Ok(token_ranges_to_string(&spec.token_ranges));
+        }
+
+        let (mut body, stop) = match &spec.rx {
+            RegexAst::Concat(parts) if matches!(parts.last(), Some(RegexAst::LookAhead(_))) => {
+                let stop = match parts.last() {
+                    Some(RegexAst::LookAhead(stop)) => stop.as_ref(),
+                    _ => unreachable!(),
+                };
+                (
+                    RegexAst::Concat(parts[..parts.len() - 1].to_vec()),
+                    Some(stop),
+                )
+            }
+            rx => (rx.clone(), None),
+        };
+        if let Some(opts) = &spec.json_options {
+            body = RegexAst::JsonQuote(Box::new(body), opts.clone());
+        }
+
+        let props = &data.props;
+        let mut attrs = vec![];
+        if let Some(name) = &props.capture_name {
+            attrs.push(format!("capture={}", quote_string(name)));
+        }
+        match stop {
+            Some(stop) if is_empty_string(stop) => attrs.push("lazy".to_string()),
+            Some(stop) => {
+                let kind = if spec.is_suffix { "suffix" } else { "stop" };
+                let (stop, prec) = self.rx(stop)?;
+                let stop = if prec == PREC_ATOM && !stop.starts_with('(') {
+                    stop
+                } else {
+                    let name = self.fresh_name(format!("{}_STOP", mangle(&data.name, true)));
+                    writeln!(self.terminals, "{}: {}", name, stop).unwrap();
+                    name
+                };
+                attrs.push(format!("{}={}", kind, stop));
+            }
+            None if spec.lazy => attrs.push("lazy".to_string()),
+            None if spec.ends_at_eos => attrs.push("stop=\"\"".to_string()),
+            None => {}
+        }
+        if let Some(name) = &props.stop_capture_name {
+            attrs.push(format!("stop_capture={}", quote_string(name)));
+        }
+        if spec.max_tokens() < usize::MAX {
+            attrs.push(format!("max_tokens={}", spec.max_tokens()));
+        }
+        if props.temperature != 0.0 {
+            attrs.push(format!("temperature={}", props.temperature));
+        }
+
+        let priority = if spec.priority != 0 {
+            format!(".{}", spec.priority)
+        } else {
+            String::new()
+        };
+
+        if !attrs.is_empty() {
+            // lexemes with attributes are defined with a rule
+            if let Some(name) = self.sym_names.get(&sym) {
+                return Ok(name.clone());
+            }
+            let name = self.fresh_name(mangle(&data.name, false));
+            self.sym_names.insert(sym, name.clone());
+            let body = self.rx(&body)?.0;
+            writeln!(
+                self.terminals,
+                "{}{}[{}]: {}",
+                name,
+                priority,
+                attrs.join(", "),
+                body
+            )
+            .unwrap();
+            return Ok(name);
+        }
+
+        if let Some(r) = self.lexeme_refs.get(&lx) {
+            return Ok(r.clone());
+        }
+        let mut literal = &body;
+        while let RegexAst::ExprRef(r) = literal {
+            match self.spec.regex_sources.get(r) {
+                Some(RegexSource::Ast(ast)) => literal = ast,
+                _ => break,
+            }
+        }
+        let r = match literal {
+            RegexAst::Literal(s) if spec.priority == 0 && !s.is_empty() => quote_string(s),
+            _ => {
+                let name = self.fresh_name(mangle(&data.name, true));
+                let body = self.rx(&body)?.0;
+                writeln!(self.terminals, "{}{}: {}", name, priority, body).unwrap();
+                name
+            }
+        };
+        self.lexeme_refs.insert(lx, r.clone());
+        Ok(r)
+    }
+
+    fn render(mut self) -> Result<String> {
+        let symbols = self.reachable();
+
+        let mut classes = vec![];
+        for &sym in &symbols {
+            if let Some(lx) = self.grammar.sym_data(sym).lexeme {
+                let spec = self.spec.lexeme_spec(lx);
+                self.count_uses(&spec.rx);
+                if !classes.contains(&spec.class()) {
+                    classes.push(spec.class());
+                }
+            }
+        }
+        for cls in &classes {
+            let skip = &self.spec.lexeme_spec(self.spec.skip_id(*cls)).rx;
+            self.count_uses(skip);
+        }
+
+        self.names.insert("start".to_string());
+        for &sym in &symbols {
+            let data = self.grammar.sym_data(sym);
+            if data.is_terminal {
+                continue;
+            }
+            let name = if sym == self.grammar.start() {
+                "start".to_string()
+            } else {
+                self.fresh_name(mangle(&data.name, false))
+            };
+            self.sym_names.insert(sym, name);
+        }
+
+        let mut outp = String::new();
+        for warning in self.grammar.warnings() {
+            writeln!(
+                outp,
+                "// warning: {}",
+                warning.to_string().replace('\n', " ")
+            )
+            .unwrap();
+        }
+        if self.spec.no_forcing {
+            writeln!(outp, "%llguidance {{\"no_forcing\": true}}").unwrap();
+        }
+
+        if self.grammar.sym_data(self.grammar.start()).is_terminal {
+            let r = self.lexeme_ref(self.grammar.start())?;
+            writeln!(outp, "start: {}", r).unwrap();
+        }
+
+        for &sym in &symbols {
+            let data = self.grammar.sym_data(sym);
+            if data.is_terminal {
+                continue;
+            }
+            ensure!(
+                data.props.max_tokens == usize::MAX,
+                "max_tokens= on rule {:?} cannot be rendered as Lark",
+                data.name
+            );
+            let mut alts = vec![];
+            for rule in self.grammar.rules_of(sym) {
+                let mut elts = vec![];
+                for &s in self.grammar.rule_rhs(*rule).0 {
+                    if self.grammar.sym_data(s).is_terminal {
+                        elts.push(self.lexeme_ref(s)?);
+                    } else {
+                        elts.push(self.sym_names[&s].clone());
+                    }
+                }
+                alts.push(elts.join(" "));
+            }
+            if data.is_nullable {
+                alts.push("\"\"".to_string());
+            }
+            if alts.is_empty() {
+                ensure!(
+                    data.gen_grammar.is_none(),
+                    "unresolved grammar reference in {:?}",
+                    data.name
+                );
+                alts.push(format!("/{}/", NO_MATCH_RX));
+            }
+            let attrs = match &data.props.capture_name {
+                Some(name) => format!("[capture={}]", quote_string(name)),
+                None => String::new(),
+            };
+            writeln!(
+                outp,
+                "{}{}: {}",
+                self.sym_names[&sym],
+                attrs,
+                alts.join(" | ")
+            )
+            .unwrap();
+        }
+
+        let mut ignore = None;
+        for cls in &classes {
+            let skip = &self.spec.lexeme_spec(self.spec.skip_id(*cls)).rx;
+            let skip = self.rx(skip)?.0;
+            let skip = if skip == format!("/{}/", NO_MATCH_RX) {
+                None
+            } else {
+                Some(skip)
+            };
+            if classes.len() > 1 && ignore.is_some() {
+                ensure!(
+                    ignore == Some(skip.clone()),
+                    "sub-grammars with different whitespace cannot be rendered as one Lark grammar"
+                );
+            }
+            ignore = Some(skip);
+        }
+        if let Some(Some(skip)) = ignore {
+            writeln!(outp, "%ignore {}", skip).unwrap();
+        }
+
+        if !self.terminals.is_empty() {
+            writeln!(outp).unwrap();
+            outp.push_str(&self.terminals);
+        }
+
+        Ok(outp)
+    }
+}
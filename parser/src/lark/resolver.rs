@@ -0,0 +1,52 @@
+use anyhow::{anyhow, Result};
+
+use crate::HashMap;
+
+/// Supplies the source of Lark grammars referenced with `%import`.
+///
+/// For `%import sql.query (select, WHERE)` the resolver is asked for
+/// module `"sql.query"`.
+/// The built-in `common` module is handled internally and never reaches the resolver.
+pub trait ImportResolver: Send + Sync {
+    /// Return Lark source of the given (dot-separated) module path.
+    fn resolve(&self, module: &str) -> Result<String>;
+}
+
+/// Resolves imports from an in-memory map of module paths to Lark sources.
+/// An empty one is used by default, so that grammars can't read files
+/// unless the embedder explicitly provides a resolver that does so.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryImportResolver {
+    modules: HashMap<String, String>,
+}
+
+impl InMemoryImportResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_module(&mut self, module: &str, lark: &str) -> &mut Self {
+        self.modules.insert(module.to_string(), lark.to_string());
+        self
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for InMemoryImportResolver {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        InMemoryImportResolver {
+            modules: iter
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+impl ImportResolver for InMemoryImportResolver {
+    fn resolve(&self, module: &str) -> Result<String> {
+        self.modules
+            .get(module)
+            .cloned()
+            .ok_or_else(|| anyhow!("module {:?} not found", module))
+    }
+}
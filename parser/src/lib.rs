@@ -22,6 +22,12 @@ pub use constraint::{CommitResult, Constraint};
 mod factory;
 pub use factory::ParserFactory;
 
+mod matcher;
+pub use matcher::{byte_tok_env, GrammarMatcher, MatchStatus};
+
+mod cost;
+pub use cost::{estimate_cost, CostEstimate, CostEstimateOptions, Distribution};
+
 mod logging;
 pub use logging::Logger;
 
@@ -32,18 +38,21 @@ pub mod ffi;
 #[cfg(feature = "rayon")]
 mod ffi_par;
 
+mod ebnf;
 mod grammar_builder;
 mod json;
 #[cfg(feature = "jsonschema_validation")]
 mod json_validation;
 pub mod substring;
 pub use grammar_builder::{GrammarBuilder, NodeRef};
-pub use json::compiler::JsonCompileOptions;
+pub use json::compiler::{JsonCompileOptions, PropertyOrder};
 pub use stop_controller::StopController;
 pub use tokenizer_json::token_bytes_from_tokenizer_json;
 
 #[cfg(feature = "lark")]
 mod lark;
+#[cfg(feature = "lark")]
+pub use lark::{ImportResolver, InMemoryImportResolver};
 
 #[cfg(feature = "wasm")]
 pub use instant::Instant;
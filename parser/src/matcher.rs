@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use toktrie::{ApproximateTokEnv, TokEnv, TokRxInfo, TokTrie, TokenId};
+
+use crate::{
+    api::{GrammarInit, ParserLimits, TopLevelGrammar},
+    earley::Parser,
+    ImportResolver, InMemoryImportResolver, Logger,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum MatchStatus {
+    /// The bytes so far match the grammar (though more bytes may still be allowed).
+    Accept,
+    /// The bytes so far are a valid prefix, but not a complete match.
+    Prefix,
+    /// The bytes are not a prefix of anything the grammar matches.
+    Reject {
+        /// Offset of the first byte that is not allowed,
+        /// or the length of input if it ended too early.
+        offset: usize,
+        /// Lexemes allowed at `offset`.
+        expected: Vec<String>,
+        message: String,
+    },
+}
+
+impl MatchStatus {
+    pub fn is_accept(&self) -> bool {
+        matches!(self, MatchStatus::Accept)
+    }
+
+    pub fn is_reject(&self) -> bool {
+        matches!(self, MatchStatus::Reject { .. })
+    }
+}
+
+/// Checks bytes against a grammar, without an LLM tokenizer.
+/// Input can be fed in arbitrary chunks, not necessarily aligned to UTF-8 characters.
+///
+/// Internally, every byte is a separate token, so `max_tokens`
+/// limits in the grammar are counted in bytes.
+pub struct GrammarMatcher {
+    parser: Parser,
+    num_bytes: usize,
+    error: Option<MatchStatus>,
+}
+
+/// Tokenizer with one token per byte, plus EOS.
+pub fn byte_tok_env() -> TokEnv {
+    let mut words = (0..=255u8).map(|b| vec![b]).collect::<Vec<_>>();
+    let eos = words.len() as TokenId;
+    let mut eos_bytes = vec![TokTrie::SPECIAL_TOKEN_MARKER];
+    eos_bytes.extend_from_slice(b"</s>");
+    words.push(eos_bytes);
+    let trie = TokTrie::from(&TokRxInfo::new(words.len() as u32, eos), &words);
+    Arc::new(ApproximateTokEnv::new(trie))
+}
+
+impl GrammarMatcher {
+    pub fn new(grammar: TopLevelGrammar) -> Result<Self> {
+        Self::from_init(
+            GrammarInit::Serialized(grammar),
+            ParserLimits::default(),
+            Arc::new(InMemoryImportResolver::new()),
+        )
+    }
+
+    pub fn from_init(
+        init: GrammarInit,
+        limits: ParserLimits,
+        import_resolver: Arc<dyn ImportResolver>,
+    ) -> Result<Self> {
+        let tok_env = byte_tok_env();
+        let mut logger = Logger::new(0, 1);
+        let grammar = init.to_cgrammar_with_resolver(
+            Some(tok_env.clone()),
+            &mut logger,
+            limits.clone(),
+            vec![],
+            import_resolver,
+        )?;
+        let parser = Parser::new(tok_env, grammar, limits)?;
+        Ok(GrammarMatcher {
+            parser,
+            num_bytes: 0,
+            error: None,
+        })
+    }
+
+    /// Feed more bytes; after a rejection, further bytes are ignored.
+    pub fn push(&mut self, bytes: &[u8]) -> MatchStatus {
+        if self.error.is_some() {
+            return self.status();
+        }
+        for (idx, &b) in bytes.iter().enumerate() {
+            if self.parser.apply_token(&[b]).is_err() {
+                let rest = &bytes[idx..];
+                self.reject(format!(
+                    "unexpected {:?}",
+                    String::from_utf8_lossy(&rest[..rest.len().min(16)])
+                ));
+                break;
+            }
+            self.num_bytes += 1;
+        }
+        self.status()
+    }
+
+    /// Signal end of input; the result is either `Accept` or `Reject`.
+    pub fn finish(&mut self) -> MatchStatus {
+        if self.status() == MatchStatus::Prefix {
+            self.reject("unexpected end of input".to_string());
+        }
+        self.status()
+    }
+
+    /// Check complete input.
+    pub fn match_bytes(&mut self, bytes: &[u8]) -> MatchStatus {
+        self.push(bytes);
+        self.finish()
+    }
+
+    pub fn status(&mut self) -> MatchStatus {
+        if let Some(e) = &self.error {
+            e.clone()
+        } else if self.parser.is_accepting() {
+            MatchStatus::Accept
+        } else {
+            MatchStatus::Prefix
+        }
+    }
+
+    /// Number of bytes accepted so far.
+    pub fn num_bytes(&self) -> usize {
+        self.num_bytes
+    }
+
+    fn reject(&mut self, message: String) {
+        let message = match self.parser.get_error() {
+            Some(e) => e.message(),
+            None => message,
+        };
+        self.error = Some(MatchStatus::Reject {
+            offset: self.num_bytes,
+            expected: self.parser.expected_lexemes(),
+            message,
+        });
+    }
+}
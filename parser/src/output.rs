@@ -1,5 +1,7 @@
 use crate::HashSet;
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use toktrie::{bytes::to_hex_string, StepResult};
 
 use crate::{api::StopReason, earley, TokenParser};
@@ -19,6 +21,23 @@ pub enum ParserOutput {
         bytes: BytesOutput,
         log_prob: f64,
     },
+    /// Capture of a `%json` node, when `json_captures` option is set.
+    JsonCapture {
+        name: String,
+        value: Value,
+        /// Set when the value doesn't match the schema
+        /// (only checked with `jsonschema_validation` feature).
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        error: Option<String>,
+    },
+    /// A rule matched bytes `start..end` of the output, when `rule_events` option is set.
+    RuleCompleted {
+        rule: String,
+        start: usize,
+        end: usize,
+        /// Set when the rule matched this span in more than one way.
+        ambiguous: bool,
+    },
     FinalText {
         #[serde(flatten)]
         bytes: BytesOutput,
@@ -56,9 +75,21 @@ impl BytesOutput {
     }
 }
 
+/// Check `value` against `schema`; only done with `jsonschema_validation` feature.
+#[allow(unused_variables)]
+pub(crate) fn validate_json_capture(schema: &Value, value: &Value) -> Result<()> {
+    #[cfg(feature = "jsonschema_validation")]
+    {
+        use crate::json_validation::validate_value;
+        validate_value(schema, value)?;
+    }
+    Ok(())
+}
+
 #[derive(Clone, Default)]
 pub struct Reporter {
     reported_captures: usize,
+    reported_rule_events: usize,
     text_ptr: usize,
     token_ptr: usize,
     prev_stats: earley::ParserStats,
@@ -107,6 +138,21 @@ impl Reporter {
             .filter(|(name, _)| seen.insert(name))
             .collect::<Vec<_>>();
         for (name, val) in captures.iter().rev() {
+            let json = tok_parser
+                .parser
+                .grammar()
+                .json_capture_schema(name)
+                .and_then(|schema| Some((schema, serde_json::from_slice(val).ok()?)));
+            if let Some((schema, value)) = json {
+                res.push(ParserOutput::JsonCapture {
+                    name: name.clone(),
+                    error: validate_json_capture(schema, &value)
+                        .err()
+                        .map(|e| e.to_string()),
+                    value,
+                });
+                continue;
+            }
             res.push(ParserOutput::Capture {
                 name: name.clone(),
                 bytes: val.as_slice().into(),
@@ -114,6 +160,18 @@ impl Reporter {
             });
         }
 
+        // then rules matched since last time
+        let rule_events = &tok_parser.parser.rule_events()[self.reported_rule_events..];
+        self.reported_rule_events += rule_events.len();
+        for ev in rule_events {
+            res.push(ParserOutput::RuleCompleted {
+                rule: ev.rule.clone(),
+                start: ev.start,
+                end: ev.end,
+                ambiguous: ev.ambiguous,
+            });
+        }
+
         // compute stats
         let delta = tok_parser.parser_stats().delta(&self.prev_stats);
         self.prev_stats = tok_parser.parser_stats().clone();
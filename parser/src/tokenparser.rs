@@ -1,11 +1,16 @@
 use std::{hint::black_box, panic::AssertUnwindSafe, sync::Arc, time::Duration};
 
 use crate::{
-    api::{GrammarInit, ParserLimits, StopReason, TopLevelGrammar},
-    earley::{BiasComputer, DefaultBiasComputer, Parser, ParserError, ParserStats},
-    infoln, panic_utils, warn, Instant, Logger,
+    api::{GrammarInit, GrammarWarning, ParserLimits, StopReason, TopLevelGrammar},
+    earley::{
+        BiasComputer, DefaultBiasComputer, Parser, ParserError, ParserStats, RejectionReport,
+    },
+    infoln,
+    output::validate_json_capture,
+    panic_utils, warn, ImportResolver, InMemoryImportResolver, Instant, Logger,
 };
 use anyhow::{ensure, Result};
+use serde_json::Value;
 use toktrie::{InferenceCapabilities, SimpleVob, TokEnv, TokenId, INVALID_TOKEN};
 
 #[derive(Clone)]
@@ -25,6 +30,9 @@ pub struct TokenParser {
     is_accepting_cache: Option<bool>,
     stop_reason: StopReason,
     error_message: Option<String>,
+    rejection: Option<RejectionReport>,
+    // tokens passed to the last validate_tokens_raw(), up to the first invalid one
+    rejected_tokens: Option<Vec<TokenId>>,
     max_tokens_total: usize,
 
     // tokens currently in KV cache
@@ -43,6 +51,26 @@ impl TokenParser {
         inference_caps: InferenceCapabilities,
         limits: ParserLimits,
         extra_lexemes: Vec<String>,
+    ) -> Result<Self> {
+        Self::from_init_with_resolver(
+            token_env,
+            grammar_init,
+            logger,
+            inference_caps,
+            limits,
+            extra_lexemes,
+            Arc::new(InMemoryImportResolver::new()),
+        )
+    }
+
+    pub fn from_init_with_resolver(
+        token_env: TokEnv,
+        grammar_init: GrammarInit,
+        logger: Logger,
+        inference_caps: InferenceCapabilities,
+        limits: ParserLimits,
+        extra_lexemes: Vec<String>,
+        import_resolver: Arc<dyn ImportResolver>,
     ) -> Result<Self> {
         panic_utils::catch_unwind(AssertUnwindSafe(|| {
             Self::init_inner(
@@ -52,6 +80,7 @@ impl TokenParser {
                 inference_caps,
                 limits,
                 extra_lexemes,
+                import_resolver,
             )
         }))
     }
@@ -81,6 +110,7 @@ impl TokenParser {
         inference_caps: InferenceCapabilities,
         limits: ParserLimits,
         extra_lexemes: Vec<String>,
+        import_resolver: Arc<dyn ImportResolver>,
     ) -> Result<Self> {
         ensure!(
             token_env.tokenize_is_canonical() || !inference_caps.ff_tokens,
@@ -98,11 +128,12 @@ impl TokenParser {
                 max_tokens = m;
             }
         }
-        let compiled_grammar = grammar_init.to_cgrammar(
+        let compiled_grammar = grammar_init.to_cgrammar_with_resolver(
             Some(token_env.clone()),
             &mut logger,
             limits.clone(),
             extra_lexemes,
+            import_resolver,
         )?;
         let parser = Parser::new(token_env.clone(), compiled_grammar, limits.clone())?;
         let eos_token = token_env.tok_trie().eos_token();
@@ -119,6 +150,8 @@ impl TokenParser {
             is_accepting_cache: None,
             stop_reason: StopReason::NotStopped,
             error_message: None,
+            rejection: None,
+            rejected_tokens: None,
             parser,
             eos_token,
             llm_tokens: Vec::new(),
@@ -130,10 +163,32 @@ impl TokenParser {
         })
     }
 
+    /// Non-fatal problems found while compiling the grammar,
+    /// e.g., JSON schema keywords ignored in lenient mode.
+    pub fn warnings(&self) -> &[GrammarWarning] {
+        self.parser.grammar().warnings()
+    }
+
     pub fn get_capture(&self, name: &str) -> Option<&[u8]> {
         self.parser.get_capture(name)
     }
 
+    /// Parse the capture of a `%json` node, and check it against the schema
+    /// (with `jsonschema_validation` feature).
+    /// Only returns `Some` when the `json_captures` option is set.
+    pub fn get_json_capture(&self, name: &str) -> Option<Result<Value>> {
+        let schema = self.parser.grammar().json_capture_schema(name)?;
+        let bytes = self.get_capture(name)?;
+        Some(
+            serde_json::from_slice(bytes)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| {
+                    validate_json_capture(schema, &value)?;
+                    Ok(value)
+                }),
+        )
+    }
+
     // regular .clone() uses a shared lexer state
     pub fn deep_clone(&self) -> Self {
         let mut copy = self.clone();
@@ -298,6 +353,35 @@ impl TokenParser {
         self.error_message.clone()
     }
 
+    /// Explain why the parser rejected a token, either in consume_token()
+    /// (which then stops the parser), or in the last validate_tokens_raw() call.
+    pub fn rejection_report(&self) -> Option<RejectionReport> {
+        if let Some(r) = &self.rejection {
+            return Some(r.clone());
+        }
+
+        let tokens = self.rejected_tokens.as_ref()?;
+        let (&rejected, valid) = tokens.split_last()?;
+        let trie = self.tok_trie();
+        let mut parser = self.parser.deep_clone();
+        for &t in valid {
+            if t != self.eos_token {
+                parser.apply_token(&trie.decode_raw(&[t])).ok()?;
+            }
+        }
+        let tok_bytes = if rejected == self.eos_token {
+            vec![]
+        } else {
+            trie.decode_raw(&[rejected])
+        };
+        let applied_before = parser.num_applied_bytes();
+        let _ = parser.apply_token(&tok_bytes);
+        let num_ok = (parser.num_applied_bytes() - applied_before).min(tok_bytes.len());
+        let mut report = parser.rejection_report(&tok_bytes[num_ok..]);
+        report.token = Some(trie.token_dbg(rejected));
+        Some(report)
+    }
+
     fn check_initialized(&self, lbl: &str) -> Result<()> {
         ensure!(!self.is_fresh, "process_prompt() not called in {}", lbl);
         ensure!(
@@ -336,6 +420,8 @@ impl TokenParser {
         // this will fail in case we're in error state or not initialized
         self.check_initialized("rollback")?;
 
+        self.rejected_tokens = None;
+
         let new_len = self.llm_tokens.len() - n_tokens;
         let mut bytes_to_drop = 0;
         for tok in &self.llm_tokens[new_len..] {
@@ -375,6 +461,11 @@ impl TokenParser {
         }
 
         let n_valid = self.parser.validate_tokens(tokens);
+        self.rejected_tokens = if n_valid < tokens.len() {
+            Some(tokens[..=n_valid].to_vec())
+        } else {
+            None
+        };
         Ok(n_valid)
     }
 
@@ -494,10 +585,18 @@ impl TokenParser {
         }
 
         // now apply normally
+        let applied_before = self.parser.num_applied_bytes();
         match self.parser.apply_token(tok_bytes) {
             Err(e) => {
+                infoln!(self, "apply_token error: {}", e);
+                let num_ok =
+                    (self.parser.num_applied_bytes() - applied_before).min(tok_bytes.len());
+                let mut report = self.parser.rejection_report(&tok_bytes[num_ok..]);
+                report.token = Some(trie.token_dbg(tok_id));
+                let msg = format!("Parser Error: {}", report);
+                self.rejection = Some(report);
                 return Err(self.stop(
-                    &format!("Parser Error: {}", e),
+                    &msg,
                     StopReason::ParserTooComplex, // TODO - there are other reasons
                 ));
             }
@@ -701,6 +800,7 @@ impl TokenParser {
     /// Returns number of tokens to backtrack if any.
     pub fn consume_token(&mut self, token: TokenId) -> Result<usize> {
         self.check_initialized("consume_token")?;
+        self.rejected_tokens = None;
 
         if self.max_tokens_total == 0 {
             return Err(self.stop("max_tokens_total reached", StopReason::MaxTokensTotal));
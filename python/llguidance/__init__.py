@@ -5,6 +5,7 @@ from ._lib import (
     LarkCompiler,
     RegexCompiler,
     LLExecutor,
+    GrammarMatcher,
 )
 from ._tokenizer import TokenizerWrapper
 
@@ -15,5 +16,6 @@ __all__ = [
     "JsonCompiler",
     "LarkCompiler",
     "RegexCompiler",
+    "GrammarMatcher",
     "TokenizerWrapper",
 ]
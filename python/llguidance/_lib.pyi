@@ -1,4 +1,4 @@
-from typing import List, Tuple, Mapping, Optional, Sequence, Union
+from typing import Dict, List, Tuple, Mapping, Optional, Sequence, Union
 from ._util import TokenId, StopReason
 from ._tokenizer import TokenizerWrapper
 
@@ -174,11 +174,23 @@ class JsonCompiler:
         separators: Optional[Tuple[str, str]] = None,
         whitespace_flexible: bool = False,
         coerce_one_of: bool = False,
+        custom_formats: Optional[Dict[str, str]] = None,
+        lenient: bool = False,
+        indent: Optional[int] = None,
+        property_order: str = "declared",
     ) -> "JsonCompiler":
         """
         Create a new JSON compiler.
         Args:
             compact: bool - whether to use compact JSON representation
+            custom_formats: Dict[str, str] - additional string "format"s, mapping
+                format name to a regex; these take precedence over built-in formats
+            lenient: bool - drop unsupported keywords (reporting them as warnings)
+                instead of failing
+            indent: int - pretty-print with this many spaces per nesting level,
+                exactly like json.dumps(indent=...); overrides whitespace_flexible
+            property_order: str - "declared", "required_first" or "any_order"
+                (each property still at most once; up to 8 properties)
         """
 
     def compile(
@@ -189,6 +201,25 @@ class JsonCompiler:
         Compile the JSON representation of the AG2 grammar/constraint.
         """
 
+    def compile_with_warnings(
+        self,
+        schema: str,
+    ) -> Tuple[str, str]:
+        """
+        Like compile(), but also return JSON-encoded list of warnings.
+        Each warning has a "message", and, for ignored keywords,
+        "json_pointer" of the sub-schema and the "keyword".
+        """
+
+    def compile_to_lark(
+        self,
+        schema: str,
+    ) -> str:
+        """
+        Compile the schema and render the resulting grammar in Lark syntax
+        (as accepted by LarkCompiler), e.g., for inspection or hand-tuning.
+        """
+
 class LarkCompiler:
     def __new__(
         cls,
@@ -244,3 +275,38 @@ class LLExecutor:
         Perform next parsing step.
         Returns: a JSON string.
         """
+
+class GrammarMatcher:
+    def __new__(
+        cls,
+        grammar: str,
+    ) -> "GrammarMatcher":
+        """
+        Create a new byte-level matcher, which checks text against a grammar without a tokenizer.
+        Args:
+            grammar: str - either a Lark grammar or stringified JSON representation of LLGuidance grammar
+        """
+
+    def push(self, data: bytes) -> str:
+        """
+        Feed more bytes to the matcher; they don't need to be valid UTF-8 on their own.
+        Returns: a JSON string with "status" of "accept", "prefix", or "reject";
+        the latter also includes "offset" of the first invalid byte, "expected" lexemes, and "message".
+        Once rejected, further bytes are ignored.
+        """
+
+    def finish(self) -> str:
+        """
+        Signal end of input.
+        Returns: a JSON string, as in push(), but "prefix" status is turned into "reject".
+        """
+
+    def status(self) -> str:
+        """
+        Returns: a JSON string with the current status, as in push().
+        """
+
+    def num_bytes(self) -> int:
+        """
+        Returns: the number of bytes accepted so far.
+        """
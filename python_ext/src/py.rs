@@ -2,7 +2,7 @@ use std::fmt::Display;
 use std::ops::DerefMut;
 use std::{borrow::Cow, sync::Arc};
 
-use llguidance::api::{GrammarInit, ParserLimits};
+use llguidance::api::{GrammarInit, GrammarWarning, ParserLimits};
 use llguidance::earley::SlicedBiasComputer;
 use llguidance::toktrie::{
     self, ApproximateTokEnv, InferenceCapabilities, TokEnv, TokRxInfo, TokTrie, TokenId,
@@ -10,7 +10,8 @@ use llguidance::toktrie::{
 };
 use llguidance::{api::TopLevelGrammar, output::ParserOutput, TokenParser};
 use llguidance::{
-    token_bytes_from_tokenizer_json, Constraint, JsonCompileOptions, Logger, ParserFactory,
+    token_bytes_from_tokenizer_json, Constraint, GrammarMatcher, HashMap, JsonCompileOptions,
+    Logger, ParserFactory, PropertyOrder,
 };
 use pyo3::types::{PyByteArray, PyList};
 use pyo3::{exceptions::PyValueError, prelude::*};
@@ -519,50 +520,91 @@ struct JsonCompiler {
     item_separator: String,
     key_separator: String,
     whitespace_flexible: bool,
+    indent: Option<usize>,
     coerce_one_of: bool,
+    property_order: PropertyOrder,
+    custom_formats: HashMap<String, String>,
+    lenient: bool,
+}
+
+impl JsonCompiler {
+    fn top_level_grammar(&self, schema: &str) -> PyResult<TopLevelGrammar> {
+        let mut schema: Value = serde_json::from_str(schema).map_err(val_error)?;
+        let compile_options = JsonCompileOptions {
+            item_separator: self.item_separator.clone(),
+            key_separator: Some(self.key_separator.clone()),
+            whitespace_flexible: self.whitespace_flexible,
+            indent: self.indent,
+            coerce_one_of: self.coerce_one_of,
+            property_order: self.property_order,
+            custom_formats: self.custom_formats.clone(),
+            lenient: self.lenient,
+            retriever: None,
+        };
+        compile_options.apply_to(&mut schema);
+        Ok(TopLevelGrammar::from_json_schema(schema))
+    }
+
+    fn compile_inner(&self, schema: &str) -> PyResult<(String, Vec<GrammarWarning>)> {
+        let grm = self.top_level_grammar(schema)?;
+        let res = serde_json::to_string(&grm).map_err(val_error)?;
+        let g_init = GrammarInit::Serialized(grm);
+        // this compiles the grammar and signals errors
+        let (grammar, _) = g_init
+            .to_internal(None, ParserLimits::default())
+            .map_err(val_error)?;
+        Ok((res, grammar.warnings().to_vec()))
+    }
 }
 
 #[pymethods]
 impl JsonCompiler {
     #[new]
-    #[pyo3(signature = (separators = None, whitespace_flexible = false, coerce_one_of = false))]
+    #[pyo3(signature = (separators = None, whitespace_flexible = false, coerce_one_of = false, custom_formats = None, lenient = false, indent = None, property_order = "declared"))]
     fn py_new(
         separators: Option<(String, String)>,
         whitespace_flexible: bool,
         coerce_one_of: bool,
-    ) -> Self {
+        custom_formats: Option<HashMap<String, String>>,
+        lenient: bool,
+        indent: Option<usize>,
+        property_order: &str,
+    ) -> PyResult<Self> {
+        let property_order: PropertyOrder =
+            serde_json::from_value(Value::String(property_order.to_string())).map_err(val_error)?;
         let (item_separator, key_separator) = separators.unwrap_or_else(|| {
-            if whitespace_flexible {
+            if indent.is_some() {
+                // same as json.dumps(indent=...)
+                (",".to_owned(), ": ".to_owned())
+            } else if whitespace_flexible {
                 (",".to_owned(), ":".to_owned())
             } else {
                 (", ".to_owned(), ": ".to_owned())
             }
         });
-        JsonCompiler {
+        Ok(JsonCompiler {
             item_separator,
             key_separator,
             whitespace_flexible,
+            indent,
             coerce_one_of,
-        }
+            property_order,
+            custom_formats: custom_formats.unwrap_or_default(),
+            lenient,
+        })
     }
     fn compile(&self, schema: &str) -> PyResult<String> {
-        let mut schema: Value = serde_json::from_str(schema).map_err(val_error)?;
-        let compile_options = JsonCompileOptions {
-            item_separator: self.item_separator.clone(),
-            key_separator: self.key_separator.clone(),
-            whitespace_flexible: self.whitespace_flexible,
-            coerce_one_of: self.coerce_one_of,
-            retriever: None,
-        };
-        compile_options.apply_to(&mut schema);
-        let grm = TopLevelGrammar::from_json_schema(schema);
-        let res = serde_json::to_string(&grm).map_err(val_error)?;
-        let g_init = GrammarInit::Serialized(grm);
-        // this compiles the grammar and signals errors
-        let _ = g_init
-            .to_internal(None, ParserLimits::default())
-            .map_err(val_error)?;
-        Ok(res)
+        Ok(self.compile_inner(schema)?.0)
+    }
+    fn compile_with_warnings(&self, schema: &str) -> PyResult<(String, String)> {
+        let (res, warnings) = self.compile_inner(schema)?;
+        let warnings = serde_json::to_string(&warnings).map_err(val_error)?;
+        Ok((res, warnings))
+    }
+    fn compile_to_lark(&self, schema: &str) -> PyResult<String> {
+        GrammarInit::Serialized(self.top_level_grammar(schema)?)
+            .to_lark(None, &mut Logger::new(0, 0), ParserLimits::default())
+            .map_err(val_error)
     }
 }
 
@@ -598,6 +640,40 @@ impl RegexCompiler {
     }
 }
 
+#[pyclass(name = "GrammarMatcher")]
+struct PyGrammarMatcher {
+    inner: GrammarMatcher,
+}
+
+#[pymethods]
+impl PyGrammarMatcher {
+    #[new]
+    fn py_new(grammar: &str) -> PyResult<Self> {
+        let grammar = TopLevelGrammar::from_lark_or_json_schema(grammar).map_err(val_error)?;
+        let inner = GrammarMatcher::new(grammar).map_err(val_error)?;
+        Ok(PyGrammarMatcher { inner })
+    }
+
+    fn push(&mut self, data: &[u8]) -> PyResult<String> {
+        let status = self.inner.push(data);
+        serde_json::to_string(&status).map_err(val_error)
+    }
+
+    fn finish(&mut self) -> PyResult<String> {
+        let status = self.inner.finish();
+        serde_json::to_string(&status).map_err(val_error)
+    }
+
+    fn status(&mut self) -> PyResult<String> {
+        let status = self.inner.status();
+        serde_json::to_string(&status).map_err(val_error)
+    }
+
+    fn num_bytes(&self) -> usize {
+        self.inner.num_bytes()
+    }
+}
+
 pub(crate) fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<LLTokenizer>()?;
     m.add_class::<LLInterpreter>()?;
@@ -605,6 +681,7 @@ pub(crate) fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<JsonCompiler>()?;
     m.add_class::<LarkCompiler>()?;
     m.add_class::<RegexCompiler>()?;
+    m.add_class::<PyGrammarMatcher>()?;
     Ok(())
 }
 
@@ -1,11 +1,12 @@
 use clap::Parser;
-use std::{fs::File, hint::black_box, io::Read, vec};
+use std::{fs::File, hint::black_box, io::Read, sync::Arc, vec};
 
 use llguidance::{
-    api::{ParserLimits, TopLevelGrammar},
+    api::{GrammarInit, ParserLimits, TopLevelGrammar},
     earley::{SlicedBiasComputer, XorShift},
+    estimate_cost,
     toktrie::{InferenceCapabilities, TokEnv},
-    Constraint, TokenParser,
+    Constraint, CostEstimate, CostEstimateOptions, InMemoryImportResolver, TokenParser,
 };
 use serde_json::json;
 
@@ -65,7 +66,21 @@ pub struct CliOptions {
     #[arg(long, default_value = "1")]
     lexer_limit: usize,
 
-    /// .ll.json/.schema.json/.lark/.txt file
+    /// Print the grammar (after compilation) in Lark syntax and exit
+    #[arg(long)]
+    to_lark: bool,
+
+    /// Print lints (unreachable/unproductive rules, nullable cycles, right recursion, ...) and exit;
+    /// the exit code is 1 if there are any
+    #[arg(long)]
+    lint: bool,
+
+    /// Generate random outputs (up to --rnd tokens each) and print grammar cost
+    /// (lexer states, Earley items, mask times) as JSON
+    #[arg(long)]
+    estimate_cost: bool,
+
+    /// .ll.json/.schema.json/.lark/.gbnf/.ebnf/.txt file
     #[arg(value_name = "GRAMMAR")]
     file: String,
 }
@@ -85,6 +100,10 @@ fn main() {
         TopLevelGrammar::from_json_schema(val)
     } else if opts.file.ends_with(".lark") {
         TopLevelGrammar::from_lark(grammar_file)
+    } else if opts.file.ends_with(".gbnf") {
+        TopLevelGrammar::from_gbnf(grammar_file)
+    } else if opts.file.ends_with(".ebnf") {
+        TopLevelGrammar::from_ebnf(grammar_file)
     } else if opts.file.ends_with(".txt") {
         let regex_opts = if opts.split_words {
             json!({
@@ -104,6 +123,35 @@ fn main() {
         panic!("Unknown schema file extension")
     };
 
+    if opts.to_lark {
+        let lark = GrammarInit::Serialized(grammar)
+            .to_lark(
+                None,
+                &mut llguidance::Logger::new(0, opts.log_level),
+                ParserLimits::default(),
+            )
+            .expect("Failed to render grammar");
+        print!("{}", lark);
+        return;
+    }
+
+    if opts.lint {
+        let lints = GrammarInit::Serialized(grammar)
+            .lint(
+                None,
+                ParserLimits::default(),
+                Arc::new(InMemoryImportResolver::new()),
+            )
+            .expect("Failed to compile grammar");
+        for lint in &lints {
+            println!("{}", lint);
+        }
+        if !lints.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // you can implement TokEnv yourself, if you have the tokenizer
     // see the ByteTokenizerEnv for an example
     let tok_env: TokEnv = toktrie_hf_tokenizers::ByteTokenizerEnv::from_name(&opts.tokenizer, None)
@@ -139,6 +187,22 @@ fn main() {
         SlicedBiasComputer::general_slices(),
     )
     .unwrap();
+
+    if opts.estimate_cost {
+        let compile_time_us = t0.elapsed().as_micros() as u64;
+        let options = CostEstimateOptions {
+            max_tokens: opts.rnd.unwrap_or(200),
+            seed: opts.seed,
+            ..Default::default()
+        };
+        let est = CostEstimate {
+            compile_time_us,
+            ..estimate_cost(&parser, &options)
+        };
+        println!("{}", serde_json::to_string_pretty(&est).unwrap());
+        return;
+    }
+
     let mut constraint = Constraint::new(parser);
 
     // enable sending parser results back via the logs (constraint.flush_logs())
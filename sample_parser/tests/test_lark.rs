@@ -1,7 +1,14 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use llguidance::{
-    api::TopLevelGrammar, earley::XorShift, substring::chunk_into_words, toktrie::bytes::limit_str,
-    TokenParser,
+    api::{GrammarInit, ParserLimits, TopLevelGrammar},
+    byte_tok_env,
+    earley::XorShift,
+    estimate_cost,
+    substring::chunk_into_words,
+    toktrie::bytes::limit_str,
+    CostEstimateOptions, GrammarMatcher, InMemoryImportResolver, Logger, MatchStatus, TokenParser,
 };
 use sample_parser::*;
 
@@ -316,7 +323,7 @@ fn test_lark_syntax_perc() {
     lark_err_test(r#"start: %json { foo"#, "key must be a string");
     lark_err_test(r#"start: %json []"#, "failed to compile JSON schema");
     lark_err_test(
-        r#"start: %json { "if": {} }"#,
+        r#"start: %json { "unevaluatedProperties": false }"#,
         "failed to compile JSON schema",
     );
 
@@ -525,6 +532,440 @@ fn test_lexeme_substring_words_unicode() {
     );
 }
 
+#[test]
+fn test_lexeme_numeric_range() {
+    lark_str_test_many(
+        r#"
+            start: "x=" BOUNDED_NUM ";"
+            BOUNDED_NUM: %regex {
+                "type": "number",
+                "minimum": -17.3,
+                "maximum": 33.721
+            }
+        "#,
+        &["x=0;", "x=-17.3;", "x=33.721;", "x=1.5;", "x=-3;"],
+        &["x=-17.31;", "x=33.7211;", "x=100;", "x=1e3;"],
+    );
+
+    lark_str_test_many(
+        r#"
+            start: MULT_NUM ("," MULT_NUM)*
+            MULT_NUM: %regex {
+                "type": "integer",
+                "exclusiveMinimum": 0,
+                "multipleOf": 10
+            }
+        "#,
+        &["10", "20,30", "1000"],
+        &["0", "FINAL_REJECT:5", "-10", "10.0"],
+    );
+
+    lark_str_test_many(
+        r#"start: %regex { "type": "integer", "minimum": 1.5, "exclusiveMaximum": 4 }"#,
+        &["2", "3"],
+        &["1", "4"],
+    );
+
+    lark_err_test(
+        r#"start: %regex { "type": "integer", "minimum": 5, "maximum": 3 }"#,
+        "minimum (5) is greater than maximum (3)",
+    );
+    lark_err_test(
+        r#"start: %regex { "type": "string" }"#,
+        "unknown %regex type",
+    );
+    lark_err_test(
+        r#"start: %regex { "minimum": 3 }"#,
+        "numeric constraints on %regex require",
+    );
+    lark_err_test(
+        r#"start: %regex { "type": "integer", "substring_chars": "abc" }"#,
+        "only one field can be set on %regex",
+    );
+}
+
+#[test]
+fn test_lexeme_and_not() {
+    lark_str_test_many(
+        r#"
+            start: ASCII_LINES
+            ASCII_LINES: /[a-zA-Z \n]*/ & ~/(?s:.*)\n\n(?s:.*)/
+        "#,
+        &["", "abc", "ab\ncd", "a\nb\nc\n"],
+        &["ab\n\ncd", "a1"],
+    );
+
+    // '&' binds weaker than concatenation, '~' applies to the following expression
+    lark_str_test_many(
+        r#"
+            start: WORD | "$" NUM
+            WORD: "a" /[a-z]*/ & ~"abc" & /.{2,4}/
+            NUM: /[0-9a-z]+/ & ~/[0-9]/*
+        "#,
+        &["ab", "abcd", "aaaa", "$1a"],
+        &[
+            "FINAL_REJECT:abc",
+            "FINAL_REJECT:a",
+            "aaaaa",
+            "bcd",
+            "FINAL_REJECT:$12",
+        ],
+    );
+
+    // ranges still work
+    lark_str_test_many(
+        r#"
+            start: A
+            A: "a" ~ 2 ~"b" "c"
+        "#,
+        &["aac", "aabbc", "aaxc"],
+        &["FINAL_REJECT:aabc", "ac"],
+    );
+
+    // can be used directly in rules, when all parts are terminals
+    lark_str_test_many(
+        r#"start: (/[a-z]+/ & ~("foo" | "bar")) ";""#,
+        &["x;", "fooo;", "ba;"],
+        &["foo;", "bar;"],
+    );
+
+    lark_err_test(
+        r#"
+            start: foo & "x"
+            foo: "x"
+        "#,
+        "unknown name",
+    );
+}
+
+#[test]
+fn test_lark_templates() {
+    lark_str_test_many(
+        r#"
+            start: _separated{row, "\n"}
+            row: _separated{CELL, ","}
+            _separated{x, sep}: x (sep x)*
+            CELL: /[a-z0-9]+/
+        "#,
+        &["a", "a,b", "a,b\nc", "a\nb,c,d\ne"],
+        &["FINAL_REJECT:", "FINAL_REJECT:a,", "a,,b", "a\n\nb"],
+    );
+
+    // nested and recursive templates
+    lark_str_test_many(
+        r#"
+            start: list{list{NUM}}
+            list{item}: "[" [_items{item}] "]"
+            _items{x}: x | x "," _items{x}
+            NUM: /[0-9]+/
+        "#,
+        &["[]", "[[]]", "[[1],[2,3]]", "[[1,2,3],[]]"],
+        &["[1]", "[[1,]]", "FINAL_REJECT:[[1]"],
+    );
+
+    // parameters can be bound to terminals, strings, regexes and rules
+    lark_str_test_many(
+        r#"
+            start: pair{"a", /[0-9]/} ";" pair{b, B}
+            pair{k, v}: k "=" v
+            b: "b"
+            B: "B"
+        "#,
+        &["a=1;b=B"],
+        &["a=a;b=B", "a=1;B=b"],
+    );
+
+    lark_err_test(
+        r#"
+            start: foo{"a"}
+            foo{x, y}: x y
+        "#,
+        "template \"foo\" expects 2 arguments, got 1",
+    );
+    lark_err_test(
+        r#"
+            start: foo
+            foo{x}: x
+        "#,
+        "template \"foo\" used without arguments",
+    );
+    lark_err_test(r#"start: foo{"a"}"#, "template \"foo\" not found");
+    lark_err_test(
+        r#"
+            start: A
+            A: foo{"a"}
+            foo{x}: x
+        "#,
+        "cannot be used in terminals",
+    );
+    lark_err_test(
+        r#"
+            start: f{A}
+            f{x}: x | f{g{x}}
+            g{y}: "(" y ")"
+            A: "a"
+        "#,
+        "template expansion too deep",
+    );
+}
+
+#[test]
+fn test_lark_common_imports() {
+    lark_str_test_many(
+        r#"
+            %import common (JSON_STRING, JSON_NUMBER, JSON_INT)
+            start: JSON_STRING | "n:" JSON_NUMBER | "i:" JSON_INT
+        "#,
+        &[
+            r#""""#,
+            r#""a\"b\\cé\n""#,
+            "n:0",
+            "n:-1.5e+10",
+            "n:12.25",
+            "i:-42",
+        ],
+        &[
+            r#""a"b""#,
+            r#""\x""#,
+            "\"a\nb\"",
+            "n:01",
+            "FINAL_REJECT:n:1.",
+            "n:.5",
+            "i:1.0",
+            "i:+1",
+        ],
+    );
+
+    lark_str_test_many(
+        r#"
+            %import common (RFC3339_DATE_TIME, RFC3339_DATE, RFC3339_TIME, UUID)
+            start: "dt:" RFC3339_DATE_TIME | "d:" RFC3339_DATE | "t:" RFC3339_TIME | "u:" UUID
+        "#,
+        &[
+            "dt:2024-02-29T12:30:00Z",
+            "dt:1999-12-31t23:59:60.123+01:00",
+            "d:2024-04-30",
+            "t:08:15:00-05:00",
+            "u:123e4567-e89b-12d3-a456-426614174000",
+        ],
+        &[
+            "dt:2024-02-30T12:30:00Z",
+            "dt:2024-01-01T24:00:00Z",
+            "FINAL_REJECT:dt:2024-01-01T12:00:00",
+            "d:2024-04-31",
+            "FINAL_REJECT:t:08:15:00",
+            "u:123e4567e89b12d3a456426614174000",
+            "u:123e4567-e89b-12d3-a456-42661417400g",
+        ],
+    );
+
+    lark_str_test_many(
+        r#"
+            %import common (PYTHON_NAME, PYTHON_STRING, C_NAME, C_STRING)
+            start: "py " PYTHON_NAME " " PYTHON_STRING | "c " C_NAME " " C_STRING
+        "#,
+        &[
+            "py _x1 'a'",
+            "py zażółć \"it's\"",
+            r#"py x rb'\d+'"#,
+            "py x '''multi\nline 'quoted' ''text'''",
+            "py x f\"\"\"a\"b\"\"\"",
+            "c main \"hello\\n\"",
+            "c _x9 u8\"\\\"q\\\"\"",
+        ],
+        &[
+            "py 1x 'a'",
+            "py x 'a\nb'",
+            "FINAL_REJECT:py x 'a\"",
+            "py x x'a'",
+            "c zażółć \"a\"",
+            "c x 'a'",
+            "c x \"a\nb\"",
+        ],
+    );
+
+    lark_str_test_many(
+        r#"
+            %import common (IPV4, IPV6, URI, SEMVER, HEX_COLOR)
+            start: "4 " IPV4 | "6 " IPV6 | "u " URI | "v " SEMVER | "c " HEX_COLOR
+        "#,
+        &[
+            "4 192.168.0.1",
+            "4 255.255.255.255",
+            "6 ::1",
+            "6 2001:db8::8a2e:370:7334",
+            "6 fe80:0:0:0:0:0:0:1",
+            "u https://example.com/a/b?x=1&y=%20#frag",
+            "u mailto:joe@example.com",
+            "u urn:isbn:0451450523",
+            "v 1.0.0",
+            "v 1.2.3-alpha.1+build.5",
+            "v 10.20.30-rc.1",
+            "c #fff",
+            "c #0a0B0c",
+            "c #11223344",
+        ],
+        &[
+            "4 256.1.1.1",
+            "FINAL_REJECT:4 1.2.3",
+            "6 1:2:3:4:5:6:7:8:9",
+            "6 ::g",
+            "FINAL_REJECT:u example.com",
+            "u http://a b",
+            "FINAL_REJECT:u http://a%2",
+            "FINAL_REJECT:v 1.2",
+            "v 01.2.3",
+            "FINAL_REJECT:v 1.2.3-01",
+            "c fff",
+            "FINAL_REJECT:c #ff",
+            "FINAL_REJECT:c #fffff",
+        ],
+    );
+
+    lark_err_test(
+        r#"
+            %import common.JSON_BLAH
+            start: JSON_BLAH
+        "#,
+        "common.JSON_NUMBER",
+    );
+}
+
+fn make_parser_with_imports(modules: &[(&str, &str)], lark: &str) -> Result<TokenParser> {
+    let factory = get_parser_factory();
+    let resolver: InMemoryImportResolver = modules.iter().copied().collect();
+    let mut parser = TokenParser::from_init_with_resolver(
+        factory.tok_env().clone(),
+        GrammarInit::Serialized(TopLevelGrammar::from_lark(lark.to_string())),
+        Logger::new(0, 1),
+        Default::default(),
+        factory.limits().clone(),
+        factory.extra_lexemes(),
+        Arc::new(resolver),
+    )?;
+    factory.post_process_parser(&mut parser);
+    parser.start_without_prompt();
+    Ok(parser)
+}
+
+fn lark_import_test(modules: &[(&str, &str)], lark: &str, passing: &[&str], failing: &[&str]) {
+    for (input, should_accept) in passing
+        .iter()
+        .map(|s| (s, true))
+        .chain(failing.iter().map(|s| (s, false)))
+    {
+        let mut p = make_parser_with_imports(modules, lark).unwrap();
+        let mut accepted = true;
+        for tok in get_tok_env().tokenize(input) {
+            if !p.compute_mask().unwrap().is_allowed(tok) {
+                accepted = false;
+                break;
+            }
+            consume(&mut p, tok);
+        }
+        accepted = accepted && p.is_accepting();
+        assert_eq!(
+            accepted, should_accept,
+            "input: {:?}; grm:\n{}",
+            input, lark
+        );
+    }
+}
+
+fn lark_import_err_test(modules: &[(&str, &str)], lark: &str, err: &str) {
+    match make_parser_with_imports(modules, lark) {
+        Err(e) => {
+            let e = format!("{}", e);
+            assert!(
+                e.contains(err),
+                "unexpected error: {}, expecting {:?}",
+                e,
+                err
+            );
+        }
+        Ok(_) => panic!("expected error: {}; grm:\n{}", err, lark),
+    }
+}
+
+#[test]
+fn test_lark_file_imports() {
+    let modules = [
+        (
+            "lib.json",
+            r#"
+                value: object | array | NUMBER | STRING
+                object: "{" [pair ("," pair)*] "}"
+                pair: STRING ":" value
+                array: "[" [value ("," value)*] "]"
+                STRING: /"[a-z]*"/
+                NUMBER: DIGIT+
+                DIGIT: /[0-9]/
+            "#,
+        ),
+        (
+            "lib.dates",
+            r#"
+                %import lib.json.NUMBER
+                DATE: YEAR "-" MONTH
+                YEAR: /[0-9]{4}/
+                MONTH: /0[1-9]|1[0-2]/
+                year: NUMBER
+            "#,
+        ),
+        ("lib.a", "%import lib.b.x\ny: x"),
+        ("lib.b", "%import lib.a.y\nx: y"),
+    ];
+
+    lark_import_test(
+        &modules,
+        r#"
+            %import lib.json (value, NUMBER)
+            start: value | "n=" NUMBER
+        "#,
+        &["1", "[1,\"a\"]", "{\"a\":[{}]}", "n=12"],
+        &["[1,]", "{1:2}", "n=a"],
+    );
+
+    // aliases, and transitive imports; DIGIT and YEAR don't clash with local names
+    lark_import_test(
+        &modules,
+        r#"
+            %import lib.dates.DATE -> D
+            %import lib.dates.year
+            %import lib.json.value -> json
+            start: D " " json " " year | DIGIT
+            DIGIT: "x"
+        "#,
+        &["2024-12 [] 7", "x"],
+        &["2024-13 [] 7", "2024-12 [] x", "1"],
+    );
+
+    lark_import_err_test(
+        &modules,
+        "%import lib.json.foo\nstart: foo",
+        "\"foo\" not found in module \"lib.json\"",
+    );
+    lark_import_err_test(
+        &modules,
+        "%import lib.csv.ROW\nstart: ROW",
+        "failed to import module \"lib.csv\"",
+    );
+    lark_import_err_test(
+        &modules,
+        "%import lib.a.y\nstart: y",
+        "circular import of module \"lib.a\"",
+    );
+    lark_import_err_test(
+        &modules,
+        "%import lib.json.value\nstart: value\nvalue: \"x\"",
+        "duplicate rule: \"value\"",
+    );
+    lark_err_test(
+        "%import lib.json.value\nstart: value",
+        "module \"lib.json\" not found",
+    );
+}
+
 fn gen_words(seed: u32, num_words: usize) -> String {
     let letters = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789,.";
     let mut rnd = XorShift::new(seed + 1);
@@ -618,63 +1059,1860 @@ fn test_lexer_amb() {
 }
 
 #[test]
-fn test_edits() {
-    let grm = r#"
-start: ( step "\n" )* step ( "\n" final_comments )?
-step: plan "\n" a_file
-plan[lazy]: /((.|\n)*\n)?```/
-final_comments: /[^`]*/
+fn test_lexer_priority() {
+    // without priorities, "if" can be lexed both as IF and NAME
+    lark_str_test_many(
+        r#"start: (IF " " NAME | NAME "=" NAME) ";"
+           IF: "if"
+           NAME: /[a-z]+/
+        "#,
+        &["if x;", "if=x;", "iff=x;"],
+        &["if;"],
+    );
 
-replace: "=======\n" repl_inner " REPLACE\n```"
-repl_inner[lazy]: /(.|\n)*\n>>>>>>>/
-SEARCH: "\n<<<<<<< SEARCH\n"
+    lark_str_test_many(
+        r#"start: (IF " " NAME | NAME "=" NAME) ";"
+           IF.2: "if"
+           NAME: /[a-z]+/
+        "#,
+        &["if x;", "iff=x;", "x=if;"],
+        &["if=x;"],
+    );
 
-// "generated"
+    lark_str_test_many(
+        r#"start: (IF " " NAME | NAME "=" NAME) ";"
+           IF: "if"
+           NAME.1: /[a-z]+/
+        "#,
+        &["if=x;", "iff=x;"],
+        &["if x;"],
+    );
 
-file_0: "gbnf_to_lark.py" SEARCH FILE_0 replace
-a_file: file_0
+    // negative priorities
+    lark_str_test_many(
+        r#"start: (IF " " NAME | NAME "=" NAME) ";"
+           IF: "if"
+           NAME.-1: /[a-z]+/
+        "#,
+        &["if x;", "iff=x;"],
+        &["if=x;"],
+    );
 
-FILE_0: %regex {
-  "substring_chunks": [
-    "foo\n",
-    "bar\n",
-    "baz\n",
-    "line\n",
-    "line\n"
-  ]
+    // rule priorities apply to rules compiled as lexemes
+    lark_str_test_many(
+        r#"start: (IF " " name | name "=" name) ";"
+           IF: "if"
+           name.1[max_tokens=10]: /[a-z]+/
+        "#,
+        &["if=x;", "iff=x;"],
+        &["if x;"],
+    );
 }
-"#;
-
-    fn repl_block(filename: &str, src: &str, dst: &str) -> String {
-        format!(
-            "```\n{}\n<<<<<<< SEARCH\n{}\n=======\n{}\n>>>>>>> REPLACE\n```",
-            filename,
-            src.trim_end_matches("\n"),
-            dst.trim_end_matches("\n")
-        )
-    }
-
-    let filename = "gbnf_to_lark.py";
-    let repl = repl_block(filename, "foo\nbar", "qux");
 
+#[test]
+fn test_json_pattern_properties() {
     lark_str_test_many(
-        grm,
+        r#"start: %json {
+            "type": "object",
+            "properties": { "a": { "type": "string" } },
+            "patternProperties": {
+                "^[0-9]+$": { "type": "integer" },
+                "^a": { "type": "string", "maxLength": 2 }
+            },
+            "additionalProperties": { "type": "boolean" }
+        }"#,
         &[
-            &repl,
-            &format!("{}\n", repl),
-            &format!("{}\n\n", repl),
-            &format!("Some text\n{}", repl),
-            &format!("Some text\nMore\n{}", repl),
-            &format!("Some text\nMore\n{}\n", repl),
-            &format!("Some text\nMore\n{}\nAnd then some", repl),
-            &format!("Some text\nMore\n{}\nAnd then some\n", repl),
+            r#"{"a":"xy"}"#,
+            r#"{"12":1,"ab":"q","zz":true}"#,
+            r#"{"zz":false}"#,
         ],
         &[
-            "FINAL_REJECT:Some text\nSome more\n",
-            "FINAL_REJECT:Some text\nSome more",
-            "Foo\n```\nbar",
-            "FINAL_REJECT:Foo\n```\ngbnf",
-            &repl_block(filename, "fooz\nbar", "quux"),
+            r#"{"a":"xyz"}"#,
+            r#"{"12":true}"#,
+            r#"{"zz":1}"#,
+            r#"{"ab":"abc"}"#,
         ],
     );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "patternProperties": { "^a": { "type": "integer" } },
+            "required": ["abc"]
+        }"#,
+        &[r#"{"abc":7}"#, r#"{"abc":7,"ax":1}"#],
+        &[r#"{"abc":"x"}"#, r#"{}"#],
+    );
+}
+
+#[test]
+fn test_json_property_names() {
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "propertyNames": { "pattern": "^[a-z]+$", "maxLength": 3 }
+        }"#,
+        &[r#"{"abc":1}"#, r#"{}"#, r#"{"a":1,"b":null}"#],
+        &[r#"{"abcd":1}"#, r#"{"A":1}"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "properties": { "Foo": {}, "bar": {} },
+            "propertyNames": { "pattern": "^[a-z]+$" }
+        }"#,
+        &[r#"{"bar":1}"#, r#"{"bar":1,"baz":2}"#],
+        &[r#"{"Foo":1}"#],
+    );
+}
+
+#[test]
+fn test_json_min_max_properties() {
+    lark_str_test_many(
+        r#"start: %json { "type": "object", "minProperties": 2, "maxProperties": 3 }"#,
+        &[r#"{"a":1,"b":2}"#, r#"{"a":1,"b":2,"c":3}"#],
+        &[r#"{}"#, r#"{"a":1}"#, r#"{"a":1,"b":2,"c":3,"d":4}"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer" },
+                "b": { "type": "integer" },
+                "c": { "type": "integer" }
+            },
+            "required": ["a"],
+            "minProperties": 2,
+            "maxProperties": 2,
+            "additionalProperties": false
+        }"#,
+        &[r#"{"a":1,"b":2}"#, r#"{"a":1,"c":3}"#],
+        &[r#"{"a":1}"#, r#"{"b":1,"c":2}"#, r#"{"a":1,"b":2,"c":3}"#],
+    );
+
+    lark_err_test(
+        r#"start: %json { "type": "object", "required": ["a", "b"], "maxProperties": 1 }"#,
+        "Unsatisfiable schema",
+    );
+}
+
+#[test]
+fn test_json_unique_items() {
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "items": { "enum": ["a", "b", "c"] },
+            "uniqueItems": true
+        }"#,
+        &[r#"[]"#, r#"["a"]"#, r#"["c","a"]"#, r#"["a","b","c"]"#],
+        &[r#"["a","a"]"#, r#"["a","b","a"]"#, r#"["d"]"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "items": { "type": "boolean" },
+            "uniqueItems": true,
+            "minItems": 1
+        }"#,
+        &[r#"[true,false]"#, r#"[false]"#],
+        &[r#"[]"#, r#"[true,true]"#],
+    );
+
+    // best-effort only
+    lark_str_test_many(
+        r#"start: %json { "type": "array", "items": { "type": "string" }, "uniqueItems": true }"#,
+        &[r#"[]"#, r#"["a","b"]"#, r#"["a","a"]"#],
+        &[r#"[1]"#],
+    );
+
+    lark_err_test(
+        r#"start: %json {
+            "type": "array",
+            "items": { "enum": [1, 2] },
+            "uniqueItems": true,
+            "minItems": 3
+        }"#,
+        "Unsatisfiable schema",
+    );
+}
+
+#[test]
+fn test_json_contains() {
+    lark_str_test_many(
+        r#"start: %json { "type": "array", "contains": { "type": "integer" } }"#,
+        &[r#"[1]"#, r#"["a",1]"#, r#"[1,"a",2]"#],
+        &[r#"[]"#, r#"["a"]"#, r#"["a","b"]"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": { "role": { "type": "string" } },
+                "required": ["role"]
+            },
+            "contains": { "properties": { "role": { "const": "system" } } }
+        }"#,
+        &[
+            r#"[{"role":"system"}]"#,
+            r#"[{"role":"user"},{"role":"system"}]"#,
+        ],
+        &[r#"[]"#, r#"[{"role":"user"}]"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "contains": { "type": "integer" },
+            "minContains": 2,
+            "maxContains": 3
+        }"#,
+        &[r#"[1,2]"#, r#"["a",1,2,3]"#, r#"[1,"x",2,null]"#],
+        &[r#"[1]"#, r#"[1,2,3,4]"#, r#"["a",1,"b"]"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "prefixItems": [{ "type": "string" }],
+            "items": { "type": "integer" },
+            "contains": { "type": "string" },
+            "maxItems": 3
+        }"#,
+        &[r#"["a"]"#, r#"["a",1,2]"#],
+        &[r#"[]"#, r#"[1]"#, r#"["a",1,2,3]"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "contains": { "type": "integer" },
+            "minContains": 2,
+            "maxContains": 3,
+            "minItems": 4,
+            "maxItems": 6
+        }"#,
+        &[
+            r#"[1,2,"a","b"]"#,
+            r#"["a","b","c",1,2,3]"#,
+            r#"[1,"a","b","c","d",2]"#,
+        ],
+        &[
+            r#"[1,2,"a"]"#,
+            r#"[1,2,"a","b","c","d","e"]"#,
+            r#"[1,2,3,4]"#,
+            r#"["a","b","c","d",1]"#,
+        ],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "contains": { "type": "string" },
+            "minItems": 3
+        }"#,
+        &[r#"[1,"a",2]"#, r#"["a","b","c","d"]"#],
+        &[r#"[1,"a"]"#, r#"[1,2,3]"#],
+    );
+
+    // the grammar doesn't grow linearly with maxItems
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "contains": { "type": "integer" },
+            "minContains": 1,
+            "maxContains": 2,
+            "maxItems": 100000
+        }"#,
+        &[r#"[1]"#, r#"["a",1,"b",2]"#],
+        &[r#"[]"#, r#"[1,2,3]"#, r#"["a"]"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "array",
+            "contains": { "type": "string" },
+            "minContains": 2,
+            "minItems": 3,
+            "maxItems": 100000
+        }"#,
+        &[r#"["a","b",1]"#, r#"[1,"a",2,"b",3]"#],
+        &[r#"["a","b"]"#, r#"["a",1,2]"#],
+    );
+
+    lark_err_test(
+        r#"start: %json {
+            "type": "array",
+            "items": { "type": "integer" },
+            "contains": { "type": "string" }
+        }"#,
+        "Unsatisfiable schema",
+    );
+
+    lark_err_test(
+        r#"start: %json {
+            "type": "array",
+            "contains": { "type": "object" },
+            "maxContains": 1
+        }"#,
+        "maxContains is only supported",
+    );
+
+    lark_err_test(
+        r#"start: %json {
+            "type": "array",
+            "contains": { "type": "integer" },
+            "minContains": 3,
+            "maxContains": 1
+        }"#,
+        "minContains (3) is greater than maxContains (1)",
+    );
+}
+
+#[test]
+fn test_json_more_formats() {
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "properties": {
+                "uri": { "type": "string", "format": "uri" },
+                "ref": { "type": "string", "format": "uri-reference" },
+                "iri": { "type": "string", "format": "iri" }
+            }
+        }"#,
+        &[
+            r#"{"uri":"https://example.com/a?b=c#d"}"#,
+            r#"{"uri":"urn:isbn:0451450523"}"#,
+            r#"{"uri":"http://[::1]:80/%20"}"#,
+            r#"{"ref":"../a/b.json#/defs/x"}"#,
+            r##"{"ref":"#frag"}"##,
+            r#"{"ref":"mailto:a@b.c"}"#,
+            r#"{"iri":"https://zażółć.pl/gęś"}"#,
+        ],
+        &[
+            r#"{"uri":"example.com"}"#,
+            r#"{"uri":"http://a b"}"#,
+            r#"{"uri":"http://a%2"}"#,
+            r#"{"uri":"https://zażółć.pl"}"#,
+            r#"{"ref":"a:b c"}"#,
+            r#"{"ref":"a\"b"}"#,
+        ],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "properties": {
+                "ptr": { "type": "string", "format": "json-pointer" },
+                "rel": { "type": "string", "format": "relative-json-pointer" },
+                "b64": { "type": "string", "format": "byte" },
+                "email": { "type": "string", "format": "idn-email" },
+                "rx": { "type": "string", "format": "regex" }
+            }
+        }"#,
+        &[
+            r#"{"ptr":""}"#,
+            r#"{"ptr":"/a~1b/0/~0"}"#,
+            r#"{"rel":"0"}"#,
+            r#"{"rel":"2/foo/0"}"#,
+            r#"{"rel":"1#"}"#,
+            r#"{"b64":""}"#,
+            r#"{"b64":"aGVsbG8="}"#,
+            r#"{"b64":"aGVsbG8hIQ=="}"#,
+            r#"{"email":"jürgen@bücher.de"}"#,
+            r#"{"rx":"[a-z]+("}"#,
+        ],
+        &[
+            r#"{"ptr":"a/b"}"#,
+            r#"{"ptr":"/a~2"}"#,
+            r#"{"rel":"01/a"}"#,
+            r#"{"rel":"/a"}"#,
+            r#"{"b64":"aGVsbG8"}"#,
+            r#"{"b64":"aGVs=G8="}"#,
+            r#"{"email":"jürgen@-bücher.de"}"#,
+        ],
+    );
+
+    // OpenAPI numeric formats
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "properties": {
+                "i32": { "type": "integer", "format": "int32" },
+                "i64": { "type": "integer", "format": "int64" },
+                "u64": { "type": "integer", "format": "int64", "minimum": 0 },
+                "f": { "type": "number", "format": "float" },
+                "d": { "type": "number", "format": "double" }
+            }
+        }"#,
+        &[
+            r#"{"i32":-2147483648}"#,
+            r#"{"i32":2147483647}"#,
+            r#"{"i64":-9223372036854775808}"#,
+            r#"{"i64":9223372036854775807}"#,
+            r#"{"u64":9223372036854775807}"#,
+            r#"{"f":-3.4e38}"#,
+            r#"{"f":1.5e-10}"#,
+            r#"{"f":0.125}"#,
+            r#"{"f":12e3}"#,
+            r#"{"f":0.5e1}"#,
+            r#"{"f":-0}"#,
+            r#"{"d":1.7e308}"#,
+            r#"{"d":-2.5E+100}"#,
+            r#"{"d":12e3}"#,
+            r#"{"d":0.5e1}"#,
+            r#"{"d":-0}"#,
+        ],
+        &[
+            r#"{"i32":2147483648}"#,
+            r#"{"i32":-2147483649}"#,
+            r#"{"i32":1.5}"#,
+            r#"{"i64":-9223372036854775809}"#,
+            r#"{"i64":9223372036854775808}"#,
+            r#"{"u64":-1}"#,
+            r#"{"f":"1.5"}"#,
+            r#"{"d":true}"#,
+        ],
+    );
+
+    // i64::MIN as an explicit bound
+    lark_str_test_many(
+        r#"start: %regex {"type":"integer","minimum":-9223372036854775808}"#,
+        &["-9223372036854775808", "0", "9223372036854775808"],
+        &["-9223372036854775809", "-10000000000000000000"],
+    );
+}
+
+#[test]
+fn test_json_custom_formats() {
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": {
+                "custom_formats": {
+                    "sku": "[A-Z]{3}-[0-9]{4}",
+                    "phone-e164": "^\\+[1-9][0-9]{1,14}$",
+                    "email": "[a-z]+@example\\.com"
+                }
+            },
+            "type": "object",
+            "properties": {
+                "sku": { "type": "string", "format": "sku" },
+                "phone": { "type": "string", "format": "phone-e164" },
+                "email": { "type": "string", "format": "email" },
+                "date": { "type": "string", "format": "date" }
+            }
+        }"#,
+        &[
+            r#"{"sku":"ABC-1234"}"#,
+            r#"{"phone":"+48123456789"}"#,
+            r#"{"email":"joe@example.com"}"#,
+            r#"{"date":"2024-01-31"}"#,
+        ],
+        &[
+            r#"{"sku":"ABC-12345"}"#,
+            r#"{"sku":"xABC-1234"}"#,
+            r#"{"phone":"48123456789"}"#,
+            r#"{"email":"joe@example.org"}"#,
+            r#"{"date":"2024-01-32"}"#,
+        ],
+    );
+
+    lark_err_test(
+        r#"start: %json {
+            "x-guidance": { "custom_formats": { "sku": "[A-Z]{3}" } },
+            "type": "string",
+            "format": "iso-currency"
+        }"#,
+        "Unknown format: iso-currency",
+    );
+}
+
+#[test]
+fn test_json_dynamic_refs() {
+    // Cases below are ported from JSON-Schema-Test-Suite,
+    // tests/draft2020-12/dynamicRef.json and tests/draft2019-09/recursiveRef.json;
+    // each is labelled with the suite's description.
+
+    // dynamicRef.json: "A $dynamicRef to a $dynamicAnchor in the same schema resource
+    // behaves like a normal $ref to an $anchor"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/dynamicRef-dynamicAnchor-same-schema/root",
+            "type": "array",
+            "items": { "$dynamicRef": "#items" },
+            "$defs": { "foo": { "$dynamicAnchor": "items", "type": "string" } }
+        }"##,
+        &[r#"["foo","bar"]"#],
+        &[r#"["foo",42]"#],
+    );
+
+    // dynamicRef.json: "A $dynamicRef to an $anchor in the same schema resource
+    // behaves like a normal $ref to an $anchor"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/dynamicRef-anchor-same-schema/root",
+            "type": "array",
+            "items": { "$dynamicRef": "#items" },
+            "$defs": { "foo": { "$anchor": "items", "type": "string" } }
+        }"##,
+        &[r#"["foo","bar"]"#],
+        &[r#"["foo",42]"#],
+    );
+
+    // dynamicRef.json: "A $dynamicRef resolves to the first $dynamicAnchor still in scope
+    // that is encountered when the schema is evaluated"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/typical-dynamic-resolution/root",
+            "$ref": "list",
+            "$defs": {
+                "foo": { "$dynamicAnchor": "items", "type": "string" },
+                "list": {
+                    "$id": "list",
+                    "type": "array",
+                    "items": { "$dynamicRef": "#items" },
+                    "$defs": { "items": { "$dynamicAnchor": "items" } }
+                }
+            }
+        }"##,
+        &[r#"["foo","bar"]"#],
+        &[r#"["foo",42]"#],
+    );
+
+    // dynamicRef.json: "A $dynamicRef without anchor in fragment behaves identical to $ref"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/dynamicRef-without-anchor/root",
+            "$ref": "list",
+            "$defs": {
+                "foo": { "$dynamicAnchor": "items", "type": "string" },
+                "list": {
+                    "$id": "list",
+                    "type": "array",
+                    "items": { "$dynamicRef": "#/$defs/items" },
+                    "$defs": { "items": { "$dynamicAnchor": "items", "type": "number" } }
+                }
+            }
+        }"##,
+        &[r#"[24,42]"#],
+        &[r#"["foo","bar"]"#],
+    );
+
+    // dynamicRef.json: "A $dynamicRef with intermediate scopes that don't include
+    // a matching $dynamicAnchor does not affect dynamic scope resolution"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/dynamic-resolution-with-intermediate-scopes/root",
+            "$ref": "intermediate-scope",
+            "$defs": {
+                "foo": { "$dynamicAnchor": "items", "type": "string" },
+                "intermediate-scope": { "$id": "intermediate-scope", "$ref": "list" },
+                "list": {
+                    "$id": "list",
+                    "type": "array",
+                    "items": { "$dynamicRef": "#items" },
+                    "$defs": { "items": { "$dynamicAnchor": "items" } }
+                }
+            }
+        }"##,
+        &[r#"["foo","bar"]"#],
+        &[r#"["foo",42]"#],
+    );
+
+    // dynamicRef.json: "An $anchor with the same name as a $dynamicAnchor
+    // is not used for dynamic scope resolution"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/dynamic-resolution-ignores-anchors/root",
+            "$ref": "list",
+            "$defs": {
+                "foo": { "$anchor": "items", "type": "string" },
+                "list": {
+                    "$id": "list",
+                    "type": "array",
+                    "items": { "$dynamicRef": "#items" },
+                    "$defs": { "items": { "$dynamicAnchor": "items" } }
+                }
+            }
+        }"##,
+        &[r#"["foo",42]"#],
+        &[],
+    );
+
+    // dynamicRef.json: "A $dynamicRef without a matching $dynamicAnchor
+    // in the same schema resource behaves like a normal $ref to $anchor"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/dynamic-resolution-without-bookend/root",
+            "$ref": "list",
+            "$defs": {
+                "foo": { "$dynamicAnchor": "items", "type": "string" },
+                "list": {
+                    "$id": "list",
+                    "type": "array",
+                    "items": { "$dynamicRef": "#items" },
+                    "$defs": { "items": { "$anchor": "items" } }
+                }
+            }
+        }"##,
+        &[r#"["foo",42]"#],
+        &[],
+    );
+
+    // dynamicRef.json: "A $dynamicRef with a non-matching $dynamicAnchor
+    // in the same schema resource behaves like a normal $ref to $anchor"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/unmatched-dynamic-anchor/root",
+            "$ref": "list",
+            "$defs": {
+                "foo": { "$dynamicAnchor": "items", "type": "string" },
+                "list": {
+                    "$id": "list",
+                    "type": "array",
+                    "items": { "$dynamicRef": "#items" },
+                    "$defs": { "items": { "$anchor": "items", "$dynamicAnchor": "foo" } }
+                }
+            }
+        }"##,
+        &[r#"["foo",42]"#],
+        &[],
+    );
+
+    // dynamicRef.json: "A $dynamicRef that initially resolves to a schema with a matching
+    // $dynamicAnchor resolves to the first $dynamicAnchor in the dynamic scope"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "https://test.json-schema.org/relative-dynamic-reference/root",
+            "$dynamicAnchor": "meta",
+            "type": "object",
+            "properties": { "foo": { "const": "pass" } },
+            "$ref": "extended",
+            "$defs": {
+                "extended": {
+                    "$id": "extended",
+                    "$dynamicAnchor": "meta",
+                    "type": "object",
+                    "properties": { "bar": { "$ref": "bar" } }
+                },
+                "bar": {
+                    "$id": "bar",
+                    "type": "object",
+                    "properties": { "baz": { "$dynamicRef": "extended#meta" } }
+                }
+            }
+        }"##,
+        &[r#"{"foo":"pass","bar":{"baz":{"foo":"pass"}}}"#],
+        &[r#"{"foo":"pass","bar":{"baz":{"foo":"fail"}}}"#],
+    );
+
+    // recursiveRef.json: "$recursiveRef without $recursiveAnchor works like $ref"
+    lark_str_test_many(
+        r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2019-09/schema",
+            "properties": { "foo": { "$recursiveRef": "#" } },
+            "additionalProperties": false
+        }"##,
+        &[r#"{"foo":false}"#, r#"{"foo":{"foo":false}}"#],
+        &[r#"{"bar":false}"#, r#"{"foo":{"bar":false}}"#],
+    );
+
+    // recursiveRef.json: "$recursiveRef without using nesting"
+    let recursive_ref = r##"start: %json {
+            "$schema": "https://json-schema.org/draft/2019-09/schema",
+            "$id": "http://localhost:4242/draft2019-09/recursiveRef2/schema.json",
+            "$defs": {
+                "myobject": {
+                    "$id": "myobject.json",
+                    "$recursiveAnchor": false,
+                    "anyOf": [
+                        { "type": "string" },
+                        { "type": "object", "additionalProperties": { "$recursiveRef": "#" } }
+                    ]
+                }
+            },
+            "anyOf": [{ "type": "integer" }, { "$ref": "#/$defs/myobject" }]
+        }"##;
+    lark_str_test_many(
+        recursive_ref,
+        &["1", r#"{"foo":"hi"}"#, r#"{"foo":{"bar":"hi"}}"#],
+        &[r#"{"foo":1}"#, r#"{"foo":{"bar":1}}"#],
+    );
+
+    // recursiveRef.json: "$recursiveRef with nesting"
+    let recursive_ref = recursive_ref
+        .replace("recursiveRef2", "recursiveRef3")
+        .replace(
+            r#""$recursiveAnchor": false"#,
+            r#""$recursiveAnchor": true"#,
+        )
+        .replacen(
+            "\"$defs\"",
+            "\"$recursiveAnchor\": true,\n            \"$defs\"",
+            1,
+        );
+    lark_str_test_many(
+        &recursive_ref,
+        &[
+            "1",
+            r#"{"foo":"hi"}"#,
+            r#"{"foo":1}"#,
+            r#"{"foo":{"bar":"hi"}}"#,
+            r#"{"foo":{"bar":1}}"#,
+        ],
+        &[r#"{"foo":{"bar":true}}"#],
+    );
+}
+
+#[test]
+fn test_json_lenient() {
+    let schema = r#"{
+        "type": "object",
+        "properties": {
+            "a": { "type": "array", "items": { "type": "integer" }, "unevaluatedItems": false },
+            "b/c": { "type": "object", "unevaluatedProperties": false }
+        },
+        "unevaluatedProperties": false,
+        "required": ["a"]
+    }"#;
+
+    lark_err_test(
+        &format!("start: %json {}", schema),
+        "Unimplemented keys: [\"unevaluatedProperties\"]",
+    );
+
+    let lark = format!(
+        "start: %json {}",
+        schema.replacen('{', r#"{ "x-guidance": { "lenient": true },"#, 1)
+    );
+    lark_str_test_many(
+        &lark,
+        &[r#"{"a":[1]}"#, r#"{"a":[],"b/c":{"x":1},"d":1}"#],
+        &[r#"{"a":["x"]}"#, r#"{"b/c":{}}"#],
+    );
+
+    let parser = make_parser(&lark, false).unwrap();
+    let mut warnings = parser
+        .warnings()
+        .iter()
+        .map(|w| {
+            (
+                w.json_pointer.clone().unwrap(),
+                w.keyword.clone().unwrap(),
+                w.message.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    warnings.sort();
+    assert_eq!(
+        warnings,
+        vec![
+            (
+                "".to_string(),
+                "unevaluatedProperties".to_string(),
+                "unsupported keyword \"unevaluatedProperties\" ignored".to_string()
+            ),
+            (
+                "/properties/a".to_string(),
+                "unevaluatedItems".to_string(),
+                "unsupported keyword \"unevaluatedItems\" ignored".to_string()
+            ),
+            (
+                "/properties/b~1c".to_string(),
+                "unevaluatedProperties".to_string(),
+                "unsupported keyword \"unevaluatedProperties\" ignored".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_json_indent() {
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": { "indent": 2, "key_separator": ": " },
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer" },
+                "b": { "type": "array", "items": { "type": "string" } },
+                "c": { "type": "object" }
+            },
+            "required": ["a"]
+        }"#,
+        &[
+            "{\n  \"a\": 1\n}",
+            "{\n  \"a\": 1,\n  \"b\": []\n}",
+            "{\n  \"a\": 1,\n  \"b\": [\n    \"x\",\n    \"y\"\n  ]\n}",
+            "{\n  \"a\": 1,\n  \"c\": {}\n}",
+            "{\n  \"a\": 1,\n  \"c\": {\n    \"x\": [\n      {\n        \"y\": null\n      }\n    ]\n  }\n}",
+        ],
+        &[
+            "{\"a\": 1}",
+            "{\n\"a\": 1\n}",
+            "{\n  \"a\":1\n}",
+            "{\n  \"a\": 1,\n  \"b\": [ ]\n}",
+            "{\n  \"a\": 1,\n  \"b\": [\n  \"x\"\n  ]\n}",
+            "{\n  \"a\": 1,\n  \"c\": {\n  }\n}",
+        ],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": { "indent": 4 },
+            "type": "array",
+            "items": { "enum": ["a", "b"] },
+            "uniqueItems": true
+        }"#,
+        &["[]", "[\n    \"a\"\n]", "[\n    \"b\",\n    \"a\"\n]"],
+        &[
+            "[ ]",
+            "[\n    \"a\",\n    \"a\"\n]",
+            "[\n    \"a\",\"b\"\n]",
+        ],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": { "indent": 1 },
+            "type": "array",
+            "contains": { "type": "integer" },
+            "minItems": 1
+        }"#,
+        &["[\n 1\n]", "[\n \"x\",\n 2,\n []\n]"],
+        &["[]", "[\n \"x\"\n]", "[1]"],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": { "indent": 2 },
+            "type": "object",
+            "minProperties": 1,
+            "maxProperties": 2
+        }"#,
+        &[
+            "{\n  \"x\": 1\n}",
+            "{\n  \"x\": 1,\n  \"y\": [\n    true\n  ]\n}",
+        ],
+        &[
+            "{}",
+            "{\n  \"x\":1\n}",
+            "{\n  \"x\": 1,\n  \"y\": 2,\n  \"z\": 3\n}",
+        ],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": { "indent": 2, "key_separator": ":" },
+            "type": "object"
+        }"#,
+        &["{\n  \"x\":1\n}"],
+        &["{\n  \"x\": 1\n}"],
+    );
+
+    // levels past 16 are not indented
+    let lark = r#"start: %json { "x-guidance": { "indent": 1 } }"#;
+    let nested = |indented: usize| {
+        let mut r = "1".to_string();
+        for depth in (1..=18).rev() {
+            r = if depth <= indented {
+                let indent = |depth: usize| " ".repeat(depth);
+                format!("[\n{}{}\n{}]", indent(depth), r, indent(depth - 1))
+            } else {
+                format!("[{}]", r)
+            };
+        }
+        r
+    };
+    lark_str_test_many(lark, &[&nested(16)], &[&nested(18), &nested(15)]);
+    let parser = make_parser(lark, false).unwrap();
+    let warnings = parser
+        .warnings()
+        .iter()
+        .map(|w| w.message.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        warnings,
+        vec!["arrays and objects nested deeper than 16 levels are not indented"]
+    );
+}
+
+#[test]
+fn test_json_property_order() {
+    let schema = |order: &str| {
+        format!(
+            r#"start: %json {{
+                "x-guidance": {{ "property_order": "{}" }},
+                "type": "object",
+                "properties": {{
+                    "a": {{ "type": "integer" }},
+                    "b": {{ "type": "integer" }},
+                    "c": {{ "type": "integer" }}
+                }},
+                "required": ["c", "b"],
+                "additionalProperties": false
+            }}"#,
+            order
+        )
+    };
+
+    lark_str_test_many(
+        &schema("declared"),
+        &[r#"{"b":1,"c":2}"#, r#"{"a":0,"b":1,"c":2}"#],
+        &[r#"{"c":2,"b":1}"#, r#"{"b":1,"c":2,"a":0}"#],
+    );
+
+    lark_str_test_many(
+        &schema("required_first"),
+        &[r#"{"b":1,"c":2}"#, r#"{"b":1,"c":2,"a":0}"#],
+        &[r#"{"a":0,"b":1,"c":2}"#, r#"{"c":2,"b":1}"#],
+    );
+
+    lark_str_test_many(
+        &schema("any_order"),
+        &[
+            r#"{"b":1,"c":2}"#,
+            r#"{"c":2,"b":1}"#,
+            r#"{"c":2,"a":0,"b":1}"#,
+            r#"{"a":0,"b":1,"c":2}"#,
+        ],
+        &[
+            r#"{"c":2}"#,
+            r#"{"c":2,"b":1,"c":3}"#,
+            r#"{"a":0,"c":2,"a":1,"b":1}"#,
+            r#"{"c":2,"b":1,"d":0}"#,
+        ],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": { "property_order": "any_order" },
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer" },
+                "b": { "type": "integer" }
+            },
+            "required": ["a"]
+        }"#,
+        &[
+            r#"{"a":1}"#,
+            r#"{"b":2,"a":1}"#,
+            r#"{"b":2,"x":null,"a":1,"y":[]}"#,
+        ],
+        &[r#"{"b":2,"a":1,"b":3}"#, r#"{"x":1}"#],
+    );
+
+    // additional properties anywhere, with bounds on their number
+    lark_str_test_many(
+        r#"start: %json {
+            "x-guidance": { "property_order": "any_order" },
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer" },
+                "b": { "type": "string" }
+            },
+            "required": ["a"],
+            "additionalProperties": { "type": "boolean" },
+            "maxProperties": 3
+        }"#,
+        &[
+            r#"{"a":1}"#,
+            r#"{"x":true,"a":1}"#,
+            r#"{"b":"","x":true,"a":1}"#,
+            r#"{"x":true,"y":false,"a":1}"#,
+        ],
+        &[
+            r#"{"x":true}"#,
+            r#"{"x":true,"y":false,"z":true,"a":1}"#,
+            r#"{"a":1,"a":2}"#,
+            r#"{"b":"","b":"","a":1}"#,
+        ],
+    );
+
+    lark_err_test(
+        r#"start: %json {
+            "x-guidance": { "property_order": "any_order" },
+            "type": "object",
+            "properties": { "a": {}, "b": {} },
+            "required": ["a", "b"],
+            "maxProperties": 1
+        }"#,
+        "object cannot have between 0 and Some(1) properties",
+    );
+
+    // too many properties to track: declared order, with a warning
+    let props = (0..9)
+        .map(|i| format!(r#""p{}": {{ "type": "integer" }}"#, i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let lark = format!(
+        r#"start: %json {{
+            "x-guidance": {{ "property_order": "any_order" }},
+            "type": "object",
+            "properties": {{ {} }},
+            "additionalProperties": false
+        }}"#,
+        props
+    );
+    lark_str_test_many(
+        &lark,
+        &[r#"{"p0":0,"p8":8}"#, r#"{"p3":3}"#],
+        &[r#"{"p8":8,"p0":0}"#, r#"{"p3":3,"p3":3}"#],
+    );
+    let parser = make_parser(&lark, false).unwrap();
+    let warnings = parser
+        .warnings()
+        .iter()
+        .map(|w| w.message.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        warnings,
+        vec![
+            "any_order is only supported for objects with up to 8 properties \
+             and 4096 states (used properties times property counts); using declared order"
+                .to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_json_conditional() {
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "properties": {
+                "country": { "enum": ["US", "CA"] },
+                "code": { "type": "string" }
+            },
+            "required": ["country", "code"],
+            "additionalProperties": false,
+            "if": { "properties": { "country": { "const": "US" } } },
+            "then": { "properties": { "code": { "pattern": "^[0-9]{5}$" } } },
+            "else": { "properties": { "code": { "pattern": "^[A-Z][0-9][A-Z]$" } } }
+        }"#,
+        &[
+            r#"{"country":"US","code":"12345"}"#,
+            r#"{"country":"CA","code":"K1A"}"#,
+        ],
+        &[
+            r#"{"country":"US","code":"K1A"}"#,
+            r#"{"country":"CA","code":"12345"}"#,
+        ],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "if": { "type": "integer", "minimum": 10 },
+            "else": { "type": "string" }
+        }"#,
+        &[r#"10"#, r#"123"#, r#""foo""#],
+        &["FINAL_REJECT:9", r#"true"#, r#"[]"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "if": { "type": "string", "maxLength": 2 },
+            "then": { "pattern": "^a" }
+        }"#,
+        &[r#""ab""#, r#""bcd""#, r#"1"#, r#"null"#],
+        &[r#"FINAL_REJECT:"bc""#, r#"FINAL_REJECT:"b""#],
+    );
+
+    lark_err_test(
+        r#"start: %json {
+            "if": { "type": "object", "additionalProperties": { "type": "string" } },
+            "then": { "required": ["a"] }
+        }"#,
+        "negation of the 'if' schema",
+    );
+}
+
+#[test]
+fn test_json_dependencies() {
+    lark_str_test_many(
+        r#"start: %json {
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer" },
+                "b": { "type": "integer" },
+                "c": { "type": "integer" }
+            },
+            "additionalProperties": false,
+            "dependentRequired": { "a": ["b"] },
+            "dependentSchemas": { "b": { "required": ["c"] } }
+        }"#,
+        &[
+            r#"{}"#,
+            r#"{"c":1}"#,
+            r#"{"b":1,"c":2}"#,
+            r#"{"a":1,"b":2,"c":3}"#,
+        ],
+        &[r#"{"a":1}"#, r#"{"a":1,"b":2}"#, r#"{"b":1}"#],
+    );
+
+    lark_str_test_many(
+        r#"start: %json {
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer" },
+                "b": { "type": "integer" }
+            },
+            "additionalProperties": false,
+            "dependencies": { "a": ["b"], "b": { "properties": { "b": { "minimum": 5 } } } }
+        }"#,
+        &[r#"{"b":5}"#, r#"{"a":1,"b":7}"#],
+        &[r#"{"a":1}"#, r#"{"b":4}"#, r#"{"a":1,"b":4}"#],
+    );
+}
+
+fn to_lark(lark: &str) -> String {
+    GrammarInit::Serialized(TopLevelGrammar::from_lark(lark.to_string()))
+        .to_lark(
+            None,
+            &mut Logger::new(0, 1),
+            get_parser_factory().limits().clone(),
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_to_lark() {
+    let lark = to_lark(
+        r#"start: %json {
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer", "minimum": 3, "maximum": 20 },
+                "b": { "type": "string", "pattern": "^x+$", "maxLength": 4 },
+                "c": { "enum": ["foo", "bar"] },
+                "d": { "type": "number", "multipleOf": 0.5 }
+            },
+            "required": ["a"],
+            "additionalProperties": false
+        }"#,
+    );
+    lark_str_test_many(
+        &lark,
+        &[
+            r#"{"a": 5}"#,
+            r#"{"a": 5, "b": "xx"}"#,
+            r#"{"a": 5, "c": "bar", "d": 1.5}"#,
+        ],
+        &[
+            r#"{"a": 2}"#,
+            r#"{"a": 5, "b": "xxxxx"}"#,
+            r#"{"a": 5, "b": "y"}"#,
+            r#"{"a": 5, "d": 1.25}"#,
+        ],
+    );
+
+    let lark = to_lark(
+        r#"
+            start: foo | bar
+            foo[capture]: /[a-z]+/ "!"
+            bar[lazy]: /[0-9]+/ "x"
+        "#,
+    );
+    lark_str_test_many(
+        &lark,
+        &["a!", "12x"],
+        &["FINAL_REJECT:12", "FINAL_REJECT:a", "1a"],
+    );
+
+    // stop= lexemes need backtracking, which lark_str_test() doesn't do
+    let lark = to_lark(
+        r#"
+            start: foo
+            foo[stop="<end>"]: /.*/
+        "#,
+    );
+    assert!(lark.contains(r#"stop="<end>""#), "{}", lark);
+    lark_ok(&lark);
+
+    // regex sources are only kept when rendering is requested
+    let cgrammar = GrammarInit::Serialized(TopLevelGrammar::from_lark("start: /a+/".to_string()))
+        .to_cgrammar(
+            None,
+            &mut Logger::new(0, 1),
+            get_parser_factory().limits().clone(),
+            vec![],
+        )
+        .unwrap();
+    assert!(cgrammar.to_lark().is_err());
+
+    let lark = to_lark(
+        r#"
+            start: A B
+            A.2: /[a-z]+/ & ~/(?s:.*)q(?s:.*)/
+            B: %regex {"substring_words": "foo bar baz"}
+            %ignore /[ \t]+/
+        "#,
+    );
+    lark_str_test_many(&lark, &["ab foo bar", "ab"], &["aqb foo"]);
+}
+
+#[test]
+fn test_edits() {
+    let grm = r#"
+start: ( step "\n" )* step ( "\n" final_comments )?
+step: plan "\n" a_file
+plan[lazy]: /((.|\n)*\n)?```/
+final_comments: /[^`]*/
+
+replace: "=======\n" repl_inner " REPLACE\n```"
+repl_inner[lazy]: /(.|\n)*\n>>>>>>>/
+SEARCH: "\n<<<<<<< SEARCH\n"
+
+// "generated"
+
+file_0: "gbnf_to_lark.py" SEARCH FILE_0 replace
+a_file: file_0
+
+FILE_0: %regex {
+  "substring_chunks": [
+    "foo\n",
+    "bar\n",
+    "baz\n",
+    "line\n",
+    "line\n"
+  ]
+}
+"#;
+
+    fn repl_block(filename: &str, src: &str, dst: &str) -> String {
+        format!(
+            "```\n{}\n<<<<<<< SEARCH\n{}\n=======\n{}\n>>>>>>> REPLACE\n```",
+            filename,
+            src.trim_end_matches("\n"),
+            dst.trim_end_matches("\n")
+        )
+    }
+
+    let filename = "gbnf_to_lark.py";
+    let repl = repl_block(filename, "foo\nbar", "qux");
+
+    lark_str_test_many(
+        grm,
+        &[
+            &repl,
+            &format!("{}\n", repl),
+            &format!("{}\n\n", repl),
+            &format!("Some text\n{}", repl),
+            &format!("Some text\nMore\n{}", repl),
+            &format!("Some text\nMore\n{}\n", repl),
+            &format!("Some text\nMore\n{}\nAnd then some", repl),
+            &format!("Some text\nMore\n{}\nAnd then some\n", repl),
+        ],
+        &[
+            "FINAL_REJECT:Some text\nSome more\n",
+            "FINAL_REJECT:Some text\nSome more",
+            "Foo\n```\nbar",
+            "FINAL_REJECT:Foo\n```\ngbnf",
+            &repl_block(filename, "fooz\nbar", "quux"),
+        ],
+    );
+}
+
+fn make_gbnf_parser(gbnf: &str) -> Result<TokenParser> {
+    // also checks GBNF is auto-detected
+    let grm = TopLevelGrammar::from_lark_or_json_schema(gbnf)?;
+    assert!(grm.grammars[0].gbnf_grammar.is_some());
+    let mut parser = get_parser_factory().create_parser(grm)?;
+    parser.start_without_prompt();
+    Ok(parser)
+}
+
+fn make_ebnf_parser(ebnf: &str) -> Result<TokenParser> {
+    let grm = TopLevelGrammar::from_ebnf(ebnf.to_string());
+    let mut parser = get_parser_factory().create_parser(grm)?;
+    parser.start_without_prompt();
+    Ok(parser)
+}
+
+fn src_grammar_test(
+    make_parser: fn(&str) -> Result<TokenParser>,
+    src: &str,
+    passing: &[&str],
+    failing: &[&str],
+) {
+    for (input, should_accept) in passing
+        .iter()
+        .map(|s| (s, true))
+        .chain(failing.iter().map(|s| (s, false)))
+    {
+        let mut p = make_parser(src).unwrap();
+        let mut accepted = true;
+        for tok in get_tok_env().tokenize(input) {
+            if !p.compute_mask().unwrap().is_allowed(tok) {
+                accepted = false;
+                break;
+            }
+            consume(&mut p, tok);
+        }
+        accepted = accepted && p.is_accepting();
+        assert_eq!(accepted, should_accept, "input: {:?}; grm:\n{}", input, src);
+    }
+}
+
+fn src_grammar_err_test(make_parser: fn(&str) -> Result<TokenParser>, src: &str, err: &str) {
+    match make_parser(src) {
+        Err(e) => {
+            let e = format!("{}", e);
+            assert!(
+                e.contains(err),
+                "unexpected error: {}, expecting {:?}",
+                e,
+                err
+            );
+        }
+        Ok(_) => panic!("expected error: {}; grm:\n{}", err, src),
+    }
+}
+
+fn gbnf_test(gbnf: &str, passing: &[&str], failing: &[&str]) {
+    src_grammar_test(make_gbnf_parser, gbnf, passing, failing);
+}
+
+fn gbnf_err_test(gbnf: &str, err: &str) {
+    src_grammar_err_test(make_gbnf_parser, gbnf, err);
+}
+
+fn ebnf_test(ebnf: &str, passing: &[&str], failing: &[&str]) {
+    src_grammar_test(make_ebnf_parser, ebnf, passing, failing);
+}
+
+fn ebnf_err_test(ebnf: &str, err: &str) {
+    src_grammar_err_test(make_ebnf_parser, ebnf, err);
+}
+
+#[test]
+fn test_gbnf() {
+    gbnf_test(
+        include_str!("../data/from-llama.cpp/json.gbnf"),
+        &[r#"{"a": [1, 2.5, "x\n", true, null], "b": {}}"#, "{}"],
+        &["[1]", r#"{"a" 1}"#],
+    );
+    gbnf_test(
+        include_str!("../data/from-llama.cpp/chess.gbnf"),
+        &["1. e4 e5\n2. Nf3 Nc6\n"],
+        &["1. x9"],
+    );
+
+    gbnf_test(
+        r#"
+            # comment
+            root ::= "a"{2,3} [^a-c\x41]? x
+            x ::= ( "q" | [0-9]+ "." )* # comment
+        "#,
+        &["aa", "aaaD1.22.q", "aaq"],
+        &["aaaa", "aab", "aaA"],
+    );
+    gbnf_test(
+        r#"root ::= ( "(" root ")" )* ."#,
+        &["(x)\n", "((y)x)z"],
+        &["(x"],
+    );
+
+    gbnf_err_test(
+        "root ::= foo\nfoo ::= \"a\" bar",
+        "at 2(13): rule \"bar\" not defined",
+    );
+    gbnf_err_test("root ::= \"a\" |\n  \"b\" )", "at 2(7): unexpected ')'");
+    gbnf_err_test("foo ::= \"a\"", "no root rule found");
+    gbnf_err_test("root ::= \"a\"{3,2}", "invalid repetition {3,2}");
+    gbnf_err_test("root ::= \"\\q\"", "invalid escape sequence \\q");
+    gbnf_err_test(
+        "root ::= \"a\"\nroot ::= \"b\"",
+        "rule \"root\" defined twice",
+    );
+}
+
+#[test]
+fn test_w3c_ebnf() {
+    ebnf_test(
+        r#"
+            /* subset of XML */
+            [1] document ::= element
+            [39] element ::= EmptyElemTag | STag content ETag [ wfc: Element Type Match ]
+            [40] STag ::= '<' Name (S Attribute)* S? '>'
+            [41] Attribute ::= Name Eq AttValue
+            [42] ETag ::= '</' Name S? '>'
+            [43] content ::= CharData? ((element | CDSect) CharData?)*
+            [44] EmptyElemTag ::= '<' Name (S Attribute)* S? '/>'
+            [25] Eq ::= S? '=' S?
+            [10] AttValue ::= '"' [^<&"]* '"' | "'" [^<&']* "'"
+            [14] CharData ::= [^<&]+ - ([^<&]* ']]>' [^<&]*)
+            [18] CDSect ::= '<![CDATA[' CData ']]>'
+            [20] CData ::= (Char* - (Char* ']]>' Char*))
+            [2] Char ::= #x9 | #xA | #xD | [#x20-#xD7FF] | [#xE000-#xFFFD] | [#x10000-#x10FFFF]
+            [3] S ::= (#x20 | #x9 | #xD | #xA)+
+            [5] Name ::= NameStartChar (NameChar)*
+            [4] NameStartChar ::= ":" | [A-Z] | "_" | [a-z]
+            [4a] NameChar ::= NameStartChar | "-" | "." | [0-9]
+        "#,
+        &[
+            "<a/>",
+            r#"<a x='1' y="2">hi<b/>there</a>"#,
+            "<a><![CDATA[x]]y]]></a>",
+        ],
+        &["<a>]]></a>", "<a x=1/>", "<a>x<y</a>"],
+    );
+    ebnf_test(
+        "ident ::= Name - ('x' | 'X')\nName ::= [a-zA-Z]+",
+        &["ab", "y", "xx"],
+        &["x", "X", ""],
+    );
+    ebnf_test(
+        "Expr ::= Term ('+' Term)*\nTerm ::= '(' Expr ')' | Num\nNum ::= [0-9]+",
+        &["1+(2+3)", "((4))"],
+        &["1+", "(1"],
+    );
+
+    // excerpt of the SPARQL 1.1 grammar, with simplified triples and character classes
+    ebnf_test(
+        r#"
+            %ignore WS
+            %case_insensitive
+            [7] SelectQuery ::= SelectClause WhereClause SolutionModifier
+            [9] SelectClause ::= 'SELECT' ( 'DISTINCT' | 'REDUCED' )? ( Var+ | '*' )
+            [17] WhereClause ::= 'WHERE'? GroupGraphPattern
+            [18] SolutionModifier ::= LimitClause?
+            [27] LimitClause ::= 'LIMIT' INTEGER
+            [53] GroupGraphPattern ::= '{' TriplesBlock? '}'
+            [55] TriplesBlock ::= TriplesSameSubject ( '.' TriplesBlock? )?
+            [75] TriplesSameSubject ::= VarOrTerm Verb VarOrTerm
+            [78] Verb ::= VarOrIri | 'a'
+            [106] VarOrTerm ::= Var | GraphTerm
+            [107] VarOrIri ::= Var | iri
+            [108] Var ::= VAR1 | VAR2
+            [109] GraphTerm ::= iri | INTEGER | BooleanLiteral
+            [134] BooleanLiteral ::= 'true' | 'false'
+            [136] iri ::= IRIREF | PrefixedName
+            [137] PrefixedName ::= PNAME_LN | PNAME_NS
+            [139] IRIREF ::= '<' ([^<>"{}|^`\]-[#x00-#x20])* '>'
+            [140] PNAME_NS ::= PN_PREFIX? ':'
+            [141] PNAME_LN ::= PNAME_NS PN_LOCAL
+            [143] VAR1 ::= '?' VARNAME
+            [144] VAR2 ::= '$' VARNAME
+            [146] INTEGER ::= [0-9]+
+            [162] WS ::= #x20 | #x9 | #xD | #xA /* #xD is carriage return, #xA is line feed */
+            [164] PN_CHARS_BASE ::= [A-Z] | [a-z] | [#x00C0-#x00D6]
+            [165] PN_CHARS_U ::= PN_CHARS_BASE | '_'
+            [166] VARNAME ::= ( PN_CHARS_U | [0-9] ) ( PN_CHARS_U | [0-9] | #x00B7 )*
+            [167] PN_CHARS ::= PN_CHARS_U | '-' | [0-9] | #x00B7
+            [168] PN_PREFIX ::= PN_CHARS_BASE ((PN_CHARS|'.')* PN_CHARS)?
+            [169] PN_LOCAL ::= (PN_CHARS_U | ':' | [0-9] ) ((PN_CHARS|'.'|':')* (PN_CHARS|':') )?
+        "#,
+        &[
+            "SELECT ?x WHERE { ?x ?p ?o }",
+            "select distinct ?name\nwhere {\n  ?person foaf:name ?name .\n  ?person a foaf:Person\n}\nLIMIT 10",
+            "SELECT * { <http://example.org/a> $p 42 . }",
+            "SELECT?x{?x ?p true}",
+        ],
+        &[
+            "SELECT ?x WHERE { ?x ?p }",
+            "SELECT ? x { ?x ?p ?o }",
+            "SELECT ?x WHERE { ?x ?p ?o } LIMIT",
+            "SELECT ?x WHERE { ?x ?p ?o } LIMIT 1 0",
+        ],
+    );
+    ebnf_test(
+        "Sum ::= Num ('+' Num)*\n%terminals\nNum ::= Digit+\nDigit ::= [0-9]\n%ignore ' '",
+        &["1 + 23", "12+3"],
+        &["1 2", "1 + 2 3"],
+    );
+
+    ebnf_err_test("a ::= b c\nb ::= 'x'", "at 1(9): rule \"c\" not defined");
+    ebnf_err_test(
+        "a ::= b - 'x'\nb ::= '(' b ')' | 'y'",
+        "operands of '-' can only reference non-recursive rules",
+    );
+    ebnf_err_test("/* nothing */", "no productions found");
+    ebnf_err_test("a ::= 'x' )", "at 1(11): unexpected ')'");
+    ebnf_err_test("a ::= [#x7A-#x61]", "invalid range");
+    ebnf_err_test("a ::= 'x'\na ::= 'y'", "at 2(1): rule \"a\" defined twice");
+    ebnf_err_test("a ::= 'x'\n%foo", "at 2(1): unknown directive %foo");
+    ebnf_err_test(
+        "a ::= B\nB ::= '(' B ')' | 'y'",
+        "terminal \"B\" can only reference non-recursive rules",
+    );
+    ebnf_err_test(
+        "a ::= 'x' a?\n%ignore a",
+        "%ignore can only reference non-recursive rules",
+    );
+}
+
+fn regex_captures_test(rx: &str, input: &str, expected: &[(&str, Option<&str>)]) {
+    let grm = TopLevelGrammar::from_regex_with_captures(rx).unwrap();
+    let mut p = get_parser_factory().create_parser(grm).unwrap();
+    p.start_without_prompt();
+    for tok in get_tok_env().tokenize(input) {
+        assert!(
+            p.compute_mask().unwrap().is_allowed(tok),
+            "input {:?} rejected by {:?}",
+            input,
+            rx
+        );
+        consume(&mut p, tok);
+    }
+    // flush the last lexeme
+    let eos = get_tok_env().tok_trie().eos_token();
+    assert!(p.compute_mask().unwrap().is_allowed(eos));
+    consume(&mut p, eos);
+    for (name, value) in expected {
+        let capture = p.get_capture(name).map(|b| String::from_utf8_lossy(b));
+        assert_eq!(capture.as_deref(), *value, "capture {:?} in {:?}", name, rx);
+    }
+}
+
+#[test]
+fn test_regex_captures() {
+    regex_captures_test(
+        r"(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})",
+        "2024-01-31",
+        &[
+            ("year", Some("2024")),
+            ("month", Some("01")),
+            ("day", Some("31")),
+        ],
+    );
+    regex_captures_test(
+        r"(?P<date>(?P<y>\d{4})/(?P<m>\d\d))T(?:x(?P<z>z+))",
+        "2024/01Txzz",
+        &[
+            ("date", Some("2024/01")),
+            ("y", Some("2024")),
+            ("m", Some("01")),
+            ("z", Some("zz")),
+        ],
+    );
+    // groups under ? are not lifted
+    regex_captures_test(
+        r"name: (?P<name>[a-z]+)(, age: (?P<age>\d+))?",
+        "name: bob, age: 3",
+        &[("name", Some("bob")), ("age", None)],
+    );
+    regex_captures_test(r"(?P<x>)a", "a", &[("x", Some(""))]);
+
+    let err = TopLevelGrammar::from_regex_with_captures(r"a(?i)(?P<x>b)").unwrap_err();
+    assert!(err.to_string().contains("inline flags (?i) cannot be used"));
+
+    // greedy lexemes would not stop where the regex does
+    let err = TopLevelGrammar::from_regex_with_captures(r"(?P<a>\d+)(?P<b>\d+)").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("capture group \"b\" cannot be split"));
+    let err = TopLevelGrammar::from_regex_with_captures(r"\w+(?P<d>\d)").unwrap_err();
+    assert!(err.to_string().contains("/\\w+/ can extend into /\\d/"));
+    let err = TopLevelGrammar::from_regex_with_captures(r"(?P<a>a+)(?P<b>b?)a").unwrap_err();
+    assert!(err.to_string().contains("/a+/ can extend into /a/"));
+    regex_captures_test(r"[a-z]+(?P<d>\d)", "ab1", &[("d", Some("1"))]);
+    regex_captures_test(
+        r"(?P<a>\d+)x(?P<b>\d+)",
+        "12x3",
+        &[("a", Some("12")), ("b", Some("3"))],
+    );
+}
+
+#[test]
+fn test_json_captures() {
+    let lark = r#"
+        %llguidance { "json_captures": true }
+        start: "<tool>" data "</tool>" n
+        data[capture]: %json {
+            "properties": { "foo": { "type": "string" } },
+            "required": ["foo"]
+        }
+        n[capture]: /[0-9]+/
+    "#;
+    let mut p = make_parser(lark, false).unwrap();
+    for tok in get_tok_env().tokenize(r#"<tool>{"foo": "x"}</tool>12"#) {
+        assert!(p.compute_mask().unwrap().is_allowed(tok));
+        consume(&mut p, tok);
+    }
+    assert_eq!(
+        p.get_json_capture("data").unwrap().unwrap(),
+        serde_json::json!({ "foo": "x" })
+    );
+    assert!(p.get_json_capture("n").is_none());
+    assert_eq!(p.get_capture("data").unwrap(), br#"{"foo": "x"}"#);
+
+    // without the option, captures are only available as bytes
+    let mut p = make_parser(&lark.replace("true", "false"), false).unwrap();
+    for tok in get_tok_env().tokenize(r#"<tool>{"foo": "x"}</tool>"#) {
+        consume(&mut p, tok);
+    }
+    assert!(p.get_json_capture("data").is_none());
+    assert_eq!(p.get_capture("data").unwrap(), br#"{"foo": "x"}"#);
+}
+
+fn rule_events(lark: &str, input: &str) -> Vec<(String, String, bool)> {
+    let mut p = make_parser(lark, false).unwrap();
+    for tok in get_tok_env().tokenize(input) {
+        assert!(p.compute_mask().unwrap().is_allowed(tok));
+        consume(&mut p, tok);
+    }
+    p.parser
+        .rule_events()
+        .iter()
+        .map(|e| {
+            (
+                e.rule.clone(),
+                input[e.start..e.end].to_string(),
+                e.ambiguous,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_rule_events() {
+    let lark = r#"
+        %llguidance { "rule_events": true }
+        start: expr
+        expr: term ("+" term)*
+        term: NUM | "(" expr ")"
+        NUM: /[0-9]+/
+    "#;
+    let events = rule_events(lark, "1+(2+3)");
+    let ev = |r: &str, s: &str| (r.to_string(), s.to_string(), false);
+    assert_eq!(
+        events,
+        vec![
+            ev("term", "1"),
+            ev("expr", "1"),
+            ev("start", "1"),
+            ev("term", "2"),
+            ev("expr", "2"),
+            ev("term", "3"),
+            ev("expr", "2+3"),
+            ev("term", "(2+3)"),
+            ev("expr", "1+(2+3)"),
+            ev("start", "1+(2+3)"),
+        ]
+    );
+
+    // ambiguous grammar
+    let lark = r#"
+        %llguidance { "rule_events": true }
+        start: e
+        e: e "+" e | "x"
+    "#;
+    let events = rule_events(lark, "x+x+x");
+    assert!(events.contains(&("e".to_string(), "x+x".to_string(), false)));
+    assert!(events.contains(&("e".to_string(), "x+x+x".to_string(), true)));
+
+    // no events by default
+    assert!(rule_events(&lark.replace("true", "false"), "x+x").is_empty());
+}
+
+#[test]
+fn test_grammar_matcher() {
+    let lark = r#"
+        start: expr
+        expr: term ("+" term)*
+        term: NUM | "(" expr ")"
+        NUM: /[0-9]+/
+    "#;
+    let matcher = || GrammarMatcher::new(TopLevelGrammar::from_lark(lark.to_string())).unwrap();
+
+    let mut m = matcher();
+    assert_eq!(m.push(b"1+(2"), MatchStatus::Prefix);
+    assert_eq!(m.push(b"2+3)"), MatchStatus::Accept);
+    assert_eq!(m.finish(), MatchStatus::Accept);
+    assert_eq!(m.num_bytes(), 8);
+
+    let mut m = matcher();
+    match m.push(b"12a+3") {
+        MatchStatus::Reject {
+            offset,
+            expected,
+            message,
+        } => {
+            assert_eq!(offset, 2);
+            assert_eq!(expected.len(), 2);
+            assert_eq!(message, "unexpected \"a+3\"");
+        }
+        s => panic!("unexpected {:?}", s),
+    }
+    // further input is ignored
+    assert!(m.push(b"3").is_reject());
+
+    let mut m = matcher();
+    assert_eq!(m.push(b"1+"), MatchStatus::Prefix);
+    match m.finish() {
+        MatchStatus::Reject {
+            offset, message, ..
+        } => {
+            assert_eq!(offset, 2);
+            assert_eq!(message, "unexpected end of input");
+        }
+        s => panic!("unexpected {:?}", s),
+    }
+
+    // chunks don't need to be valid UTF-8
+    let mut m =
+        GrammarMatcher::new(TopLevelGrammar::from_lark(r#"start: "café""#.to_string())).unwrap();
+    assert_eq!(m.push(b"caf\xc3"), MatchStatus::Prefix);
+    assert_eq!(m.push(b"\xa9"), MatchStatus::Accept);
+}
+
+#[test]
+fn test_rejection_report() {
+    let lark = r#"
+        start: expr
+        expr: term ("+" term)*
+        term: NUM | "(" expr ")"
+        NUM: /[0-9]+/
+    "#;
+    let mut p = TokenParser::from_grammar(
+        byte_tok_env(),
+        TopLevelGrammar::from_lark(lark.to_string()),
+        Logger::new(0, 0),
+        Default::default(),
+        Default::default(),
+        vec![],
+    )
+    .unwrap();
+    p.start_without_prompt();
+    for &b in b"1+(22" {
+        p.compute_mask().unwrap();
+        consume(&mut p, b as u32);
+    }
+
+    let tokens = b"+3a".iter().map(|&b| b as u32).collect::<Vec<_>>();
+    assert_eq!(p.validate_tokens_raw(&tokens).unwrap(), 2);
+    let report = p.rejection_report().unwrap();
+    assert_eq!(report.byte_offset, 7);
+    assert_eq!(report.consumed, "1+(22+3");
+    assert_eq!(report.rejected, "a");
+    // the number can continue, or be followed by ')' or '+'
+    assert_eq!(report.expected_lexemes.len(), 3);
+    assert!(report.expected_lexemes.iter().any(|l| l.ends_with("')'")));
+    assert!(!report.rules_in_progress.is_empty());
+
+    p.compute_mask().unwrap();
+    let err = p.consume_token(b'x' as u32).unwrap_err().to_string();
+    assert!(err.contains("rejected \"x\""));
+    assert!(err.contains("at byte 5, after \"1+(22\""));
+    assert!(err.contains("rules in progress: [{"));
+    assert_eq!(p.rejection_report().unwrap().byte_offset, 5);
+}
+
+fn lark_lints(lark: &str) -> Vec<(String, String)> {
+    GrammarInit::Serialized(TopLevelGrammar::from_lark(lark.to_string()))
+        .lint(
+            None,
+            Default::default(),
+            Arc::new(InMemoryImportResolver::new()),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|l| (l.kind.to_string(), l.rule))
+        .collect()
+}
+
+#[test]
+fn test_grammar_lints() {
+    let lints = lark_lints(
+        r#"
+            start: expr | loop | digits | list
+            expr: expr "+" NUM | NUM
+            loop: "(" loop ")"
+            digits: "0x" /[0-9a-f]/+
+            list: NUM "," list | NUM
+            unused: NUM
+            NUM: /[0-9]+/
+        "#,
+    );
+    let expected = [
+        ("unreachable", "unused"),
+        ("unproductive", "loop"),
+        ("right_recursion", "list"),
+        ("single_char_repetition", "digits"),
+    ];
+    assert_eq!(
+        lints,
+        expected
+            .iter()
+            .map(|(k, r)| (k.to_string(), r.to_string()))
+            .collect::<Vec<_>>()
+    );
+
+    let lints = lark_lints(
+        r#"
+            start: a | b
+            a: ("x"?)*
+            b: c
+            c: b | "y"
+        "#,
+    );
+    assert_eq!(lints.len(), 2);
+    assert!(lints.iter().all(|(k, _)| k == "nullable_cycle"));
+
+    // repetition inside a terminal, and left recursion are fine
+    assert!(lark_lints(
+        r#"
+            start: expr
+            expr: expr "+" ID | ID
+            ID: /[a-z]/+
+        "#,
+    )
+    .is_empty());
+}
+
+#[test]
+fn test_estimate_cost() {
+    let lark = r#"
+        start: expr
+        expr: term ("+" term)*
+        term: NUM | "(" expr ")"
+        NUM: /[0-9]+/
+    "#;
+    let make = |limits| {
+        TokenParser::from_grammar(
+            byte_tok_env(),
+            TopLevelGrammar::from_lark(lark.to_string()),
+            Logger::new(0, 0),
+            Default::default(),
+            limits,
+            vec![],
+        )
+        .unwrap()
+    };
+    let options = CostEstimateOptions {
+        num_samples: 5,
+        max_tokens: 50,
+        seed: 42,
+    };
+
+    let est = estimate_cost(&make(Default::default()), &options);
+    assert!(est.errors.is_empty(), "{:?}", est.errors);
+    assert_eq!(est.num_samples, 5);
+    assert!(est.num_tokens > 5);
+    assert_eq!(est.lexer_states.count, 5);
+    assert!(est.lexer_states.min > 0);
+    assert!(est.mask_time_us.count >= est.items_per_row.count);
+    assert!(est.items_per_row.max > 0);
+    assert!(est.items_per_row.median <= est.items_per_row.p90);
+
+    // hitting parser limits is reported, not fatal
+    let limits = ParserLimits {
+        max_items_in_row: 3,
+        ..Default::default()
+    };
+    let est = estimate_cost(&make(limits), &options);
+    assert_eq!(est.errors.len(), 5);
 }